@@ -0,0 +1,13 @@
+fn main() {
+    // the text modes share one fragment shader
+    shader_build::ShaderBuild::new("shaders")
+        .variant("solid-color", "text-renderer.frag.glsl", &[("MODE", "MODE_SOLID_COLOR")])
+        .variant("subpixel",    "text-renderer.frag.glsl", &[("MODE", "MODE_SUBPIXEL")])
+        .variant("sdf",         "text-renderer.frag.glsl", &[("MODE", "MODE_SDF")])
+        // for HDR swapchains, see renderer::OutputSpace
+        .variant("subpixel-scrgb", "text-renderer.frag.glsl", &[("MODE", "MODE_SUBPIXEL"), ("OUTPUT", "OUTPUT_SCRGB")])
+        .variant("subpixel-hdr10", "text-renderer.frag.glsl", &[("MODE", "MODE_SUBPIXEL"), ("OUTPUT", "OUTPUT_HDR10")])
+        // for transparent targets, see renderer::BlendMode::ComponentAlphaPremultiplied
+        .variant("subpixel-premultiplied", "text-renderer.frag.glsl", &[("MODE", "MODE_SUBPIXEL"), ("PREMULTIPLIED", "1")])
+        .compile();
+}
@@ -0,0 +1,329 @@
+use core::ops::*;
+
+/// sRGB transfer function, encoded to linear light.
+/// Extended to negative values by mirroring, as scRGB does.
+pub fn srgb_to_linear(c: f32) -> f32 {
+    let x = c.abs();
+    let linear = if x <= 0.04045 { x/12.92 } else { ((x+0.055)/1.055).powf(2.4) };
+    linear.copysign(c)
+}
+
+/// Inverse of `srgb_to_linear`, linear light to encoded.
+pub fn linear_to_srgb(c: f32) -> f32 {
+    let x = c.abs();
+    let encoded = if x <= 0.0031308 { x*12.92 } else { 1.055*x.powf(1.0/2.4) - 0.055 };
+    encoded.copysign(c)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 { a + (b-a)*t }
+
+/// The bits of the nearest IEEE half float, ties to even. Out of range values become infinite.
+pub fn f16_bits(x: f32) -> u16 {
+    let bits = x.to_bits();
+    let sign = ((bits>>16) & 0x8000) as u16;
+    let mantissa = bits & 0x7F_FFFF;
+    if (bits>>23) & 0xFF == 0xFF {
+        // NaNs stay NaNs
+        return sign | 0x7C00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let exponent = ((bits>>23) & 0xFF) as i32 - 127 + 15;
+    let round = |value: u32, shift: u32| {
+        let (kept, rest, half) = (value>>shift, value & ((1<<shift)-1), 1<<(shift-1));
+        kept + (rest > half || (rest == half && kept&1 == 1)) as u32
+    };
+    if exponent >= 0x1F {
+        sign | 0x7C00
+    } else if exponent > 0 {
+        // a carry out of the mantissa correctly bumps the exponent, up to infinity
+        sign | round(((exponent as u32)<<23) | mantissa, 13) as u16
+    } else if exponent >= -10 {
+        // subnormal, with the implicit leading bit
+        sign | round(mantissa | 0x80_0000, (14-exponent) as u32) as u16
+    } else {
+        sign
+    }
+}
+
+/// sRGB encoded 8 bit color with straight alpha, as stored in images.
+/// Convert with `to_linear` or `to_oklab` to compute with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct Color{
+    r:u8, g:u8, b:u8, a:u8
+}
+impl Color{
+    pub const CLEAR:Color = Color{r:0x00, g:0x00, b:0x00, a:0x00};
+    pub const WHITE:Color = Color{r:0xFF, g:0xFF, b:0xFF, a:0xFF};
+    pub const BLACK:Color = Color{r:0x00, g:0x00, b:0x00, a:0xFF};
+    pub const fn srgb8(r:u8, g:u8, b:u8, a:u8) -> Self{ Self{r,g,b,a} }
+    pub const fn srgba8(self) -> [u8;4] { [self.r, self.g, self.b, self.a] }
+
+    pub fn to_linear(self) -> LinearRgba {
+        let c = |c:u8| srgb_to_linear(c as f32/255.0);
+        LinearRgba{ r: c(self.r), g: c(self.g), b: c(self.b), a: self.a as f32/255.0 }
+    }
+    pub fn to_oklab(self) -> Oklab { self.to_linear().into() }
+
+    /// Perceptually uniform mix, `t` = 0 is `self` and 1 is `other`.
+    pub fn mix(self, other: Color, t: f32) -> Color {
+        self.to_oklab().lerp(other.to_oklab(), t).to_linear().to_srgb8()
+    }
+}
+
+/// Linear light with sRGB (BT.709) primaries, where 1.0 is SDR white, like scRGB.
+/// Components below 0 reach colors outside the sRGB gamut, above 1 brighter than SDR white,
+/// which only HDR swapchains can show. Alpha is straight, not premultiplied.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[repr(C)]
+pub struct LinearRgba{
+    pub r:f32, pub g:f32, pub b:f32, pub a:f32
+}
+impl LinearRgba{
+    pub const fn new(r:f32, g:f32, b:f32, a:f32) -> Self{ Self{r,g,b,a} }
+
+    /// linear Display P3 components, D65 white
+    pub fn display_p3(r:f32, g:f32, b:f32, a:f32) -> Self{
+        Self{
+            r:  1.22494*r - 0.22494*g,
+            g: -0.042057*r + 1.042057*g,
+            b: -0.019638*r - 0.078636*g + 1.098274*b,
+            a,
+        }
+    }
+    /// linear BT.2020 components
+    pub fn rec2020(r:f32, g:f32, b:f32, a:f32) -> Self{
+        Self{
+            r:  1.660491*r - 0.587641*g - 0.07285*b,
+            g: -0.12455*r + 1.1329*g - 0.008349*b,
+            b: -0.018151*r - 0.100579*g + 1.11873*b,
+            a,
+        }
+    }
+    /// Scales the color, 2.0 is twice as bright as SDR white on HDR outputs.
+    pub fn with_intensity(self, factor:f32) -> Self{
+        Self{ r: self.r*factor, g: self.g*factor, b: self.b*factor, a: self.a }
+    }
+    pub const fn with_alpha(self, a:f32) -> Self{ Self{ a, ..self } }
+
+    /// Clamped to the sRGB gamut and rounded.
+    pub fn to_srgb8(self) -> Color {
+        let c = |c:f32| (linear_to_srgb(c.clamp(0.0, 1.0))*255.0).round() as u8;
+        Color{ r: c(self.r), g: c(self.g), b: c(self.b), a: (self.a.clamp(0.0, 1.0)*255.0).round() as u8 }
+    }
+    /// The sRGB encoded components, unclamped.
+    pub fn to_srgb(self) -> [f32;4] {
+        [linear_to_srgb(self.r), linear_to_srgb(self.g), linear_to_srgb(self.b), self.a]
+    }
+    pub fn to_oklab(self) -> Oklab { self.into() }
+
+    /// Half floats for `Vertex::color`, R16G16B16A16_SFLOAT.
+    pub fn to_f16(self) -> [u16;4] {
+        [f16_bits(self.r), f16_bits(self.g), f16_bits(self.b), f16_bits(self.a)]
+    }
+
+    /// Physically linear mix, as light would add up.
+    pub fn lerp(self, other: Self, t: f32) -> Self {
+        Self{ r: lerp(self.r, other.r, t), g: lerp(self.g, other.g, t), b: lerp(self.b, other.b, t), a: lerp(self.a, other.a, t) }
+    }
+}
+impl From<Color> for LinearRgba{
+    fn from(color: Color) -> Self{ color.to_linear() }
+}
+impl From<LinearRgba> for Color{
+    fn from(color: LinearRgba) -> Self{ color.to_srgb8() }
+}
+impl Add for LinearRgba {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self { Self{ r: self.r+rhs.r, g: self.g+rhs.g, b: self.b+rhs.b, a: self.a+rhs.a } }
+}
+impl Sub for LinearRgba {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self { Self{ r: self.r-rhs.r, g: self.g-rhs.g, b: self.b-rhs.b, a: self.a-rhs.a } }
+}
+impl Mul<f32> for LinearRgba {
+    type Output = Self;
+    fn mul(self, rhs: f32) -> Self { Self{ r: self.r*rhs, g: self.g*rhs, b: self.b*rhs, a: self.a*rhs } }
+}
+
+/// Perceptual color space by Björn Ottosson: `l` is lightness from 0 to 1,
+/// `a` goes from green to red and `b` from blue to yellow.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Oklab{
+    pub l:f32, pub a:f32, pub b:f32, pub alpha:f32
+}
+impl Oklab{
+    pub const fn new(l:f32, a:f32, b:f32, alpha:f32) -> Self{ Self{l,a,b,alpha} }
+    pub fn to_linear(self) -> LinearRgba { self.into() }
+    pub fn to_oklch(self) -> Oklch { self.into() }
+
+    /// Perceptually even interpolation, `t` = 0 is `self` and 1 is `other`.
+    pub fn lerp(self, other: Self, t: f32) -> Self {
+        Self{ l: lerp(self.l, other.l, t), a: lerp(self.a, other.a, t), b: lerp(self.b, other.b, t), alpha: lerp(self.alpha, other.alpha, t) }
+    }
+}
+impl From<LinearRgba> for Oklab{
+    fn from(c: LinearRgba) -> Self{
+        let l = 0.41222147*c.r + 0.53633254*c.g + 0.051445993*c.b;
+        let m = 0.2119035*c.r + 0.6806995*c.g + 0.10739696*c.b;
+        let s = 0.08830246*c.r + 0.28171884*c.g + 0.6299787*c.b;
+        let (l, m, s) = (l.cbrt(), m.cbrt(), s.cbrt());
+        Self{
+            l: 0.21045426*l + 0.7936178*m - 0.004072047*s,
+            a: 1.9779985*l - 2.4285922*m + 0.4505937*s,
+            b: 0.025904037*l + 0.78277177*m - 0.80867577*s,
+            alpha: c.a,
+        }
+    }
+}
+impl From<Oklab> for LinearRgba{
+    fn from(c: Oklab) -> Self{
+        let l = c.l + 0.39633778*c.a + 0.21580376*c.b;
+        let m = c.l - 0.105561346*c.a - 0.06385417*c.b;
+        let s = c.l - 0.08948418*c.a - 1.2914855*c.b;
+        let (l, m, s) = (l*l*l, m*m*m, s*s*s);
+        Self{
+            r:  4.0767417*l - 3.3077116*m + 0.23096993*s,
+            g: -1.268438*l + 2.6097574*m - 0.3413194*s,
+            b: -0.0041960863*l - 0.7034186*m + 1.7076147*s,
+            a: c.alpha,
+        }
+    }
+}
+
+/// Oklab in polar coordinates: lightness, chroma and hue in degrees.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Oklch{
+    pub l:f32, pub c:f32, pub h:f32, pub alpha:f32
+}
+impl Oklch{
+    pub const fn new(l:f32, c:f32, h:f32, alpha:f32) -> Self{ Self{l,c,h,alpha} }
+    pub fn to_oklab(self) -> Oklab { self.into() }
+    pub fn to_linear(self) -> LinearRgba { self.to_oklab().into() }
+
+    /// Interpolates the hue along the shorter way around the circle.
+    /// Gray colors have no hue, so the other color's hue is kept.
+    pub fn lerp(self, other: Self, t: f32) -> Self {
+        const ACHROMATIC: f32 = 1e-4;
+        let (from, to) = match (self.c < ACHROMATIC, other.c < ACHROMATIC) {
+            (true, false) => (other.h, other.h),
+            (false, true) => (self.h, self.h),
+            _ => (self.h, self.h + (other.h-self.h+180.0).rem_euclid(360.0) - 180.0),
+        };
+        Self{ l: lerp(self.l, other.l, t), c: lerp(self.c, other.c, t), h: lerp(from, to, t).rem_euclid(360.0), alpha: lerp(self.alpha, other.alpha, t) }
+    }
+}
+impl From<Oklab> for Oklch{
+    fn from(c: Oklab) -> Self{
+        Self{ l: c.l, c: c.a.hypot(c.b), h: c.b.atan2(c.a).to_degrees().rem_euclid(360.0), alpha: c.alpha }
+    }
+}
+impl From<Oklch> for Oklab{
+    fn from(c: Oklch) -> Self{
+        let (sin, cos) = c.h.to_radians().sin_cos();
+        Self{ l: c.l, a: c.c*cos, b: c.c*sin, alpha: c.alpha }
+    }
+}
+impl From<LinearRgba> for Oklch{
+    fn from(c: LinearRgba) -> Self{ Oklab::from(c).into() }
+}
+impl From<Oklch> for LinearRgba{
+    fn from(c: Oklch) -> Self{ Oklab::from(c).into() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn close(a: f32, b: f32, tolerance: f32) -> bool { (a-b).abs() <= tolerance }
+
+    #[test]
+    fn every_srgb8_value_round_trips() {
+        for c in 0..=255u8 {
+            let color = Color::srgb8(c, c, c, c);
+            assert_eq!(color.to_linear().to_srgb8(), color);
+            assert!(close(linear_to_srgb(srgb_to_linear(c as f32/255.0)), c as f32/255.0, 1e-6));
+        }
+        // the transfer function is continuous at the linear segment
+        assert!(close(srgb_to_linear(0.04045), 0.0031308, 1e-7));
+    }
+
+    #[test]
+    fn known_values() {
+        let white = Oklab::from(LinearRgba::new(1.0, 1.0, 1.0, 1.0));
+        assert!(close(white.l, 1.0, 1e-4) && close(white.a, 0.0, 1e-4) && close(white.b, 0.0, 1e-4));
+        // reference values from the Oklab post
+        let red = Oklab::from(LinearRgba::new(1.0, 0.0, 0.0, 1.0));
+        assert!(close(red.l, 0.6279, 1e-3) && close(red.a, 0.2249, 1e-3) && close(red.b, 0.1258, 1e-3));
+        let red = red.to_oklch();
+        assert!(close(red.c, 0.2577, 1e-3) && close(red.h, 29.23, 0.05));
+    }
+
+    #[test]
+    fn wide_gamuts_contain_srgb() {
+        for white in [LinearRgba::display_p3(1.0, 1.0, 1.0, 1.0), LinearRgba::rec2020(1.0, 1.0, 1.0, 1.0)] {
+            assert!(close(white.r, 1.0, 1e-4) && close(white.g, 1.0, 1e-4) && close(white.b, 1.0, 1e-4));
+        }
+        assert!(LinearRgba::rec2020(0.0, 1.0, 0.0, 1.0).r < 0.0);
+        let srgb = Color::srgb8(0x80, 0x0A, 0xFF, 0x80).to_linear();
+        assert!(close(srgb.r, 0.21586, 1e-4) && close(srgb.g, 0.0030353, 1e-4) && srgb.b == 1.0 && close(srgb.a, 0.50196, 1e-4));
+    }
+
+    #[test]
+    fn half_floats() {
+        assert_eq!([1.0, 0.5, -2.0, 0.0, -0.0].map(f16_bits), [0x3C00, 0x3800, 0xC000, 0x0000, 0x8000]);
+        assert_eq!(f16_bits(65504.0), 0x7BFF);
+        assert_eq!([65520.0, 1e9, f32::INFINITY].map(f16_bits), [0x7C00; 3]);
+        assert!(f16_bits(f32::NAN) & 0x7FFF > 0x7C00);
+        // ties to even, below and above 1
+        assert_eq!(f16_bits(1.0 + 0.5/1024.0), 0x3C00);
+        assert_eq!(f16_bits(1.0 + 1.5/1024.0), 0x3C02);
+        assert_eq!(f16_bits(1.0 + 0.6/1024.0), 0x3C01);
+        // subnormals
+        assert_eq!(f16_bits(2f32.powi(-24)), 0x0001);
+        assert_eq!(f16_bits(2f32.powi(-25)), 0x0000);
+        assert_eq!(f16_bits(3.0*2f32.powi(-25)), 0x0002);
+        assert_eq!(f16_bits(2f32.powi(-14) - 2f32.powi(-25)), 0x0400);
+        assert_eq!(f16_bits(-1e-10), 0x8000);
+    }
+
+    #[test]
+    fn mixing() {
+        // the mid gray between black and white is perceptual, not the linear 50%
+        let gray = Color::BLACK.mix(Color::WHITE, 0.5);
+        let [r, g, b, a] = gray.srgba8();
+        assert!(r == g && g == b && (r as i32-0x63).abs() <= 1 && a == 0xFF);
+        assert_eq!(LinearRgba::new(0.0, 0.0, 0.0, 1.0).lerp(LinearRgba::new(1.0, 1.0, 1.0, 1.0), 0.5), LinearRgba::new(0.5, 0.5, 0.5, 1.0));
+        // hues take the shorter way across 0°
+        let mid = Oklch::new(0.5, 0.1, 350.0, 1.0).lerp(Oklch::new(0.5, 0.1, 30.0, 1.0), 0.5);
+        assert!(close(mid.h, 10.0, 1e-3));
+        let gray = Oklch::new(0.5, 0.0, 0.0, 1.0);
+        assert!(close(gray.lerp(Oklch::new(0.5, 0.1, 120.0, 1.0), 0.5).h, 120.0, 1e-3));
+    }
+
+    proptest! {
+        #[test]
+        fn srgb8_round_trips_through_oklab(r: u8, g: u8, b: u8, a: u8) {
+            let color = Color::srgb8(r, g, b, a);
+            prop_assert_eq!(color.to_oklab().to_linear().to_srgb8(), color);
+            prop_assert_eq!(Oklch::from(color.to_linear()).to_linear().to_srgb8(), color);
+        }
+
+        #[test]
+        fn linear_round_trips_through_oklab(r in -0.5f32..4.0, g in -0.5f32..4.0, b in -0.5f32..4.0) {
+            let color = LinearRgba::new(r, g, b, 1.0);
+            let back = color.to_oklab().to_oklch().to_linear();
+            prop_assert!(close(back.r, r, 1e-3) && close(back.g, g, 1e-3) && close(back.b, b, 1e-3), "{color:?} -> {back:?}");
+        }
+
+        #[test]
+        fn mix_stays_between_endpoints(a in any::<[u8;3]>(), b in any::<[u8;3]>(), t in 0.0f32..=1.0) {
+            let (ca, cb) = (Color::srgb8(a[0], a[1], a[2], 0xFF), Color::srgb8(b[0], b[1], b[2], 0xFF));
+            let l = ca.mix(cb, t).to_oklab().l;
+            let (la, lb) = (ca.to_oklab().l, cb.to_oklab().l);
+            prop_assert!(l >= la.min(lb) - 0.01 && l <= la.max(lb) + 0.01);
+            prop_assert_eq!(ca.mix(cb, 0.0), ca);
+            prop_assert_eq!(ca.mix(cb, 1.0), cb);
+        }
+    }
+}
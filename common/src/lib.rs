@@ -1,53 +1,53 @@
 #![feature(const_trait_impl, const_fn_floating_point_arithmetic)]
-// TODO: think about colors. Right now Color means sRGBA8
-// maybe have f32 oklab colors internally and use .srgba8() to convert?
-#[derive(Debug, Clone, Copy)]
-#[repr(C)]
-pub struct Color{
-    r:u8, g:u8, b:u8, a:u8
-}
-impl Color{
-    pub const CLEAR:Color = Color{r:0x00, g:0x00, b:0x00, a:0x00};
-    pub const WHITE:Color = Color{r:0xFF, g:0xFF, b:0xFF, a:0xFF};
-    pub const BLACK:Color = Color{r:0x00, g:0x00, b:0x00, a:0xFF};
-    pub const fn srgb8(r:u8, g:u8, b:u8, a:u8) -> Self{ Self{r,g,b,a} }
-}
+mod color;
+pub use color::*;
+pub mod shapes;
+pub mod path;
 
+/// 28 bytes, glyphs and shapes share the layout so they batch into one draw.
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct Vertex{
     pub x:i16,
     pub y:i16,
+    /// texel of a glyph, `u` is the phase in 1/8 pixels for `shapes::wave`
     pub u:u16,
     pub v:u16,
-    pub color: Color
+    /// linear sRGB as half floats, see `LinearRgba::to_f16`
+    pub color: [u16;4],
+    /// position relative to the center of a shape in 1/8 pixels, see `shapes`
+    pub local: [i16;2],
+    /// the `shapes::ShapeKind` and its sizes in 1/8 pixels, see `shapes::encode_shape`, all zero for text
+    pub shape: [u16;4],
 }
 
-pub fn gen_quad(x: i16, y: i16, w: i16, h: i16, u:u16, v:u16, color: Color) -> [Vertex;4] {
+pub fn gen_quad(x: i16, y: i16, w: i16, h: i16, u:u16, v:u16, color: LinearRgba) -> [Vertex;4] {
     assert!(w > 0);
     assert!(h > 0);
     let w_ = w as u16;
     let h_ = h as u16;
+    let color = color.to_f16();
     [
-        Vertex{x:x+0,  y:y+0, u:u+0,  v:v+0,  color}, // top left
-        Vertex{x:x+0,  y:y+h, u:u+0,  v:v+h_, color}, // bottom left
-        Vertex{x:x+w,  y:y+0, u:u+w_, v:v+0,  color}, // top right
-        Vertex{x:x+w,  y:y+h, u:u+w_, v:v+h_, color}, // bottom right
+        Vertex{x:x+0,  y:y+0, u:u+0,  v:v+0,  color, local: [0;2], shape: [0;4]}, // top left
+        Vertex{x:x+0,  y:y+h, u:u+0,  v:v+h_, color, local: [0;2], shape: [0;4]}, // bottom left
+        Vertex{x:x+w,  y:y+0, u:u+w_, v:v+0,  color, local: [0;2], shape: [0;4]}, // top right
+        Vertex{x:x+w,  y:y+h, u:u+w_, v:v+h_, color, local: [0;2], shape: [0;4]}, // bottom right
     ]
 }
 
-pub fn gen_rect(position:Vec2<f32>, extent:Vec2<f32>, color: Color) -> [Vertex;4] {
+pub fn gen_rect(position:Vec2<f32>, extent:Vec2<f32>, color: LinearRgba) -> [Vertex;4] {
     let x = position.x.round() as i16;
     let y = position.y.round() as i16;
     let w = extent.x.round()   as i16;
     let h = extent.y.round()   as i16;
     assert!(w > 0);
     assert!(h > 0);
+    let color = color.to_f16();
     [
-        Vertex{x:x+0,  y:y+0, u:0xFFFF, v:0xFFFF, color}, // top left
-        Vertex{x:x+0,  y:y+h, u:0xFFFF, v:0xFFFF, color}, // bottom left
-        Vertex{x:x+w,  y:y+0, u:0xFFFF, v:0xFFFF, color}, // top right
-        Vertex{x:x+w,  y:y+h, u:0xFFFF, v:0xFFFF, color}, // bottom right
+        Vertex{x:x+0,  y:y+0, u:0xFFFF, v:0xFFFF, color, local: [0;2], shape: [0;4]}, // top left
+        Vertex{x:x+0,  y:y+h, u:0xFFFF, v:0xFFFF, color, local: [0;2], shape: [0;4]}, // bottom left
+        Vertex{x:x+w,  y:y+0, u:0xFFFF, v:0xFFFF, color, local: [0;2], shape: [0;4]}, // top right
+        Vertex{x:x+w,  y:y+h, u:0xFFFF, v:0xFFFF, color, local: [0;2], shape: [0;4]}, // bottom right
     ]
 }
 
@@ -150,6 +150,15 @@ impl<T> Vec2<T> where T:Clone+Copy {
     }
 }
 
+impl Vec2<f32> {
+    pub fn length(self) -> f32 { self.dot(self).sqrt() }
+    /// zero for the zero vector
+    pub fn normalized(self) -> Self {
+        let length = self.length();
+        if length > 0.0 { self/length } else { self }
+    }
+}
+
 impl<T>   const Add for Vec2<T> where T:Clone+Copy+Add<Output=T> { type Output=Self; fn add(self, rhs: Self) -> Self { self.map2(rhs,|a,b|a+b) } }
 impl<T>   const Sub for Vec2<T> where T:Clone+Copy+Sub<Output=T> { type Output=Self; fn sub(self, rhs: Self) -> Self { self.map2(rhs,|a,b|a-b) } }
 impl<T>   const Mul<T> for Vec2<T> where T:Clone+Copy+Mul<Output=T> { type Output=Self; fn mul(self, rhs: T) -> Self { vec2t(self.x*rhs, self.y*rhs) } }
@@ -177,12 +186,40 @@ impl<T> std::fmt::Display for Vec2<T> where T:Clone+Copy+std::fmt::Display {
 #[derive(Clone,Copy)]
 pub struct BiVec2<T> where T:Clone+Copy {pub xy: T}
 
+/// scalar and bivector part, rotating by the full angle as `v*R`
 #[derive(Clone,Copy)]
-pub struct Rotor2<T>(pub Vec2<T>,pub BiVec2<T>) where T:Clone+Copy;
+pub struct Rotor2<T>(pub T,pub BiVec2<T>) where T:Clone+Copy;
+
+impl Rotor2<f32> {
+    /// from x towards y by `angle` radians
+    pub fn from_angle(angle: f32) -> Self { Rotor2(angle.cos(), BiVec2{xy:angle.sin()}) }
+    /// rotates the direction of `from` into the direction of `to`
+    pub fn between(from: Vec2<f32>, to: Vec2<f32>) -> Self {
+        let (s, b) = (from.dot(to), (from^to).xy);
+        let norm = (s*s+b*b).sqrt();
+        if norm > 0.0 { Rotor2(s/norm, BiVec2{xy:b/norm}) } else { Rotor2(1.0, BiVec2{xy:0.0}) }
+    }
+    /// in (-pi, pi]
+    pub fn angle(self) -> f32 { self.1.xy.atan2(self.0) }
+    pub fn rotate(self, v: Vec2<f32>) -> Vec2<f32> {
+        Vec2{ x: v.x*self.0 - v.y*self.1.xy, y: v.x*self.1.xy + v.y*self.0 }
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    fn rotor() {
+        let quarter = Rotor2::from_angle(std::f32::consts::FRAC_PI_2).rotate(vec2(2, 0));
+        assert!((quarter.x - 0.0).abs() < 1e-6 && (quarter.y - 2.0).abs() < 1e-6);
+        let between = Rotor2::between(vec2(1, 1), vec2(-3, 3));
+        assert!((between.angle() - std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+        let rotated = between.rotate(vec2(1, 1));
+        assert!((rotated.x + 1.0).abs() < 1e-6 && (rotated.y - 1.0).abs() < 1e-6);
+    }
+
     //#[test]
     //fn i32q6() {
     //    assert_eq!(13,    13.q6().i32());
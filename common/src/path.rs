@@ -0,0 +1,546 @@
+//! Vector paths of lines, quadratic and cubic Béziers and circular arcs, filled or stroked.
+//! They are flattened to an `Outline`, whose edges the fragment shader accumulates to coverage,
+//! so they are drawn with the same vertices as glyphs and shapes, see `Outline::quad`.
+use crate::*;
+use crate::shapes::LineCap;
+use std::f32::consts::{PI, TAU};
+
+/// Maximum distance in pixels between curves and the polylines approximating them.
+const TOLERANCE : f32 = 0.1;
+
+/// Subdivisions of curves and arcs are capped, for degenerate input.
+const MAX_SUBDIVISIONS : usize = 1024;
+
+#[derive(Clone,Copy)]
+enum Segment {
+    MoveTo(Vec2<f32>),
+    LineTo(Vec2<f32>),
+    QuadTo(Vec2<f32>, Vec2<f32>),
+    CubicTo(Vec2<f32>, Vec2<f32>, Vec2<f32>),
+    ArcTo{ center: Vec2<f32>, sweep: f32 },
+    Close,
+}
+
+/// In pixels with y down, built by chaining calls.
+/// Subpaths without a `move_to` start where the previous one ended, or at the origin.
+#[derive(Clone,Default)]
+pub struct Path {
+    segments: Vec<Segment>,
+}
+
+/// Which regions of overlapping or self-intersecting subpaths are inside.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+pub enum FillRule {
+    #[default]
+    NonZero,
+    EvenOdd,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum LineJoin {
+    /// Falls back to `Bevel` where the miter would be longer than the limit times the stroke width.
+    Miter(f32),
+    Round,
+    Bevel,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Stroke {
+    pub width: f32,
+    pub join:  LineJoin,
+    pub cap:   LineCap,
+}
+impl Stroke {
+    /// miter joins with SVG's default limit of 4 and butt caps
+    pub fn new(width: f32) -> Self { Self{ width, join: LineJoin::Miter(4.0), cap: LineCap::Butt } }
+    pub fn with_join(mut self, join: LineJoin) -> Self { self.join = join; self }
+    pub fn with_cap(mut self, cap: LineCap) -> Self { self.cap = cap; self }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Paint {
+    /// subpaths are closed implicitly
+    Fill(FillRule),
+    Stroke(Stroke),
+}
+
+/// Closed polygons and the rule filling them, what `Path::rasterize` and the fragment shader fill.
+#[derive(Clone,Default)]
+pub struct Outline {
+    pub polygons:  Vec<Vec<Vec2<f32>>>,
+    pub fill_rule: FillRule,
+}
+
+/// Coverage of `width`×`height` pixels, row by row, with the top left pixel at `left`,`top`.
+#[derive(Debug,Clone,Default,PartialEq,Eq)]
+pub struct Mask {
+    pub left:     i32,
+    pub top:      i32,
+    pub width:    u32,
+    pub height:   u32,
+    pub coverage: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Polyline {
+    points: Vec<Vec2<f32>>,
+    closed: bool,
+}
+
+impl Path {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn move_to(mut self, to: Vec2<f32>) -> Self {
+        self.segments.push(Segment::MoveTo(to)); self
+    }
+    pub fn line_to(mut self, to: Vec2<f32>) -> Self {
+        self.segments.push(Segment::LineTo(to)); self
+    }
+    pub fn quad_to(mut self, control: Vec2<f32>, to: Vec2<f32>) -> Self {
+        self.segments.push(Segment::QuadTo(control, to)); self
+    }
+    pub fn cubic_to(mut self, control1: Vec2<f32>, control2: Vec2<f32>, to: Vec2<f32>) -> Self {
+        self.segments.push(Segment::CubicTo(control1, control2, to)); self
+    }
+    /// Circular arc from the current point around `center` by `sweep` radians,
+    /// positive from x towards y, which is clockwise on screen.
+    pub fn arc_to(mut self, center: Vec2<f32>, sweep: f32) -> Self {
+        self.segments.push(Segment::ArcTo{ center, sweep }); self
+    }
+    /// Back to the start of the subpath, joined instead of capped when stroked.
+    pub fn close(mut self) -> Self {
+        self.segments.push(Segment::Close); self
+    }
+
+    pub fn outline(&self, paint: &Paint) -> Outline {
+        let polylines = self.flatten();
+        match paint {
+            Paint::Fill(fill_rule) => Outline{ polygons: polylines.into_iter().map(|polyline| polyline.points).collect(), fill_rule: *fill_rule },
+            // the pieces all wind the same way, so they are merged by the non-zero rule
+            Paint::Stroke(stroke) => Outline{ polygons: stroke_polygons(&polylines, stroke), fill_rule: FillRule::NonZero },
+        }
+    }
+
+    /// Coverage on the CPU, the same as drawing `outline(paint).quad(..)`.
+    pub fn rasterize(&self, paint: &Paint) -> Mask {
+        let outline = self.outline(paint);
+        rasterize(&outline.polygons, outline.fill_rule)
+    }
+
+    fn flatten(&self) -> Vec<Polyline> {
+        let mut polylines = Vec::new();
+        let mut current = Polyline::default();
+        let mut last = vec2(0, 0);
+        for segment in &self.segments {
+            if current.points.is_empty() && !matches!(segment, Segment::MoveTo(_) | Segment::Close) {
+                current.points.push(last);
+            }
+            match *segment {
+                Segment::MoveTo(to) => {
+                    finish(&mut polylines, &mut current);
+                    current.points.push(to);
+                },
+                Segment::LineTo(to) => current.points.push(to),
+                Segment::QuadTo(control, to) => {
+                    // Wang's formula, the second difference bounds the distance to the chords
+                    let n = subdivisions((last - control*2.0 + to).length()/(4.0*TOLERANCE));
+                    current.points.extend((1..=n).map(|i| {
+                        let t = i as f32/n as f32;
+                        let s = 1.0-t;
+                        last*(s*s) + control*(2.0*s*t) + to*(t*t)
+                    }));
+                },
+                Segment::CubicTo(control1, control2, to) => {
+                    let dd = (last - control1*2.0 + control2).length().max((control1 - control2*2.0 + to).length());
+                    let n = subdivisions(3.0*dd/(4.0*TOLERANCE));
+                    current.points.extend((1..=n).map(|i| {
+                        let t = i as f32/n as f32;
+                        let s = 1.0-t;
+                        last*(s*s*s) + control1*(3.0*s*s*t) + control2*(3.0*s*t*t) + to*(t*t*t)
+                    }));
+                },
+                Segment::ArcTo{ center, sweep } => {
+                    let offset = last - center;
+                    let n = arc_subdivisions(offset.length(), sweep);
+                    current.points.extend((1..=n).map(|i| center + Rotor2::from_angle(sweep*i as f32/n as f32).rotate(offset)));
+                },
+                Segment::Close => {
+                    current.closed = true;
+                    let start = current.points.first().copied().unwrap_or(last);
+                    finish(&mut polylines, &mut current);
+                    last = start;
+                    continue;
+                },
+            }
+            last = *current.points.last().unwrap();
+        }
+        finish(&mut polylines, &mut current);
+        polylines
+    }
+}
+
+impl Outline {
+    pub fn bounds(&self) -> Option<(Vec2<f32>,Vec2<f32>)> {
+        let (mut min, mut max) = (vec2(f32::MAX, f32::MAX), vec2(f32::MIN, f32::MIN));
+        for &point in self.polygons.iter().flatten() {
+            min = min.map2(point, f32::min);
+            max = max.map2(point, f32::max);
+        }
+        (min.x < max.x && min.y < max.y).then_some((min, max))
+    }
+
+    /// A quad over the outline, whose edges are appended to `edges` relative to the quad's center.
+    /// The fragment shader reads them from a storage buffer and accumulates coverage like `rasterize`.
+    /// None if the outline has no area.
+    pub fn quad(&self, fill: impl Into<shapes::Fill>, edges: &mut Vec<[f32;4]>) -> Option<[Vertex;4]> {
+        let (min, max) = self.bounds()?;
+        // on the grid of `Vertex::local`, so the edges line up with it exactly
+        let center = ((min+max)*0.5).map(|c| (c*shapes::UNITS_PER_PIXEL).round()/shapes::UNITS_PER_PIXEL);
+        let half = (max-center).map2(center-min, f32::max);
+        let first = edges.len() as u32;
+        for polygon in &self.polygons {
+            for i in 0..polygon.len() {
+                let (from, to) = (polygon[i] - center, polygon[(i+1)%polygon.len()] - center);
+                // horizontal edges never change the winding
+                if from.y != to.y {
+                    edges.push([from.x, from.y, to.x, to.y]);
+                }
+            }
+        }
+        let count = edges.len() - first as usize;
+        assert!(count <= u16::MAX as usize, "a path can have at most 65535 edges, this one has {count}");
+        let shape = shapes::encode_path(first, count as u16, self.fill_rule);
+        Some(shapes::shape_quad(center, half, vec2(1, 0), shape, fill.into()))
+    }
+}
+
+/// From the average winding number over a pixel, `shaders/shapes.glsl` does the same.
+fn fill_coverage(winding: f32, fill_rule: FillRule) -> f32 {
+    match fill_rule {
+        FillRule::NonZero => winding.abs().min(1.0),
+        // a triangle wave, 1 for odd windings
+        FillRule::EvenOdd => {
+            let folded = winding.abs()%2.0;
+            if folded > 1.0 { 2.0-folded } else { folded }
+        },
+    }
+}
+
+/// a lone `move_to` draws nothing
+fn finish(polylines: &mut Vec<Polyline>, current: &mut Polyline) {
+    let polyline = std::mem::take(current);
+    if polyline.points.len() > 1 {
+        polylines.push(polyline);
+    }
+}
+
+/// for curves, from the square of the subdivision count
+fn subdivisions(squared: f32) -> usize {
+    (squared.sqrt().ceil() as usize).clamp(1, MAX_SUBDIVISIONS)
+}
+
+fn arc_subdivisions(radius: f32, sweep: f32) -> usize {
+    // the angle whose chord stays within the tolerance
+    let step = if radius > TOLERANCE { 2.0*(1.0 - TOLERANCE/radius).acos() } else { PI/2.0 };
+    ((sweep.abs()/step).ceil() as usize).clamp(1, MAX_SUBDIVISIONS)
+}
+
+fn perpendicular(v: Vec2<f32>) -> Vec2<f32> { vec2(-v.y, v.x) }
+
+fn circle_polygon(center: Vec2<f32>, radius: f32) -> Vec<Vec2<f32>> {
+    let n = arc_subdivisions(radius, TAU).max(4);
+    (0..n).map(|i| center + Rotor2::from_angle(TAU*i as f32/n as f32).rotate(vec2(radius, 0))).collect()
+}
+
+/// The outline of a stroke as overlapping polygons: a quad per segment plus joins and caps.
+fn stroke_polygons(polylines: &[Polyline], stroke: &Stroke) -> Vec<Vec<Vec2<f32>>> {
+    let half = stroke.width.max(0.0)*0.5;
+    let mut pieces = Vec::new();
+    if half <= 0.0 {
+        return pieces;
+    }
+    for polyline in polylines {
+        let mut points : Vec<Vec2<f32>> = Vec::with_capacity(polyline.points.len());
+        for &point in &polyline.points {
+            if points.last().is_none_or(|&last| (point-last).length() > 1e-4) {
+                points.push(point);
+            }
+        }
+        if polyline.closed && points.len() > 2 && (points[0] - points[points.len()-1]).length() <= 1e-4 {
+            points.pop();
+        }
+        let n = points.len();
+        if n == 1 {
+            // zero length, only caps are visible
+            let point = points[0];
+            match stroke.cap {
+                LineCap::Butt   => {},
+                LineCap::Square => pieces.push([(-1,-1), (1,-1), (1,1), (-1,1)].map(|(x,y)| point + vec2(x, y)*half).to_vec()),
+                LineCap::Round  => pieces.push(circle_polygon(point, half)),
+            }
+            continue;
+        }
+        let closed = polyline.closed && n > 2;
+        let segment_count = if closed { n } else { n-1 };
+        for i in 0..segment_count {
+            let (a, b) = (points[i], points[(i+1)%n]);
+            let normal = perpendicular((b-a).normalized())*half;
+            pieces.push(vec![a+normal, b+normal, b-normal, a-normal]);
+        }
+        let joins = if closed { 0..n } else { 1..n-1 };
+        for i in joins {
+            let point = points[i];
+            let incoming = (point - points[(i+n-1)%n]).normalized();
+            let outgoing = (points[(i+1)%n] - point).normalized();
+            join(&mut pieces, point, incoming, outgoing, half, stroke.join);
+        }
+        if !closed {
+            cap(&mut pieces, points[0],   (points[0]   - points[1]  ).normalized(), half, stroke.cap);
+            cap(&mut pieces, points[n-1], (points[n-1] - points[n-2]).normalized(), half, stroke.cap);
+        }
+    }
+    for piece in &mut pieces {
+        let area : f32 = (0..piece.len()).map(|i| (piece[i] ^ piece[(i+1)%piece.len()]).xy).sum();
+        if area < 0.0 {
+            piece.reverse();
+        }
+    }
+    pieces
+}
+
+/// Fills the gap on the outer side of a turn, the segment quads already cover the inner side.
+fn join(pieces: &mut Vec<Vec<Vec2<f32>>>, point: Vec2<f32>, incoming: Vec2<f32>, outgoing: Vec2<f32>, half: f32, join: LineJoin) {
+    let turn = (incoming^outgoing).xy;
+    let cos = incoming.dot(outgoing);
+    if turn.abs() < 1e-6 && cos > 0.0 {
+        return;
+    }
+    if join == LineJoin::Round {
+        pieces.push(circle_polygon(point, half));
+        return;
+    }
+    let side = if turn > 0.0 { -half } else { half };
+    let (normal0, normal1) = (perpendicular(incoming)*side, perpendicular(outgoing)*side);
+    let mut piece = vec![point, point+normal0];
+    if let LineJoin::Miter(limit) = join {
+        // the miter is 1/cos(angle/2) times the stroke width long
+        let cos_half = ((1.0 + cos)*0.5).max(0.0).sqrt();
+        if cos_half > 0.0 && 1.0/cos_half <= limit {
+            piece.push(point + (normal0+normal1).normalized()*(half/cos_half));
+        }
+    }
+    piece.push(point+normal1);
+    pieces.push(piece);
+}
+
+/// `direction` points away from the stroke
+fn cap(pieces: &mut Vec<Vec<Vec2<f32>>>, point: Vec2<f32>, direction: Vec2<f32>, half: f32, cap: LineCap) {
+    let normal = perpendicular(direction)*half;
+    let extension = direction*half;
+    match cap {
+        LineCap::Butt   => {},
+        LineCap::Square => pieces.push(vec![point+normal, point+normal+extension, point-normal+extension, point-normal]),
+        LineCap::Round  => pieces.push(circle_polygon(point, half)),
+    }
+}
+
+/// Closed polygons to coverage, by accumulating signed areas as in font-rs.
+fn rasterize(polygons: &[Vec<Vec2<f32>>], fill_rule: FillRule) -> Mask {
+    let (mut min, mut max) = (vec2(f32::MAX, f32::MAX), vec2(f32::MIN, f32::MIN));
+    for &point in polygons.iter().flatten() {
+        min = min.map2(point, f32::min);
+        max = max.map2(point, f32::max);
+    }
+    if !(min.x < max.x && min.y < max.y) {
+        return Mask::default();
+    }
+    let origin = min.map(f32::floor);
+    let width  = (max.x.ceil() - origin.x) as usize;
+    let height = (max.y.ceil() - origin.y) as usize;
+    // edges on the right border and rounding errors write past the last pixel
+    let mut accumulation = vec![0.0f32; width*height + 2];
+    for polygon in polygons {
+        for i in 0..polygon.len() {
+            let (from, to) = (polygon[i] - origin, polygon[(i+1)%polygon.len()] - origin);
+            accumulate_line(&mut accumulation, width, height, from, to);
+        }
+    }
+    let mut winding = 0.0;
+    let coverage = accumulation[..width*height].iter().map(|area| {
+        winding += area;
+        (fill_coverage(winding, fill_rule)*255.0 + 0.5) as u8
+    }).collect();
+    Mask{ left: origin.x as i32, top: origin.y as i32, width: width as u32, height: height as u32, coverage }
+}
+
+/// Adds the area between the line and each pixel's left edge, signed by the line's direction.
+/// The running sum over all pixels is then the winding number, fractional along edges.
+fn accumulate_line(accumulation: &mut [f32], width: usize, height: usize, from: Vec2<f32>, to: Vec2<f32>) {
+    if from.y == to.y {
+        return;
+    }
+    let (direction, top, bottom) = if from.y < to.y { (1.0, from, to) } else { (-1.0, to, from) };
+    let dxdy = (bottom.x - top.x)/(bottom.y - top.y);
+    let mut x = top.x;
+    for y in (top.y.max(0.0) as usize)..(bottom.y.ceil() as usize).min(height) {
+        let row = y*width;
+        let dy = ((y+1) as f32).min(bottom.y) - (y as f32).max(top.y);
+        let x_next = x + dxdy*dy;
+        let d = dy*direction;
+        let (x0, x1) = if x < x_next { (x, x_next) } else { (x_next, x) };
+        let x0_floor = x0.floor();
+        let x0i = x0_floor as usize;
+        let x1_ceil = x1.ceil();
+        let x1i = x1_ceil as usize;
+        if x1i <= x0i + 1 {
+            // within one pixel, split by the mean x
+            let x_mean = 0.5*(x + x_next) - x0_floor;
+            accumulation[row + x0i]     += d - d*x_mean;
+            accumulation[row + x0i + 1] += d*x_mean;
+        } else {
+            let s = (x1 - x0).recip();
+            let x0_fract = x0 - x0_floor;
+            let a0 = 0.5*s*(1.0 - x0_fract)*(1.0 - x0_fract);
+            let x1_fract = x1 - x1_ceil + 1.0;
+            let a_end = 0.5*s*x1_fract*x1_fract;
+            accumulation[row + x0i] += d*a0;
+            if x1i == x0i + 2 {
+                accumulation[row + x0i + 1] += d*(1.0 - a0 - a_end);
+            } else {
+                let a1 = s*(1.5 - x0_fract);
+                accumulation[row + x0i + 1] += d*(a1 - a0);
+                for xi in x0i+2..x1i-1 {
+                    accumulation[row + xi] += d*s;
+                }
+                let a2 = a1 + (x1i - x0i - 3) as f32*s;
+                accumulation[row + x1i - 1] += d*(1.0 - a2 - a_end);
+            }
+            accumulation[row + x1i] += d*a_end;
+        }
+        x = x_next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(mask: &Mask, x: i32, y: i32) -> u8 {
+        mask.coverage[((y - mask.top) as u32*mask.width + (x - mask.left) as u32) as usize]
+    }
+
+    /// `edge_winding` and `path_coverage` from `shaders/shapes.glsl`
+    fn shader_coverage(edges: &[[f32;4]], rule: FillRule, local: Vec2<f32>) -> f32 {
+        let integral = |t: f32| if t <= 0.0 { 0.0 } else if t >= 1.0 { t - 0.5 } else { 0.5*t*t };
+        let corner = local - vec2(0.5, 0.5);
+        let winding : f32 = edges.iter().map(|&[ax, ay, bx, by]| {
+            let (direction, top, bottom) = if ay < by { (1.0, vec2(ax, ay), vec2(bx, by)) } else { (-1.0, vec2(bx, by), vec2(ax, ay)) };
+            let (y0, y1) = (top.y.max(corner.y), bottom.y.min(corner.y + 1.0));
+            if y0 >= y1 { return 0.0 }
+            let dxdy = (bottom.x - top.x)/(bottom.y - top.y);
+            let f0 = corner.x + 1.0 - (top.x + (y0 - top.y)*dxdy);
+            let f1 = corner.x + 1.0 - (top.x + (y1 - top.y)*dxdy);
+            let right = if (f1 - f0).abs() < 1e-4 { (0.5*(f0 + f1)).clamp(0.0, 1.0) } else { (integral(f1) - integral(f0))/(f1 - f0) };
+            direction*(y1 - y0)*right
+        }).sum();
+        fill_coverage(winding, rule)
+    }
+
+    fn rect(path: Path, from: Vec2<f32>, to: Vec2<f32>) -> Path {
+        path.move_to(from).line_to(vec2(to.x, from.y)).line_to(to).line_to(vec2(from.x, to.y)).close()
+    }
+
+    #[test]
+    fn fill_covers_pixel_area() {
+        let mask = rect(Path::new(), vec2(1, 1), vec2(3.5, 3)).rasterize(&Paint::Fill(FillRule::NonZero));
+        assert_eq!((mask.left, mask.top, mask.width, mask.height), (1, 1, 3, 2));
+        assert_eq!(mask.coverage, [255, 255, 128, 255, 255, 128]);
+    }
+
+    #[test]
+    fn fill_rules() {
+        let nested = rect(rect(Path::new(), vec2(0, 0), vec2(6, 6)), vec2(2, 2), vec2(4, 4));
+        let non_zero = nested.rasterize(&Paint::Fill(FillRule::NonZero));
+        let even_odd = nested.rasterize(&Paint::Fill(FillRule::EvenOdd));
+        assert_eq!((at(&non_zero, 1, 1), at(&non_zero, 3, 3)), (255, 255));
+        assert_eq!((at(&even_odd, 1, 1), at(&even_odd, 3, 3)), (255, 0));
+    }
+
+    #[test]
+    fn shader_matches_rasterize() {
+        let star = (0..5).fold(Path::new(), |path, i| {
+            let point = vec2(20, 20) + Rotor2::from_angle(TAU*(i*2) as f32/5.0).rotate(vec2(0, -15));
+            if i == 0 { path.move_to(point) } else { path.line_to(point) }
+        }).close();
+        let nested = rect(rect(Path::new(), vec2(0.5, 0.25), vec2(9.75, 9)), vec2(3.3, 2.6), vec2(6.1, 7));
+        let stroke = Path::new().move_to(vec2(1, 1)).quad_to(vec2(8, 20), vec2(15, 3));
+        for (path, paint) in [
+            (&star,   Paint::Fill(FillRule::NonZero)),
+            (&star,   Paint::Fill(FillRule::EvenOdd)),
+            (&nested, Paint::Fill(FillRule::EvenOdd)),
+            (&stroke, Paint::Stroke(Stroke::new(2.5).with_join(LineJoin::Round).with_cap(LineCap::Round))),
+        ] {
+            let mask = path.rasterize(&paint);
+            let outline = path.outline(&paint);
+            let mut edges = vec![[0.0;4]; 3]; // edges of other paths
+            let quad = outline.quad(LinearRgba::default(), &mut edges).unwrap();
+            let [first_lo, first_hi, count, info] = quad[0].shape;
+            let first = (first_lo as u32 | (first_hi as u32)<<16) as usize;
+            assert_eq!((first, info>>shapes::KIND_SHIFT), (3, shapes::ShapeKind::Path as u16));
+            assert_eq!(info & 1, (outline.fill_rule == FillRule::EvenOdd) as u16);
+            let edges = &edges[first..first + count as usize];
+            // interpolated like the vertex shader's out_local
+            let [lx, ly] = quad[0].local.map(|l| l as f32/shapes::UNITS_PER_PIXEL);
+            let origin = vec2(quad[0].x as f32 - lx, quad[0].y as f32 - ly);
+            for y in mask.top..mask.top + mask.height as i32 {
+                for x in mask.left..mask.left + mask.width as i32 {
+                    let local = vec2(x as f32 + 0.5, y as f32 + 0.5) - origin;
+                    let coverage = shader_coverage(edges, outline.fill_rule, local);
+                    assert!((coverage*255.0 - at(&mask, x, y) as f32).abs() <= 1.0, "{coverage} at {x},{y}");
+                }
+            }
+            // the quad covers the mask
+            assert!(quad[0].x as i32 <= mask.left && quad[3].x as i32 >= mask.left + mask.width as i32);
+            assert!(quad[0].y as i32 <= mask.top  && quad[3].y as i32 >= mask.top + mask.height as i32);
+        }
+        let flat = Path::new().move_to(vec2(1, 1)).line_to(vec2(5, 1)).close().outline(&Paint::Fill(FillRule::NonZero));
+        assert!(flat.quad(LinearRgba::default(), &mut Vec::new()).is_none());
+    }
+
+    #[test]
+    fn curves_stay_within_tolerance() {
+        let radius = 20.0;
+        let circle = Path::new().move_to(vec2(radius, 0)).arc_to(vec2(0, 0), TAU).close();
+        let area : f32 = circle.rasterize(&Paint::Fill(FillRule::NonZero)).coverage.iter().map(|&c| c as f32/255.0).sum();
+        // chords cut off at most the tolerance along the perimeter
+        let error = PI*radius*radius - area;
+        assert!(error > -0.5 && error < TAU*radius*TOLERANCE, "{area}");
+
+        // quadratic through (0,0), (5,5), (10,0) reaches y=5 in the middle
+        let points = &Path::new().move_to(vec2(0, 0)).quad_to(vec2(5, 10), vec2(10, 0)).flatten()[0].points;
+        let top = points.iter().map(|p| p.y).fold(0.0, f32::max);
+        assert!((top - 5.0).abs() < TOLERANCE, "{top}");
+    }
+
+    #[test]
+    fn stroke_caps_and_joins() {
+        let line = Path::new().move_to(vec2(0, 10)).line_to(vec2(10, 10));
+        let butt   = line.rasterize(&Paint::Stroke(Stroke::new(2.0)));
+        let square = line.rasterize(&Paint::Stroke(Stroke::new(2.0).with_cap(LineCap::Square)));
+        assert_eq!((butt.left, butt.top, butt.width, butt.height), (0, 9, 10, 2));
+        assert!(butt.coverage.iter().all(|&c| c == 255));
+        assert_eq!((square.left, square.width), (-1, 12));
+
+        // the outer corner of a right angle
+        let corner = Path::new().move_to(vec2(0, 0)).line_to(vec2(10, 0)).line_to(vec2(10, 10));
+        let miter = corner.rasterize(&Paint::Stroke(Stroke::new(4.0)));
+        let bevel = corner.rasterize(&Paint::Stroke(Stroke::new(4.0).with_join(LineJoin::Bevel)));
+        let round = corner.rasterize(&Paint::Stroke(Stroke::new(4.0).with_join(LineJoin::Round)));
+        assert_eq!(at(&miter, 11, -2), 255);
+        assert_eq!(at(&bevel, 11, -2), 0);
+        assert!((1..255).contains(&at(&round, 11, -2)));
+        // right angles have a miter ratio of sqrt(2)
+        let limited = corner.rasterize(&Paint::Stroke(Stroke::new(4.0).with_join(LineJoin::Miter(1.4))));
+        assert_eq!(limited, bevel);
+    }
+}
@@ -0,0 +1,263 @@
+//! Anti-aliased 2D shapes as quads for the same vertex and index stream as text.
+//! The fragment shader computes coverage from the distance to the shape's edge,
+//! using `Vertex::local` and `Vertex::shape`.
+use crate::*;
+
+/// Quads extend this far past the shape, for anti-aliasing and rounding the corners to pixels.
+const MARGIN : f32 = 1.5;
+
+/// `Vertex::local` and the sizes in `Vertex::shape` are fixed point, in 1/8 pixels.
+pub const UNITS_PER_PIXEL : f32 = 8.0;
+
+/// `Vertex::shape[3]` holds the kind in its top bits, and the border width below them.
+pub const KIND_SHIFT : u16 = 13;
+const BORDER_MASK : u16 = (1<<KIND_SHIFT)-1;
+
+/// What the fragment shader draws for a quad, the same values as `SHAPE_*` in `shaders/shapes.glsl`.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+#[repr(u16)]
+pub enum ShapeKind {
+    /// samples the glyph texture at `Vertex::u`,`v`, the shape is all zero
+    Glyph      = 0,
+    /// half width, half height, corner radius, border width
+    RoundedBox = 1,
+    /// radii, unused, border width
+    Ellipse    = 2,
+    /// edges from a storage buffer, see `encode_path` and `path::Outline::quad`
+    Path       = 3,
+    /// half length, amplitude, wavelength, thickness, with the phase in `Vertex::u`, see `wave`
+    Wave       = 4,
+}
+
+fn units(pixels: f32) -> u16 {
+    (pixels*UNITS_PER_PIXEL).round().clamp(0.0, u16::MAX as f32) as u16
+}
+
+/// `Vertex::shape` of a `kind` with sizes in pixels, see `ShapeKind`.
+pub fn encode_shape(kind: ShapeKind, params: [f32;3], border: f32) -> [u16;4] {
+    let [a, b, c] = params.map(units);
+    [a, b, c, ((kind as u16)<<KIND_SHIFT) | units(border).min(BORDER_MASK)]
+}
+
+#[derive(Clone,Copy)]
+pub enum Fill {
+    Solid(LinearRgba),
+    /// From `start` at `from` to `end` at `to`, interpolated in linear light.
+    /// Colors are extrapolated past `from` and `to`, so keep them on the shape's edges.
+    LinearGradient{ from: Vec2<f32>, to: Vec2<f32>, start: LinearRgba, end: LinearRgba },
+}
+impl From<LinearRgba> for Fill {
+    fn from(color: LinearRgba) -> Self { Fill::Solid(color) }
+}
+impl Fill {
+    /// affine in `position`, so interpolating the vertex colors is exact
+    fn color_at(&self, position: Vec2<f32>) -> LinearRgba {
+        match *self {
+            Fill::Solid(color) => color,
+            Fill::LinearGradient{ from, to, start, end } => {
+                let axis = to-from;
+                let t = (position-from).dot(axis)/axis.dot(axis).max(f32::EPSILON);
+                start + (end-start)*t
+            },
+        }
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum LineCap {
+    /// ends at the end points
+    Butt,
+    /// extends half the thickness past the end points
+    Square,
+    Round,
+}
+
+/// `Vertex::shape` of a path, with `edge_count` edges from `first_edge` on.
+/// The lowest bit selects the even-odd rule, there is no border.
+pub fn encode_path(first_edge: u32, edge_count: u16, fill_rule: path::FillRule) -> [u16;4] {
+    let even_odd = (fill_rule == path::FillRule::EvenOdd) as u16;
+    [first_edge as u16, (first_edge>>16) as u16, edge_count, ((ShapeKind::Path as u16)<<KIND_SHIFT) | even_odd]
+}
+
+/// Moves the edges of a path quad by `offset`, for concatenating edge buffers. Other quads are left alone.
+pub fn offset_path_edges(quad: &mut [Vertex;4], offset: u32) {
+    for vertex in quad {
+        if vertex.shape[3]>>KIND_SHIFT == ShapeKind::Path as u16 {
+            let first = (vertex.shape[0] as u32 | (vertex.shape[1] as u32)<<16) + offset;
+            (vertex.shape[0], vertex.shape[1]) = (first as u16, (first>>16) as u16);
+        }
+    }
+}
+
+/// `half` extents around `center`, with local x along the unit vector `axis`.
+/// Shapes without area collapse to a point, which draws nothing.
+pub(crate) fn shape_quad(center: Vec2<f32>, half: Vec2<f32>, axis: Vec2<f32>, shape: [u16;4], fill: Fill) -> [Vertex;4] {
+    let normal = vec2(-axis.y, axis.x);
+    let (ex, ey) = if half.x > 0.0 && half.y > 0.0 { (half.x+MARGIN, half.y+MARGIN) } else { (0.0, 0.0) };
+    // top left, bottom left, top right, bottom right, like gen_quad
+    [(-ex,-ey), (-ex,ey), (ex,-ey), (ex,ey)].map(|(lx, ly)| {
+        let corner = center + axis*lx + normal*ly;
+        let position = corner.map(f32::round);
+        // local coordinates of the rounded corner, so they stay affine in the position
+        let offset = position-center;
+        let local = |direction: Vec2<f32>| (offset.dot(direction)*UNITS_PER_PIXEL).round() as i16;
+        Vertex{
+            x: position.x as i16, y: position.y as i16,
+            u: 0, v: 0,
+            color: fill.color_at(position).to_f16(),
+            local: [local(axis), local(normal)],
+            shape,
+        }
+    })
+}
+
+fn half_size(size: Vec2<f32>) -> Vec2<f32> { size.map(|s| s.max(0.0)*0.5) }
+
+pub fn rounded_rect(position: Vec2<f32>, size: Vec2<f32>, radius: f32, fill: impl Into<Fill>) -> [Vertex;4] {
+    border(position, size, radius, 0.0, fill)
+}
+
+/// A `width` wide outline inside the edge of a rounded rectangle, 0 fills it.
+pub fn border(position: Vec2<f32>, size: Vec2<f32>, radius: f32, width: f32, fill: impl Into<Fill>) -> [Vertex;4] {
+    let half = half_size(size);
+    let radius = radius.clamp(0.0, half.x.min(half.y));
+    shape_quad(position+half, half, vec2(1, 0), encode_shape(ShapeKind::RoundedBox, [half.x, half.y, radius], width.max(0.0)), fill.into())
+}
+
+pub fn circle(center: Vec2<f32>, radius: f32, fill: impl Into<Fill>) -> [Vertex;4] {
+    ring(center, radius, 0.0, fill)
+}
+
+/// A `width` wide outline inside the edge of a circle, 0 fills it.
+pub fn ring(center: Vec2<f32>, radius: f32, width: f32, fill: impl Into<Fill>) -> [Vertex;4] {
+    let radius = radius.max(0.0);
+    shape_quad(center, vec2(radius, radius), vec2(1, 0), encode_shape(ShapeKind::RoundedBox, [radius, radius, radius], width.max(0.0)), fill.into())
+}
+
+pub fn ellipse(center: Vec2<f32>, radii: Vec2<f32>, fill: impl Into<Fill>) -> [Vertex;4] {
+    let radii = radii.map(|r| r.max(0.0));
+    shape_quad(center, radii, vec2(1, 0), encode_shape(ShapeKind::Ellipse, [radii.x, radii.y, 0.0], 0.0), fill.into())
+}
+
+/// A horizontal sine from x = `from` to `to` around y = `center`, with butt ends.
+/// It is in phase with x = 0, so pieces of one wave interrupted by gaps line up.
+pub fn wave(from: f32, to: f32, center: f32, amplitude: f32, wavelength: f32, thickness: f32, fill: impl Into<Fill>) -> [Vertex;4] {
+    let (amplitude, thickness) = (amplitude.max(0.0), thickness.max(0.0));
+    let half = vec2((to-from)*0.5, amplitude + thickness*0.5);
+    let middle = vec2((from+to)*0.5, center);
+    // where the middle is within the wavelength, the fragment shader adds it to local x
+    let phase = units(middle.x.rem_euclid(wavelength));
+    let shape = encode_shape(ShapeKind::Wave, [half.x, amplitude, wavelength], thickness);
+    let quad = if thickness > 0.0 && wavelength > 0.0 { half } else { vec2(0, 0) };
+    shape_quad(middle, quad, vec2(1, 0), shape, fill.into()).map(|vertex| Vertex{ u: phase, ..vertex })
+}
+
+pub fn line(from: Vec2<f32>, to: Vec2<f32>, thickness: f32, cap: LineCap, fill: impl Into<Fill>) -> [Vertex;4] {
+    let delta = to-from;
+    let length = delta.dot(delta).sqrt();
+    let axis = if length > 0.0 { delta/length } else { vec2(1, 0) };
+    let half_thickness = thickness.max(0.0)*0.5;
+    let (extend, radius) = match cap {
+        LineCap::Butt   => (0.0, 0.0),
+        LineCap::Square => (half_thickness, 0.0),
+        LineCap::Round  => (half_thickness, half_thickness),
+    };
+    let half = vec2(length*0.5+extend, half_thickness);
+    shape_quad(from+delta*0.5, half, axis, encode_shape(ShapeKind::RoundedBox, [half.x, half.y, radius], 0.0), fill.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool { (a-b).abs() < 1e-4 }
+    fn local(vertex: &Vertex) -> [f32;2] { vertex.local.map(|l| l as f32/UNITS_PER_PIXEL) }
+
+    #[test]
+    fn quads_cover_the_shape() {
+        let quad = rounded_rect(vec2(10.25, 20), vec2(30, 10.5), 4.0, LinearRgba::new(1.0, 1.0, 1.0, 1.0));
+        let [tl, _, _, br] = quad;
+        // at least a pixel of margin for anti-aliasing
+        assert!(tl.x as f32 <= 10.25-1.0 && tl.y as f32 <= 20.0-1.0);
+        assert!(br.x as f32 >= 40.25+1.0 && br.y as f32 >= 30.5+1.0);
+        for vertex in quad {
+            // local coordinates match the rounded positions
+            let [lx, ly] = local(&vertex);
+            assert!(close(lx, vertex.x as f32-25.25) && close(ly, vertex.y as f32-25.25));
+            assert_eq!(vertex.shape, [120, 42, 32, (ShapeKind::RoundedBox as u16)<<KIND_SHIFT]);
+        }
+        // the radius can not exceed the shorter side
+        assert_eq!(rounded_rect(vec2(0, 0), vec2(10, 4), 8.0, LinearRgba::default())[0].shape[2], 16);
+        assert_eq!(ellipse(vec2(0, 0), vec2(3, 2), LinearRgba::default())[0].shape, [24, 16, 0, (ShapeKind::Ellipse as u16)<<KIND_SHIFT]);
+        assert_eq!(border(vec2(0, 0), vec2(10, 10), 0.0, 1.5, LinearRgba::default())[0].shape[3], ((ShapeKind::RoundedBox as u16)<<KIND_SHIFT) | 12);
+    }
+
+    #[test]
+    fn path_edges_move_with_their_buffer() {
+        let mut quad = rounded_rect(vec2(0, 0), vec2(4, 4), 0.0, LinearRgba::default());
+        let mut path = quad.map(|vertex| Vertex{ shape: encode_path(0xFFFF, 7, path::FillRule::EvenOdd), ..vertex });
+        offset_path_edges(&mut path, 2);
+        assert!(path.iter().all(|vertex| vertex.shape == encode_path(0x1_0001, 7, path::FillRule::EvenOdd)));
+        let before = quad[0].shape;
+        offset_path_edges(&mut quad, 2);
+        assert_eq!(quad[0].shape, before);
+    }
+
+    #[test]
+    fn shapes_without_area_draw_nothing() {
+        let color = LinearRgba::new(1.0, 1.0, 1.0, 1.0);
+        for quad in [
+            rounded_rect(vec2(10, 10), vec2(0, 8), 2.0, color),
+            ellipse(vec2(10, 10), vec2(0, 4), color),
+            line(vec2(0, 0), vec2(10, 0), 0.0, LineCap::Round, color),
+            line(vec2(3, 3), vec2(3, 3), 2.0, LineCap::Butt, color),
+        ] {
+            assert!(quad.iter().all(|vertex| (vertex.x, vertex.y) == (quad[0].x, quad[0].y)));
+            // still a shape, never sampled from the glyph texture
+            assert_ne!(quad[0].shape[3]>>KIND_SHIFT, ShapeKind::Glyph as u16);
+        }
+        // a round cap on a zero length line is a dot
+        let dot = line(vec2(3, 3), vec2(3, 3), 2.0, LineCap::Round, color);
+        assert!(dot[3].x > dot[0].x);
+    }
+
+    #[test]
+    fn lines_are_rotated_rectangles() {
+        let quad = line(vec2(0, 0), vec2(0, 10), 2.0, LineCap::Round, LinearRgba::default());
+        for vertex in quad {
+            // local x runs along the line, local y across it
+            let [lx, ly] = local(&vertex);
+            assert!(close(lx, vertex.y as f32-5.0) && close(ly, -(vertex.x as f32)));
+            assert_eq!(vertex.shape, encode_shape(ShapeKind::RoundedBox, [6.0, 1.0, 1.0], 0.0));
+        }
+        assert_eq!(line(vec2(0, 0), vec2(10, 0), 2.0, LineCap::Butt, LinearRgba::default())[0].shape, encode_shape(ShapeKind::RoundedBox, [5.0, 1.0, 0.0], 0.0));
+    }
+
+    #[test]
+    fn waves_line_up_across_pieces() {
+        let color = LinearRgba::new(1.0, 1.0, 1.0, 1.0);
+        for piece in [wave(0.0, 10.0, 20.0, 1.0, 6.0, 1.0, color), wave(13.25, 30.0, 20.0, 1.0, 6.0, 1.0, color)] {
+            assert!(piece[0].y as f32 <= 20.0-1.5-1.0 && piece[3].y as f32 >= 20.0+1.5+1.0);
+            for vertex in piece {
+                // the shader's sine argument is local x plus the phase, the same as the position's
+                let argument = (vertex.local[0] as f32 + vertex.u as f32)/UNITS_PER_PIXEL;
+                let cycles = (argument - vertex.x as f32)/6.0;
+                assert!(close(cycles, cycles.round()), "{cycles}");
+            }
+            assert_eq!(piece[0].shape[1..], [8, 48, ((ShapeKind::Wave as u16)<<KIND_SHIFT) | 8]);
+        }
+        let flat = wave(0.0, 10.0, 20.0, 1.0, 6.0, 0.0, color);
+        assert!(flat.iter().all(|vertex| (vertex.x, vertex.y) == (flat[0].x, flat[0].y)));
+    }
+
+    #[test]
+    fn gradients_are_affine() {
+        let (start, end) = (LinearRgba::new(0.0, 0.0, 0.0, 1.0), LinearRgba::new(1.0, 0.5, 0.0, 1.0));
+        let fill = Fill::LinearGradient{ from: vec2(0, 0), to: vec2(10, 0), start, end };
+        assert_eq!(fill.color_at(vec2(0, 7)), start);
+        assert_eq!(fill.color_at(vec2(10, -3)), end);
+        assert_eq!(fill.color_at(vec2(5, 0)), LinearRgba::new(0.5, 0.25, 0.0, 1.0));
+        let quad = rounded_rect(vec2(0, 0), vec2(10, 10), 0.0, fill);
+        assert_eq!((quad[0].color[0], quad[3].color[0]), (f16_bits(-0.2), f16_bits(1.2)));
+    }
+}
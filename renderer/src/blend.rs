@@ -0,0 +1,102 @@
+use ash::vk;
+
+/// Common blend setups for `Frame::set_blend_mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    /// blending disabled, the fragment replaces the target
+    Opaque,
+    /// straight alpha, the result alpha is premultiplied-correct only for opaque targets
+    Alpha,
+    /// premultiplied color over premultiplied target, the target may be transparent
+    Premultiplied,
+    /// adds the premultiplied color, keeps the target's alpha
+    Additive,
+    /// subpixel text with dual-source blending, where the second output holds per channel coverage.
+    /// Only correct on opaque targets, the target's alpha is left alone
+    ComponentAlpha,
+    /// subpixel text writing premultiplied RGBA with coverage in alpha, for transparent targets.
+    /// Needs the fragment shader's `PREMULTIPLIED` path.
+    /// The layer keeps a single alpha, the strongest channel's coverage, so compositing it with
+    /// `Premultiplied` scales the background by 1-max(coverage) in every channel. Channels with less
+    /// coverage show less background than drawing directly with `ComponentAlpha`, a slight fringe
+    ComponentAlphaPremultiplied,
+}
+
+impl BlendMode {
+    /// None if blending is disabled
+    pub fn equation(self) -> Option<vk::ColorBlendEquationEXT> {
+        use vk::BlendFactor as F;
+        let (src_color, dst_color, src_alpha, dst_alpha) = match self {
+            BlendMode::Opaque                      => return None,
+            BlendMode::Alpha                       => (F::SRC_ALPHA, F::ONE_MINUS_SRC_ALPHA, F::ONE,  F::ONE_MINUS_SRC_ALPHA),
+            BlendMode::Premultiplied               => (F::ONE,       F::ONE_MINUS_SRC_ALPHA, F::ONE,  F::ONE_MINUS_SRC_ALPHA),
+            BlendMode::Additive                    => (F::ONE,       F::ONE,                 F::ZERO, F::ONE),
+            BlendMode::ComponentAlpha              => (F::SRC1_COLOR, F::ONE_MINUS_SRC1_COLOR, F::ZERO, F::ONE),
+            BlendMode::ComponentAlphaPremultiplied => (F::ONE,       F::ONE_MINUS_SRC1_COLOR, F::ONE,  F::ONE_MINUS_SRC1_ALPHA),
+        };
+        Some(vk::ColorBlendEquationEXT::default()
+            .src_color_blend_factor(src_color)
+            .dst_color_blend_factor(dst_color)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(src_alpha)
+            .dst_alpha_blend_factor(dst_alpha)
+            .alpha_blend_op(vk::BlendOp::ADD))
+    }
+
+    /// whether the fragment shader needs a second output at location 1
+    pub const fn dual_source(self) -> bool {
+        matches!(self, BlendMode::ComponentAlpha | BlendMode::ComponentAlphaPremultiplied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// evaluates the equation for one channel
+    fn blend(mode: BlendMode, (src, src_a): (f32, f32), (src1, src1_a): (f32, f32), (dst, dst_a): (f32, f32)) -> (f32, f32) {
+        let Some(eq) = mode.equation() else { return (src, src_a) };
+        let factor = |factor: vk::BlendFactor| match factor {
+            vk::BlendFactor::ZERO                  => 0.0,
+            vk::BlendFactor::ONE                   => 1.0,
+            vk::BlendFactor::SRC_ALPHA             => src_a,
+            vk::BlendFactor::ONE_MINUS_SRC_ALPHA   => 1.0-src_a,
+            vk::BlendFactor::SRC1_COLOR            => src1,
+            vk::BlendFactor::ONE_MINUS_SRC1_COLOR  => 1.0-src1,
+            vk::BlendFactor::ONE_MINUS_SRC1_ALPHA  => 1.0-src1_a,
+            _ => panic!("unsupported blend factor {factor:?}"),
+        };
+        (src*factor(eq.src_color_blend_factor) + dst*factor(eq.dst_color_blend_factor),
+         src_a*factor(eq.src_alpha_blend_factor) + dst_a*factor(eq.dst_alpha_blend_factor))
+    }
+
+    #[test]
+    fn premultiplied_text_composites_like_opaque_text() {
+        let (color, coverage) = (0.8, 0.25);
+        // drawn straight onto an opaque background
+        let background = 0.1;
+        let (direct, _) = blend(BlendMode::ComponentAlpha, (color, 1.0), (coverage, 1.0), (background, 1.0));
+        // drawn into a transparent layer first, which is then composited onto the background
+        let (layer, layer_a) = blend(BlendMode::ComponentAlphaPremultiplied, (color*coverage, coverage), (coverage, coverage), (0.0, 0.0));
+        assert!((layer_a-coverage).abs() < 1e-6);
+        let (composited, alpha) = blend(BlendMode::Premultiplied, (layer, layer_a), (0.0, 0.0), (background, 1.0));
+        assert!((direct-composited).abs() < 1e-6);
+        assert_eq!(alpha, 1.0);
+        assert!(BlendMode::ComponentAlphaPremultiplied.dual_source() && !BlendMode::Premultiplied.dual_source());
+    }
+
+    #[test]
+    fn unequal_coverage_loses_background_in_weaker_channels() {
+        let (color, background) = (0.8, 0.1);
+        let coverage = [0.9, 0.3, 0.6];
+        let alpha = 0.9; // the shader writes the strongest channel
+        for channel_coverage in coverage {
+            let (direct, _) = blend(BlendMode::ComponentAlpha, (color, 1.0), (channel_coverage, 1.0), (background, 1.0));
+            let (layer, layer_a) = blend(BlendMode::ComponentAlphaPremultiplied, (color*channel_coverage, alpha), (channel_coverage, alpha), (0.0, 0.0));
+            assert_eq!(layer_a, alpha);
+            let (composited, _) = blend(BlendMode::Premultiplied, (layer, layer_a), (0.0, 0.0), (background, 1.0));
+            // exact for the strongest channel, the others are missing background*(alpha-coverage)
+            assert!((direct - composited - background*(alpha-channel_coverage)).abs() < 1e-6);
+        }
+    }
+}
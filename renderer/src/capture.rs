@@ -0,0 +1,94 @@
+use std::sync::mpsc;
+use ash::vk;
+use crate::RgbaImage;
+
+/// A frame read back by `Frame::capture`. The pixels arrive once the gpu finished the frame,
+/// which the renderer notices at the start of the next frame or in `Renderer::finish_captures`.
+pub struct Capture {
+    receiver: mpsc::Receiver<RgbaImage>,
+}
+
+impl Capture {
+    /// The captured pixels, or None if the frame is still in flight.
+    pub fn try_take(&self) -> Option<RgbaImage> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+pub(crate) struct PendingCapture {
+    pub buffer: vk::Buffer,
+    pub memory: vk::DeviceMemory,
+    pub extent: vk::Extent2D,
+    pub format: vk::Format,
+    sender: mpsc::Sender<RgbaImage>,
+}
+
+impl PendingCapture {
+    pub(crate) fn new(buffer: vk::Buffer, memory: vk::DeviceMemory, extent: vk::Extent2D, format: vk::Format) -> (Self, Capture) {
+        let (sender, receiver) = mpsc::channel();
+        (Self{ buffer, memory, extent, format, sender }, Capture{ receiver })
+    }
+
+    /// Decodes the buffer and hands the pixels to the `Capture`, the frame must have finished.
+    pub(crate) fn complete(self, device: &ash::Device) {
+        let size = self.extent.width as usize*self.extent.height as usize*4;
+        let ptr = unsafe{device.map_memory(self.memory, 0, size as u64, vk::MemoryMapFlags::empty())}.expect("memory map failed");
+        let bytes = unsafe{core::slice::from_raw_parts(ptr.cast::<u8>(), size)};
+        let pixels = to_rgba(self.format, bytes).expect("capture format was checked when recording");
+        unsafe{device.unmap_memory(self.memory)};
+        unsafe{device.destroy_buffer(self.buffer, None)};
+        unsafe{device.free_memory(self.memory, None)};
+        // nobody may be waiting for the capture anymore
+        let _ = self.sender.send(RgbaImage{ width: self.extent.width, height: self.extent.height, pixels });
+    }
+}
+
+/// Whether `to_rgba` converts `format`, only 8 bit RGBA and BGRA.
+pub(crate) fn supports(format: vk::Format) -> bool {
+    matches!(format, vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB | vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB)
+}
+
+/// Converts tightly packed texels to RGBA8, None for formats captures don't support.
+/// sRGB formats keep their encoded values.
+pub(crate) fn to_rgba(format: vk::Format, bytes: &[u8]) -> Option<Vec<u8>> {
+    match format {
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => Some(bytes.to_vec()),
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB =>
+            Some(bytes.chunks_exact(4).flat_map(|bgra| [bgra[2], bgra[1], bgra[0], bgra[3]]).collect()),
+        _ => None,
+    }
+}
+
+#[cfg(feature="png")]
+impl RgbaImage {
+    pub fn encode_png(&self) -> Result<Vec<u8>, String> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|err| format!("png encoding failed: {err}"))?;
+        writer.write_image_data(&self.pixels).map_err(|err| format!("png encoding failed: {err}"))?;
+        writer.finish().map_err(|err| format!("png encoding failed: {err}"))?;
+        Ok(png)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bgra_is_swizzled() {
+        let bgra = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(to_rgba(vk::Format::B8G8R8A8_SRGB, &bgra).unwrap(), [3, 2, 1, 4, 7, 6, 5, 8]);
+        assert_eq!(to_rgba(vk::Format::R8G8B8A8_UNORM, &bgra).unwrap(), bgra);
+        assert_eq!(to_rgba(vk::Format::R16G16B16A16_SFLOAT, &bgra), None);
+    }
+
+    #[test]
+    fn supported_formats_convert() {
+        for format in [vk::Format::R8G8B8A8_SRGB, vk::Format::B8G8R8A8_UNORM, vk::Format::R16G16B16A16_SFLOAT, vk::Format::A2B10G10R10_UNORM_PACK32, vk::Format::UNDEFINED] {
+            assert_eq!(supports(format), to_rgba(format, &[0; 4]).is_some(), "{format:?}");
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use ash::vk;
+use crate::ERR_STR;
+
+/// Descriptors of each type a pool holds per set.
+const POOL_RATIOS : [(vk::DescriptorType, u32); 6] = [
+    (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 4),
+    (vk::DescriptorType::SAMPLED_IMAGE,          4),
+    (vk::DescriptorType::SAMPLER,                1),
+    (vk::DescriptorType::STORAGE_IMAGE,          2),
+    (vk::DescriptorType::UNIFORM_BUFFER,         2),
+    (vk::DescriptorType::STORAGE_BUFFER,         2),
+];
+const FIRST_POOL_SETS : u32 = 16;
+const MAX_POOL_SETS   : u32 = 1024;
+
+/// Each new pool holds twice as many sets as the previous one, up to MAX_POOL_SETS.
+fn next_pool_sets(sets: u32) -> u32 {
+    (sets*2).min(MAX_POOL_SETS)
+}
+
+/// Allocates descriptor sets of any layout, creating pools as they run out.
+/// Sets are freed all at once by `reset`.
+pub struct DescriptorAllocator {
+    ready:     Vec<vk::DescriptorPool>,
+    full:      Vec<vk::DescriptorPool>,
+    pool_sets: u32,
+}
+
+impl Default for DescriptorAllocator {
+    fn default() -> Self {
+        Self{ ready: Vec::new(), full: Vec::new(), pool_sets: FIRST_POOL_SETS }
+    }
+}
+
+impl DescriptorAllocator {
+    fn create_pool(&mut self, device: &ash::Device) -> vk::DescriptorPool {
+        let sets = self.pool_sets;
+        self.pool_sets = next_pool_sets(sets);
+        let pool_sizes = POOL_RATIOS.map(|(ty, count)| vk::DescriptorPoolSize{ ty, descriptor_count: count*sets });
+        let pool_info = vk::DescriptorPoolCreateInfo::default()
+            .pool_sizes(&pool_sizes)
+            .max_sets(sets);
+        unsafe{device.create_descriptor_pool(&pool_info, None)}.unwrap()
+    }
+
+    pub fn allocate(&mut self, device: &ash::Device, layout: vk::DescriptorSetLayout) -> vk::DescriptorSet {
+        let layouts = [layout];
+        // a fresh pool can only fail if a single set needs more descriptors than it holds
+        for _ in 0..2 {
+            let pool = match self.ready.last() {
+                Some(pool) => *pool,
+                None => { let pool = self.create_pool(device); self.ready.push(pool); pool },
+            };
+            let alloc_info = vk::DescriptorSetAllocateInfo::default()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts);
+            match unsafe{device.allocate_descriptor_sets(&alloc_info)} {
+                Ok(sets) => return sets[0],
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) => {
+                    self.full.push(self.ready.pop().unwrap());
+                },
+                Err(err) => panic!("\n{ERR_STR} descriptor set allocation failed\n{err}\n"),
+            }
+        }
+        panic!("\n{ERR_STR} descriptor set layout {layout:?} does not fit into a descriptor pool\n")
+    }
+
+    /// Every pool created so far, in no particular order.
+    pub fn pools(&self) -> Vec<vk::DescriptorPool> {
+        self.ready.iter().chain(self.full.iter()).copied().collect()
+    }
+
+    /// Frees every set allocated so far, they must no longer be in use by the gpu.
+    pub fn reset(&mut self, device: &ash::Device) {
+        self.ready.append(&mut self.full);
+        for pool in &self.ready {
+            unsafe{device.reset_descriptor_pool(*pool, vk::DescriptorPoolResetFlags::empty())}.unwrap();
+        }
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for pool in self.ready.drain(..).chain(self.full.drain(..)) {
+            unsafe{device.destroy_descriptor_pool(pool, None)};
+        }
+        self.pool_sets = FIRST_POOL_SETS;
+    }
+}
+
+enum WriteInfo {
+    Image(vk::DescriptorImageInfo),
+    Buffer(vk::DescriptorBufferInfo),
+}
+
+/// Collects descriptor writes into one set and submits them with a single `vkUpdateDescriptorSets`.
+///
+/// ```ignore
+/// DescriptorWriter::new(set)
+///     .combined_image_sampler(0, view, sampler)
+///     .storage_buffer(1, buffer, 0, vk::WHOLE_SIZE)
+///     .update(&renderer.device);
+/// ```
+pub struct DescriptorWriter {
+    set:    vk::DescriptorSet,
+    writes: Vec<(u32, u32, vk::DescriptorType, WriteInfo)>, // (binding, array element, type, info)
+}
+
+impl DescriptorWriter {
+    pub fn new(set: vk::DescriptorSet) -> Self {
+        Self{ set, writes: Vec::new() }
+    }
+
+    /// For `Frame::push_descriptor_set`, which picks the set itself.
+    pub fn push() -> Self {
+        Self::new(vk::DescriptorSet::null())
+    }
+
+    fn image(mut self, binding: u32, element: u32, ty: vk::DescriptorType, info: vk::DescriptorImageInfo) -> Self {
+        self.writes.push((binding, element, ty, WriteInfo::Image(info)));
+        self
+    }
+    fn buffer(mut self, binding: u32, ty: vk::DescriptorType, buffer: vk::Buffer, offset: u64, range: u64) -> Self {
+        self.writes.push((binding, 0, ty, WriteInfo::Buffer(vk::DescriptorBufferInfo{ buffer, offset, range })));
+        self
+    }
+
+    /// The image has to be in `SHADER_READ_ONLY_OPTIMAL` when used.
+    pub fn combined_image_sampler(self, binding: u32, view: vk::ImageView, sampler: vk::Sampler) -> Self {
+        self.combined_image_sampler_element(binding, 0, view, sampler)
+    }
+    pub fn combined_image_sampler_element(self, binding: u32, element: u32, view: vk::ImageView, sampler: vk::Sampler) -> Self {
+        let info = vk::DescriptorImageInfo{ sampler, image_view: view, image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL };
+        self.image(binding, element, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, info)
+    }
+    /// The image has to be in `SHADER_READ_ONLY_OPTIMAL` when used.
+    pub fn sampled_image(self, binding: u32, view: vk::ImageView) -> Self {
+        let info = vk::DescriptorImageInfo{ sampler: vk::Sampler::null(), image_view: view, image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL };
+        self.image(binding, 0, vk::DescriptorType::SAMPLED_IMAGE, info)
+    }
+    pub fn sampler(self, binding: u32, sampler: vk::Sampler) -> Self {
+        let info = vk::DescriptorImageInfo{ sampler, ..Default::default() };
+        self.image(binding, 0, vk::DescriptorType::SAMPLER, info)
+    }
+    /// The image has to be in `GENERAL` when used.
+    pub fn storage_image(self, binding: u32, view: vk::ImageView) -> Self {
+        let info = vk::DescriptorImageInfo{ sampler: vk::Sampler::null(), image_view: view, image_layout: vk::ImageLayout::GENERAL };
+        self.image(binding, 0, vk::DescriptorType::STORAGE_IMAGE, info)
+    }
+    pub fn uniform_buffer(self, binding: u32, buffer: vk::Buffer, offset: u64, range: u64) -> Self {
+        self.buffer(binding, vk::DescriptorType::UNIFORM_BUFFER, buffer, offset, range)
+    }
+    pub fn storage_buffer(self, binding: u32, buffer: vk::Buffer, offset: u64, range: u64) -> Self {
+        self.buffer(binding, vk::DescriptorType::STORAGE_BUFFER, buffer, offset, range)
+    }
+
+    pub fn update(self, device: &ash::Device) {
+        unsafe{device.update_descriptor_sets(&self.writes(self.set), &[])};
+    }
+
+    /// the writes targeting `set`, borrowing the collected infos
+    pub(crate) fn writes(&self, set: vk::DescriptorSet) -> Vec<vk::WriteDescriptorSet<'_>> {
+        self.writes.iter().map(|(binding, element, ty, info)| {
+            let write = vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(*binding)
+                .dst_array_element(*element)
+                .descriptor_type(*ty);
+            match info {
+                WriteInfo::Image(info)  => write.image_info(core::slice::from_ref(info)),
+                WriteInfo::Buffer(info) => write.buffer_info(core::slice::from_ref(info)),
+            }
+        }).collect()
+    }
+}
+
+/// A single set with a large, partially bound array of combined image samplers at binding 0,
+/// indexed in shaders with `nonuniformEXT`. Slots can be written while the set is bound.
+pub struct BindlessTextures {
+    pub set_layout: vk::DescriptorSetLayout,
+    pub set:        vk::DescriptorSet,
+    pub(crate) pool: vk::DescriptorPool,
+    capacity:  u32,
+    next_free: u32,
+    free:      Vec<u32>,
+}
+
+impl BindlessTextures {
+    /// Needs `Renderer::bindless`.
+    pub fn new(device: &ash::Device, capacity: u32, stages: vk::ShaderStageFlags) -> Self {
+        let bindings = [vk::DescriptorSetLayoutBinding::default()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(capacity)
+            .stage_flags(stages)];
+        let binding_flags = [vk::DescriptorBindingFlags::PARTIALLY_BOUND | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND];
+        let mut binding_flags = vk::DescriptorSetLayoutBindingFlagsCreateInfo::default().binding_flags(&binding_flags);
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::default()
+            .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+            .bindings(&bindings)
+            .push_next(&mut binding_flags);
+        let set_layout = unsafe{device.create_descriptor_set_layout(&layout_info, None)}.unwrap();
+
+        let pool_sizes = [vk::DescriptorPoolSize{ ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER, descriptor_count: capacity }];
+        let pool_info = vk::DescriptorPoolCreateInfo::default()
+            .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+            .pool_sizes(&pool_sizes)
+            .max_sets(1);
+        let pool = unsafe{device.create_descriptor_pool(&pool_info, None)}.unwrap();
+        let layouts = [set_layout];
+        let alloc_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(pool)
+            .set_layouts(&layouts);
+        let set = unsafe{device.allocate_descriptor_sets(&alloc_info)}.unwrap()[0];
+        Self{ set_layout, set, pool, capacity, next_free: 0, free: Vec::new() }
+    }
+
+    /// Returns the array index the shaders use for this texture.
+    pub fn add(&mut self, device: &ash::Device, view: vk::ImageView, sampler: vk::Sampler) -> u32 {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None if self.next_free < self.capacity => { self.next_free += 1; self.next_free-1 },
+            None => panic!("\n{ERR_STR} bindless texture table is full ({} textures)\n", self.capacity),
+        };
+        DescriptorWriter::new(self.set).combined_image_sampler_element(0, index, view, sampler).update(device);
+        index
+    }
+
+    /// The slot may be reused by the next `add`, shaders must no longer read it.
+    pub fn remove(&mut self, index: u32) {
+        debug_assert!(index < self.next_free && !self.free.contains(&index));
+        self.free.push(index);
+    }
+
+    /// For tables created with `new`, the renderer's are destroyed by `Renderer::destroy_bindless_textures`.
+    pub fn destroy(&mut self, device: &ash::Device) {
+        unsafe{device.destroy_descriptor_pool(self.pool, None)};
+        unsafe{device.destroy_descriptor_set_layout(self.set_layout, None)};
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pools_grow_up_to_a_limit() {
+        let mut sets = FIRST_POOL_SETS;
+        let mut sizes = vec![sets];
+        while sets < MAX_POOL_SETS {
+            sets = next_pool_sets(sets);
+            sizes.push(sets);
+        }
+        assert_eq!(sizes, [16, 32, 64, 128, 256, 512, 1024]);
+        assert_eq!(next_pool_sets(MAX_POOL_SETS), MAX_POOL_SETS);
+    }
+}
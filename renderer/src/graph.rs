@@ -0,0 +1,382 @@
+use std::collections::BinaryHeap;
+use core::cmp::Reverse;
+use ash::vk::{self, Extent2D, Image, ImageView};
+use crate::{Frame, Usage, ImageDesc, Renderer, LoadOp, ColorAttachment, RenderTarget};
+
+/// Handle to an image inside a `RenderGraph`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GraphImage(usize);
+
+enum Resource {
+    Swapchain,
+    Imported{ image: Image, view: ImageView, extent: Extent2D },
+    Transient{ desc: ImageDesc },
+}
+
+/// Declares what a pass reads and writes, see `RenderGraph::add_pass`.
+#[derive(Default)]
+pub struct PassBuilder {
+    reads:  Vec<(GraphImage, Usage)>,
+    colors: Vec<(GraphImage, LoadOp)>,
+}
+impl PassBuilder {
+    /// the pass samples `image` in its fragment shader
+    pub fn sample(&mut self, image: GraphImage) -> &mut Self { self.read(image, Usage::SampledFragment) }
+    pub fn read(&mut self, image: GraphImage, usage: Usage) -> &mut Self {
+        assert!(!usage.is_write(), "use color() to declare pass outputs");
+        self.reads.push((image, usage));
+        self
+    }
+    /// the pass renders into `image`, color attachments are bound in declaration order
+    pub fn color(&mut self, image: GraphImage, load: LoadOp) -> &mut Self {
+        self.colors.push((image, load));
+        self
+    }
+}
+
+struct Pass<'g> {
+    name:   &'static str,
+    reads:  Vec<(GraphImage, Usage)>,
+    colors: Vec<(GraphImage, LoadOp)>,
+    record: Box<dyn FnOnce(&mut Frame, &GraphResources) + 'g>,
+}
+impl Pass<'_> {
+    fn touches(&self, image: GraphImage) -> bool {
+        self.reads(image) || self.writes(image)
+    }
+    fn reads(&self, image: GraphImage) -> bool {
+        self.reads.iter().any(|&(i,_)|i==image)
+    }
+    fn writes(&self, image: GraphImage) -> bool {
+        self.colors.iter().any(|&(i,_)|i==image)
+    }
+}
+
+/// Physical images backing the graph handles, valid while the graph executes.
+pub struct GraphResources {
+    images: Vec<(Image, ImageView, Extent2D)>,
+}
+impl GraphResources {
+    pub fn image(&self, image: GraphImage)  -> Image     { self.images[image.0].0 }
+    pub fn view(&self, image: GraphImage)   -> ImageView { self.images[image.0].1 }
+    pub fn extent(&self, image: GraphImage) -> Extent2D  { self.images[image.0].2 }
+}
+
+/// A per-frame list of passes on top of dynamic rendering.
+/// Passes are ordered by their dependencies, transient images are allocated from a pool
+/// that is shared between frames, and barriers are derived from the declared reads and writes.
+/// A read sees the last write declared before it, so imported images can be read before
+/// they are overwritten, e.g. history buffers. Transients have no contents before their
+/// first write, reading them before any write is declared reads the first write.
+/// ```ignore
+/// let mut graph = RenderGraph::new();
+/// let layer = graph.transient(extent, vk::Format::R8G8B8A8_UNORM);
+/// let target = graph.swapchain();
+/// graph.add_pass("composite", |p|{ p.sample(layer).color(target, LoadOp::Load); }, |frame, res|{ ... });
+/// graph.add_pass("text",      |p|{ p.color(layer, LoadOp::Clear([0.0;4])); },     |frame, res|{ ... });
+/// graph.execute(&mut frame); // runs "text" before "composite"
+/// ```
+#[derive(Default)]
+pub struct RenderGraph<'g> {
+    resources: Vec<Resource>,
+    passes:    Vec<Pass<'g>>,
+}
+
+impl<'g> RenderGraph<'g> {
+    pub fn new() -> Self { Self::default() }
+
+    /// the swapchain image of the frame the graph is executed in
+    pub fn swapchain(&mut self) -> GraphImage { self.add_resource(Resource::Swapchain) }
+
+    /// an externally owned image, its state is tracked by the renderer
+    pub fn import(&mut self, image: Image, view: ImageView, extent: Extent2D) -> GraphImage {
+        self.add_resource(Resource::Imported{ image, view, extent })
+    }
+
+    /// an image that only lives for the duration of the graph, its contents start out undefined
+    pub fn transient(&mut self, extent: Extent2D, format: vk::Format) -> GraphImage {
+        self.transient_with_desc(ImageDesc{
+            width:  extent.width,
+            height: extent.height,
+            format,
+            usage:  vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            aspect: vk::ImageAspectFlags::COLOR,
+            samples: vk::SampleCountFlags::TYPE_1,
+        })
+    }
+    pub fn transient_with_desc(&mut self, desc: ImageDesc) -> GraphImage {
+        self.add_resource(Resource::Transient{ desc })
+    }
+
+    fn add_resource(&mut self, resource: Resource) -> GraphImage {
+        self.resources.push(resource);
+        GraphImage(self.resources.len()-1)
+    }
+
+    pub fn add_pass(&mut self, name: &'static str,
+            setup:  impl FnOnce(&mut PassBuilder),
+            record: impl FnOnce(&mut Frame, &GraphResources) + 'g){
+        let mut builder = PassBuilder::default();
+        setup(&mut builder);
+        for &(image,_) in builder.reads.iter() {
+            assert!(!builder.colors.iter().any(|&(i,_)|i==image), "pass {name:?} reads and writes the same image");
+        }
+        self.passes.push(Pass{ name, reads: builder.reads, colors: builder.colors, record: Box::new(record) });
+    }
+
+    /// Topological order of the passes. Readers run after the write they see and before
+    /// the next write of the image, multiple writers of the same image keep their declaration order.
+    /// Independent passes keep their declaration order as well.
+    fn order(&self) -> Vec<usize> {
+        let n = self.passes.len();
+        let mut edges = vec![Vec::new(); n];
+        let mut in_degree = vec![0; n];
+        for (idx, resource) in self.resources.iter().enumerate() {
+            let image = GraphImage(idx);
+            let writers : Vec<usize> = (0..n).filter(|&p|self.passes[p].writes(image)).collect();
+            for pair in writers.windows(2) {
+                edges[pair[0]].push(pair[1]);
+            }
+            let transient = matches!(resource, Resource::Transient{..});
+            for reader in (0..n).filter(|&p|self.passes[p].reads(image)) {
+                // number of writes that happen before the read
+                let seen = writers.partition_point(|&writer| writer < reader);
+                let seen = if seen == 0 && transient { writers.len().min(1) } else { seen };
+                if let Some(&writer) = seen.checked_sub(1).and_then(|last| writers.get(last)) {
+                    edges[writer].push(reader);
+                }
+                // write after read
+                if let Some(&writer) = writers.get(seen) {
+                    edges[reader].push(writer);
+                }
+            }
+        }
+        for &to in edges.iter().flatten() {
+            in_degree[to] += 1;
+        }
+
+        let mut ready : BinaryHeap<Reverse<usize>> = (0..n).filter(|&p|in_degree[p]==0).map(Reverse).collect();
+        let mut order = Vec::with_capacity(n);
+        while let Some(Reverse(pass)) = ready.pop() {
+            order.push(pass);
+            for &to in edges[pass].iter() {
+                in_degree[to] -= 1;
+                if in_degree[to]==0 { ready.push(Reverse(to)); }
+            }
+        }
+        if order.len() != n {
+            let stuck : Vec<_> = (0..n).filter(|p|!order.contains(p)).map(|p|self.passes[p].name).collect();
+            panic!("render graph has a cycle between passes {stuck:?}");
+        }
+        order
+    }
+
+    /// (first step, last step, resource index, desc) of every used transient, sorted by first step.
+    fn lifetimes(&self, order: &[usize]) -> Vec<(usize, usize, usize, ImageDesc)> {
+        let mut lifetimes : Vec<_> = self.resources.iter().enumerate().filter_map(|(idx, resource)|{
+            let Resource::Transient{desc} = resource else { return None };
+            let mut uses = order.iter().enumerate().filter(|(_,&p)|self.passes[p].touches(GraphImage(idx))).map(|(step,_)|step);
+            let first = uses.next()?;
+            let last = uses.last().unwrap_or(first);
+            Some((first, last, idx, *desc))
+        }).collect();
+        lifetimes.sort_by_key(|&(first,..)|first);
+        lifetimes
+    }
+
+    /// Assigns pooled images to the transient resources. Transients whose lifetimes
+    /// do not overlap share an image.
+    fn allocate(&self, renderer: &mut Renderer, order: &[usize]) -> Vec<Option<usize>> {
+        let lifetimes = self.lifetimes(order);
+        let mut pool : Vec<ImageDesc> = renderer.transient_images.iter().map(|(pooled,_)|pooled.desc).collect();
+        let slots = alias_slots(lifetimes.iter().map(|&(first, last, _, desc)|(first, last, desc)), &mut pool);
+        for desc in pool[renderer.transient_images.len()..].iter() {
+            let pooled = renderer.alloc_image(desc);
+            renderer.transient_images.push((pooled, 0));
+        }
+
+        let mut assigned = vec![None; self.resources.len()];
+        for (&(_, _, idx, _), slot) in lifetimes.iter().zip(slots) {
+            renderer.transient_images[slot].1 = renderer.frame_index;
+            assigned[idx] = Some(slot);
+        }
+
+        // Frame::new waits on the previous submission, so anything not used this frame is idle
+        let frame_index = renderer.frame_index;
+        let (keep, stale) : (Vec<_>, Vec<_>) = renderer.transient_images.drain(..).enumerate()
+            .partition(|(_,(_,last_used))| *last_used == frame_index);
+        let mut remap = vec![None; pool.len()];
+        for (new_slot, (old_slot, pooled)) in keep.into_iter().enumerate() {
+            remap[old_slot] = Some(new_slot);
+            renderer.transient_images.push(pooled);
+        }
+        for (_, (pooled, _)) in stale {
+            renderer.free_image(pooled);
+        }
+        assigned.into_iter().map(|slot|slot.and_then(|slot|remap[slot])).collect()
+    }
+
+    pub fn execute(self, frame: &mut Frame){
+        let order = self.order();
+        let slots = self.allocate(frame.renderer(), &order);
+
+        let images = self.resources.iter().zip(slots.iter()).map(|(resource, slot)| match resource {
+            Resource::Swapchain => (frame.swapchain_image(), frame.swapchain_view(), frame.swapchain_extent()),
+            Resource::Imported{image, view, extent} => (*image, *view, *extent),
+            Resource::Transient{..} => {
+                let (pooled,_) = frame.renderer().transient_images[slot.unwrap()];
+                (pooled.image, pooled.view, pooled.desc.extent())
+            },
+        }).collect();
+        let resources = GraphResources{ images };
+
+        let mut first_use = vec![true; self.resources.len()];
+        let mut passes : Vec<Option<Pass>> = self.passes.into_iter().map(Some).collect();
+        for pass in order {
+            let Pass{ reads, colors, record, .. } = passes[pass].take().unwrap();
+            for &(image, usage) in reads.iter() {
+                first_use[image.0] = false;
+                frame.use_image(resources.image(image), usage);
+            }
+            let attachments : Vec<_> = colors.iter().map(|&(image, load)|{
+                // transients start out undefined, there is nothing to load
+                let transient = matches!(self.resources[image.0], Resource::Transient{..});
+                let load = if load == LoadOp::Load && transient && first_use[image.0] { LoadOp::DontCare } else { load };
+                first_use[image.0] = false;
+                ColorAttachment::new(resources.image(image), resources.view(image), load)
+            }).collect();
+
+            if colors.is_empty() {
+                record(frame, &resources);
+                continue;
+            }
+            let extent = colors.iter()
+                .map(|&(image,_)|resources.extent(image))
+                .reduce(|a,b|Extent2D{ width: a.width.min(b.width), height: a.height.min(b.height) })
+                .unwrap();
+            frame.begin_rendering_with(&RenderTarget::new(extent, &attachments));
+            record(frame, &resources);
+            frame.end_rendering();
+        }
+    }
+}
+
+/// Picks a slot of `pool` for each (first step, last step, desc), in order of first step.
+/// Lifetimes that do not overlap share a slot, slots that are missing are appended to `pool`.
+fn alias_slots(lifetimes: impl IntoIterator<Item=(usize, usize, ImageDesc)>, pool: &mut Vec<ImageDesc>) -> Vec<usize> {
+    let mut busy_until : Vec<Option<usize>> = vec![None; pool.len()];
+    lifetimes.into_iter().map(|(first, last, desc)|{
+        let free = pool.iter().zip(busy_until.iter())
+            .position(|(pooled, until)| *pooled == desc && until.map_or(true, |until| until < first));
+        let slot = free.unwrap_or_else(||{
+            pool.push(desc);
+            busy_until.push(None);
+            pool.len()-1
+        });
+        busy_until[slot] = Some(last);
+        slot
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(graph: &RenderGraph) -> Vec<&'static str> {
+        graph.order().into_iter().map(|p|graph.passes[p].name).collect()
+    }
+
+    #[test]
+    fn readers_run_after_writers() {
+        let mut graph = RenderGraph::new();
+        let extent = Extent2D{ width: 64, height: 64 };
+        let target = graph.swapchain();
+        let layer  = graph.transient(extent, vk::Format::R8G8B8A8_UNORM);
+        let blur   = graph.transient(extent, vk::Format::R8G8B8A8_UNORM);
+        graph.add_pass("composite", |p|{ p.sample(layer).sample(blur).color(target, LoadOp::Clear([0.0;4])); }, |_,_|{});
+        graph.add_pass("blur",      |p|{ p.sample(layer).color(blur, LoadOp::DontCare); }, |_,_|{});
+        graph.add_pass("text",      |p|{ p.color(layer, LoadOp::Clear([0.0;4])); }, |_,_|{});
+        assert_eq!(names(&graph), ["text", "blur", "composite"]);
+    }
+
+    #[test]
+    fn writers_keep_declaration_order() {
+        let mut graph = RenderGraph::new();
+        let target = graph.swapchain();
+        graph.add_pass("background", |p|{ p.color(target, LoadOp::Clear([0.0;4])); }, |_,_|{});
+        graph.add_pass("ui",         |p|{ p.color(target, LoadOp::Load); }, |_,_|{});
+        graph.add_pass("overlay",    |p|{ p.color(target, LoadOp::Load); }, |_,_|{});
+        assert_eq!(names(&graph), ["background", "ui", "overlay"]);
+    }
+
+    #[test]
+    fn history_is_read_before_it_is_overwritten() {
+        let mut graph = RenderGraph::new();
+        let extent = Extent2D{ width: 64, height: 64 };
+        let target  = graph.swapchain();
+        let history = graph.import(vk::Image::null(), vk::ImageView::null(), extent);
+        let layer   = graph.transient(extent, vk::Format::R8G8B8A8_UNORM);
+        graph.add_pass("composite", |p|{ p.sample(layer).sample(history).color(target, LoadOp::Load); }, |_,_|{});
+        graph.add_pass("history",   |p|{ p.sample(layer).color(history, LoadOp::DontCare); }, |_,_|{});
+        graph.add_pass("text",      |p|{ p.color(layer, LoadOp::Clear([0.0;4])); }, |_,_|{});
+        // "history" has to wait for "composite" to read last frame's contents
+        assert_eq!(names(&graph), ["text", "composite", "history"]);
+    }
+
+    #[test]
+    fn reads_see_the_last_earlier_write() {
+        let mut graph = RenderGraph::new();
+        let target = graph.swapchain();
+        let extent = Extent2D{ width: 64, height: 64 };
+        let copy   = graph.transient(extent, vk::Format::R8G8B8A8_UNORM);
+        let copy2  = graph.transient(extent, vk::Format::R8G8B8A8_UNORM);
+        graph.add_pass("background", |p|{ p.color(target, LoadOp::Clear([0.0;4])); }, |_,_|{});
+        graph.add_pass("copy",       |p|{ p.sample(target).color(copy, LoadOp::DontCare); }, |_,_|{});
+        graph.add_pass("ui",         |p|{ p.color(target, LoadOp::Load); }, |_,_|{});
+        graph.add_pass("copy2",      |p|{ p.sample(target).color(copy2, LoadOp::DontCare); }, |_,_|{});
+        let order = graph.order();
+        let step = |name| order.iter().position(|&p|graph.passes[p].name==name).unwrap();
+        assert!(step("background") < step("copy") && step("copy") < step("ui"));
+        assert!(step("ui") < step("copy2"));
+    }
+
+    #[test]
+    fn transients_alias_when_lifetimes_do_not_overlap() {
+        let mut graph = RenderGraph::new();
+        let extent = Extent2D{ width: 64, height: 64 };
+        let target = graph.swapchain();
+        let a = graph.transient(extent, vk::Format::R8G8B8A8_UNORM);
+        let b = graph.transient(extent, vk::Format::R8G8B8A8_UNORM);
+        let c = graph.transient(extent, vk::Format::R8G8B8A8_UNORM);
+        let d = graph.transient(extent, vk::Format::R16G16B16A16_SFLOAT);
+        graph.add_pass("a", |p|{ p.color(a, LoadOp::DontCare); }, |_,_|{});
+        graph.add_pass("b", |p|{ p.sample(a).color(b, LoadOp::DontCare); }, |_,_|{});
+        graph.add_pass("c", |p|{ p.sample(b).color(c, LoadOp::DontCare); }, |_,_|{});
+        graph.add_pass("d", |p|{ p.sample(c).color(d, LoadOp::DontCare); }, |_,_|{});
+        graph.add_pass("e", |p|{ p.sample(d).color(target, LoadOp::DontCare); }, |_,_|{});
+        let lifetimes = graph.lifetimes(&graph.order());
+        let steps : Vec<_> = lifetimes.iter().map(|&(first, last, idx, _)|(idx, first, last)).collect();
+        assert_eq!(steps, [(a.0, 0, 1), (b.0, 1, 2), (c.0, 2, 3), (d.0, 3, 4)]);
+
+        let lifetimes = || lifetimes.iter().map(|&(first, last, _, desc)|(first, last, desc));
+        let mut pool = Vec::new();
+        // "c" reuses the image of "a", which is dead by then, "d" has a different format
+        assert_eq!(alias_slots(lifetimes(), &mut pool), [0, 1, 0, 2]);
+        assert_eq!(pool.len(), 3);
+        // the next frame takes everything from the pool
+        assert_eq!(alias_slots(lifetimes(), &mut pool), [0, 1, 0, 2]);
+        assert_eq!(pool.len(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "cycle")]
+    fn cycles_are_rejected() {
+        let mut graph = RenderGraph::new();
+        let extent = Extent2D{ width: 64, height: 64 };
+        let a = graph.transient(extent, vk::Format::R8G8B8A8_UNORM);
+        let b = graph.transient(extent, vk::Format::R8G8B8A8_UNORM);
+        graph.add_pass("a", |p|{ p.sample(b).color(a, LoadOp::DontCare); }, |_,_|{});
+        graph.add_pass("b", |p|{ p.sample(a).color(b, LoadOp::DontCare); }, |_,_|{});
+        graph.order();
+    }
+}
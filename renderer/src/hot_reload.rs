@@ -0,0 +1,71 @@
+use ash::vk::{self, ShaderEXT};
+use crate::{Renderer, ShaderVariant, ERR_STR};
+use crate::variant::{Sources, modified};
+
+const STAGES : [vk::ShaderStageFlags;2] = [vk::ShaderStageFlags::VERTEX, vk::ShaderStageFlags::FRAGMENT];
+
+/// Watches the GLSL sources of a vertex/fragment shader pair and the files they include, and swaps in
+/// recompiled shaders when any of them changes. `poll` it between frames.
+pub struct ShaderWatcher {
+    pub vs: ShaderEXT,
+    pub fs: ShaderEXT,
+    variants: [ShaderVariant;2],
+    modified: [Sources;2],
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+    set_layouts:          Vec<vk::DescriptorSetLayout>,
+}
+
+/// The files `variant` was last compiled from by `renderer`, only its source if it was not compiled from GLSL.
+fn sources(renderer: &Renderer, variant: &ShaderVariant, stage: vk::ShaderStageFlags) -> Sources {
+    renderer.variant_cache.sources(variant, stage).cloned()
+        .unwrap_or_else(|| vec![(variant.path.clone(), modified(&variant.path))])
+}
+
+impl ShaderWatcher {
+    /// Takes over `vs` and `fs`, which `renderer` loaded from the `vs_variant` and `fs_variant` sources with the given layouts.
+    pub fn new<V:Into<ShaderVariant>>(renderer: &Renderer, vs_variant: V, fs_variant: V, vs: ShaderEXT, fs: ShaderEXT,
+            push_constant_ranges: &[vk::PushConstantRange],
+            set_layouts: &[vk::DescriptorSetLayout]) -> Self {
+        let variants = [vs_variant.into(), fs_variant.into()];
+        let modified = [0, 1].map(|i| sources(renderer, &variants[i], STAGES[i]));
+        Self{ vs, fs, variants, modified, push_constant_ranges: push_constant_ranges.to_vec(), set_layouts: set_layouts.to_vec() }
+    }
+
+    /// Recompiles the shaders if their sources or included files changed, returns whether they were replaced.
+    /// Compile errors are printed, and the previous shaders stay in use.
+    pub fn poll(&mut self, renderer: &mut Renderer) -> bool {
+        let modified = self.modified.each_ref().map(|sources| sources.iter().map(|(path, _)| (path.clone(), modified(path))).collect::<Sources>());
+        if modified == self.modified { return false }
+        // a failed compile is not retried until the files change again
+        self.modified = modified;
+        self.reload(renderer)
+    }
+
+    /// Switches to another fragment shader variant, e.g. with different defines, and recompiles the pair.
+    /// Returns whether the shaders were replaced, like `poll`.
+    pub fn set_fs_variant<V:Into<ShaderVariant>>(&mut self, renderer: &mut Renderer, fs_variant: V) -> bool {
+        self.variants[1] = fs_variant.into();
+        self.modified[1] = vec![(self.variants[1].path.clone(), modified(&self.variants[1].path))];
+        self.reload(renderer)
+    }
+
+    fn reload(&mut self, renderer: &mut Renderer) -> bool {
+        match renderer.try_load_glsl_vs_fs(self.variants[0].clone(), self.variants[1].clone(), &self.push_constant_ranges, &self.set_layouts) {
+            Err(err) => {
+                println!("{ERR_STR} shader reload failed, keeping the previous version\n{err}");
+                false
+            },
+            Ok((vs, fs)) => {
+                // includes may have been added or removed
+                self.modified = [0, 1].map(|i| sources(renderer, &self.variants[i], STAGES[i]));
+                // the last submitted frame may still be drawing with the old shaders
+                unsafe{renderer.device.device_wait_idle()}.unwrap();
+                renderer.destroy_shader(self.vs);
+                renderer.destroy_shader(self.fs);
+                (self.vs, self.fs) = (vs, fs);
+                println!("reloaded {} and {}", self.variants[0].path.display(), self.variants[1].path.display());
+                true
+            },
+        }
+    }
+}
@@ -2,18 +2,57 @@ use core::{
     mem::size_of,
     ffi,
 };
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use raw_window_handle::{RawWindowHandle, RawDisplayHandle};
 use ash::{
      ext, khr, vk::{self, CommandBuffer, CommandPool, Fence, Handle, Image, ImageView, InstanceCreateInfo, PhysicalDevice, Queue, Semaphore, ShaderEXT, SurfaceFormatKHR, SurfaceKHR, SwapchainCreateInfoKHR, SwapchainKHR, Extent2D, PhysicalDeviceMemoryProperties},
 };
 use bitflags::bitflags;
 
+mod resource;
+pub use resource::{Usage, UsageInfo, Barrier, ResourceState};
+use resource::TrackedImage;
+mod graph;
+pub use graph::{RenderGraph, GraphImage, GraphResources, PassBuilder};
+mod state;
+pub use state::CommandStats;
+use state::{StateCache, replace_faces, viewport_key, blend_equation_key};
+mod pipeline;
+mod reflect;
+#[cfg(feature="glsl")]
+mod hot_reload;
+#[cfg(feature="glsl")]
+pub use hot_reload::ShaderWatcher;
+#[cfg(feature="glsl")]
+mod variant;
+#[cfg(feature="glsl")]
+pub use variant::ShaderVariant;
+#[cfg(feature="glsl")]
+use variant::VariantCache;
+pub use reflect::{ShaderReflection, DescriptorBinding, VertexInput, VertexLayout, merge_bindings};
+use pipeline::{PipelineBackend, PipelineKey, AttachmentFormats};
+mod descriptor;
+pub use descriptor::{DescriptorAllocator, DescriptorWriter, BindlessTextures};
+mod texture;
+pub use texture::{TextureDesc, Texture, SamplerPreset, RgbaImage, mip_count, texel_size};
+mod capture;
+pub use capture::Capture;
+mod blend;
+pub use blend::BlendMode;
+mod swapchain;
+pub use swapchain::{SwapchainConfig, OutputSpace, SDR_WHITE_NITS};
+use swapchain::{swapchain_extent, display_dpi};
+use capture::PendingCapture;
+mod target;
+pub use target::{AttachmentLoad, LoadOp, ColorAttachment, DepthStencilAttachment, Resolve, RenderTarget};
+mod validation;
+use validation::ValidationMessenger;
+
 const ERR_STR : &'static str = "\x1B[41;97;1m ERROR \x1B[m";
 
 pub struct Renderer{
-    pub raw_window:  RawWindowHandle,
-    pub raw_display: RawDisplayHandle,
+    pub raw_window:  Option<RawWindowHandle>, // None for headless renderers
+    pub raw_display: Option<RawDisplayHandle>,
     pub entry:    ash::Entry,
     pub instance: ash::Instance,
     pub gpu:      PhysicalDevice,
@@ -24,10 +63,15 @@ pub struct Renderer{
     pub device:   ash::Device,
     pub queue:    Queue,
     pub fam_idx:  u32,
-    pub descriptor_pool: vk::DescriptorPool,
+    pub descriptors: DescriptorAllocator, // sets that live until freed by hand
+    frame_descriptors: DescriptorAllocator, // reset every frame
     pub surface_format:   SurfaceFormatKHR,
     pub swapchain:        SwapchainKHR,
     pub swapchain_extent: Extent2D,
+    window_extent:        Extent2D, // last size passed to `resize`
+    swapchain_config:     SwapchainConfig,
+    pub present_mode:     vk::PresentModeKHR, // chosen from `SwapchainConfig::present_mode`
+    pub swapchain_usage:  vk::ImageUsageFlags, // `SwapchainConfig::image_usage` without unsupported flags
     pub swapchain_images: Vec<Image>,
     pub swapchain_views:  Vec<ImageView>,
     pub command_pool:     CommandPool,
@@ -39,7 +83,63 @@ pub struct Renderer{
     pub khr_surface:    khr::surface::Instance,
     pub khr_swapchain:  khr::swapchain::Device,
     pub khr_dynamic_rendering: khr::dynamic_rendering::Device,
-    pub ext_shader_object: ext::shader_object::Device,
+    pub ext_shader_object: Option<ext::shader_object::Device>, // None when falling back to pipelines
+    pub khr_push_descriptor: Option<khr::push_descriptor::Device>, // None when push descriptors are emulated
+    pub features:   vk::PhysicalDeviceFeatures, // the optional core features that were enabled
+    pub extensions: OptionalExtensions,
+    pub bindless:   bool, // descriptor indexing features needed by BindlessTextures
+    pub frame_index: u64,
+    pub depth_stencil: Option<AllocatedImage>,
+    image_states:  HashMap<Image, TrackedImage>,
+    buffer_states: HashMap<vk::Buffer, ResourceState>,
+    transient_images: Vec<(AllocatedImage, u64)>, // (image, last frame_index it was used in)
+    pipeline_backend: Option<PipelineBackend>,
+    captures: Vec<PendingCapture>, // recorded into the frame in flight
+    validation: Option<ValidationMessenger>, // None if VK_EXT_debug_utils is missing
+    bindless_tables: Vec<(vk::DescriptorPool, vk::DescriptorSetLayout)>, // not yet destroyed by hand
+    #[cfg(feature="glsl")]
+    variant_cache: VariantCache,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageDesc {
+    pub width:  u32,
+    pub height: u32,
+    pub format: vk::Format,
+    pub usage:  vk::ImageUsageFlags,
+    pub aspect: vk::ImageAspectFlags,
+    pub samples: vk::SampleCountFlags,
+}
+impl ImageDesc {
+    pub const fn extent(&self) -> Extent2D { Extent2D{ width: self.width, height: self.height } }
+    const fn subresource_range(&self) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange{ aspect_mask: self.aspect, ..SUBRANGE }
+    }
+}
+
+/// Layouts derived from shader reflection, see `Renderer::create_shader_layout`.
+#[derive(Debug, Clone)]
+pub struct ShaderLayout {
+    pub push_constant_ranges: Vec<vk::PushConstantRange>,
+    pub bindings:        Vec<DescriptorBinding>,
+    pub set_layouts:     Vec<vk::DescriptorSetLayout>, // indexed by set
+    pub pipeline_layout: vk::PipelineLayout,
+    pub vertex_inputs:   Vec<VertexInput>,
+    pub push_descriptor_set: Option<u32>, // see Renderer::create_push_descriptor_layout
+}
+impl ShaderLayout {
+    /// Checks that `V` provides every input the vertex shader reads, with a matching numeric type.
+    pub fn validate_vertex_input<V: VertexLayout>(&self) -> Result<(), String> {
+        reflect::validate_vertex_input::<V>(&self.vertex_inputs)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AllocatedImage {
+    pub image:  Image,
+    pub view:   ImageView,
+    pub memory: vk::DeviceMemory,
+    pub desc:   ImageDesc,
 }
 
 const SUBRANGE : vk::ImageSubresourceRange = vk::ImageSubresourceRange{
@@ -52,10 +152,22 @@ const SUBRANGE : vk::ImageSubresourceRange = vk::ImageSubresourceRange{
 
 impl Renderer {
     // TODO: remove dependencie on winit, use raw window/display handles instead
-    fn platform_specific_init(entry: &ash::Entry, raw_window:RawWindowHandle, raw_display:RawDisplayHandle, mut extensions: Vec<&ffi::CStr>) -> (ash::Instance, SurfaceKHR) {
+    fn platform_specific_init(entry: &ash::Entry, window: Option<(RawWindowHandle, RawDisplayHandle)>, mut extensions: Vec<&ffi::CStr>) -> (ash::Instance, SurfaceKHR) {
         let layers = [c"VK_LAYER_KHRONOS_validation".as_ptr()];
-        match (raw_window, raw_display) {
-            (RawWindowHandle::Xlib(win), RawDisplayHandle::Xlib(dpy)) => {
+        match window {
+            None => {
+                extensions.push(ext::headless_surface::NAME);
+                let extensions: Vec<*const i8> = extensions.iter().map(|x| x.as_ptr()).collect();
+                let instance_info = InstanceCreateInfo::default()
+                    .enabled_layer_names(&layers)
+                    .enabled_extension_names(&extensions);
+                let instance = unsafe{entry.create_instance(&instance_info, None)}
+                .expect("could not create vulkan instance");
+                let headless_surface = ext::headless_surface::Instance::new(entry, &instance);
+                let surface = unsafe{headless_surface.create_headless_surface(&vk::HeadlessSurfaceCreateInfoEXT::default(), None)}.unwrap();
+                return (instance, surface);
+            },
+            Some((RawWindowHandle::Xlib(win), RawDisplayHandle::Xlib(dpy))) => {
                 extensions.push(khr::xlib_surface::NAME);
                 let extensions: Vec<*const i8> = extensions.iter().map(|x| x.as_ptr()).collect();
                 let instance_info = InstanceCreateInfo::default()
@@ -74,31 +186,26 @@ impl Renderer {
         }
     }
 
-    fn create_swapchain(gpu:&PhysicalDevice, device:&ash::Device, khr_swapchain: &khr::swapchain::Device, khr_surface: &khr::surface::Instance, surface: SurfaceKHR, surface_format:SurfaceFormatKHR)
-            -> (SwapchainKHR, Vec<Image>, Vec<ImageView>, Extent2D) {
-        let default_size = Extent2D{width: 1280, height:720}; // TODO: derive from Display
+    /// `window_extent` is only used if the surface leaves the size up to the swapchain.
+    fn create_swapchain(gpu:&PhysicalDevice, device:&ash::Device, khr_swapchain: &khr::swapchain::Device, khr_surface: &khr::surface::Instance, surface: SurfaceKHR, surface_format:SurfaceFormatKHR, window_extent: Extent2D, config: &SwapchainConfig)
+            -> (SwapchainKHR, Vec<Image>, Vec<ImageView>, Extent2D, vk::PresentModeKHR, vk::ImageUsageFlags) {
         let capabilities = unsafe{khr_surface.get_physical_device_surface_capabilities(*gpu, surface)}.unwrap();
-        let swapchain_extent = match capabilities.current_extent {
-            Extent2D{width:u32::MAX, height:u32::MAX} => {
-                let min = capabilities.max_image_extent;
-                let max = capabilities.max_image_extent;
-                vk::Extent2D{
-                    width:  default_size.width.clamp(min.width, max.width),
-                    height: default_size.height.clamp(min.height, max.height),
-                }
-            },
-            x => x,
-        };
+        let present_modes = unsafe{khr_surface.get_physical_device_surface_present_modes(*gpu, surface)}.unwrap();
+        let swapchain_extent = swapchain_extent(&capabilities, window_extent);
+        let present_mode = config.present_mode(&present_modes);
+        let image_usage = config.image_usage(capabilities.supported_usage_flags);
         let swapchain_info = SwapchainCreateInfoKHR::default()
             .surface(surface)
-            .min_image_count(capabilities.max_image_count.min(capabilities.min_image_count+1))
+            .min_image_count(config.image_count(&capabilities))
             .image_format(surface_format.format)
             .image_color_space(surface_format.color_space)
             .image_extent(swapchain_extent)
             .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .image_usage(image_usage)
             .pre_transform(vk::SurfaceTransformFlagsKHR::IDENTITY)
-            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE);
+            .composite_alpha(config.composite_alpha(capabilities.supported_composite_alpha))
+            .present_mode(present_mode)
+            .clipped(true);
         let swapchain = unsafe{khr_swapchain.create_swapchain(&swapchain_info, None)}.unwrap();
         let swapchain_images = unsafe{khr_swapchain.get_swapchain_images(swapchain)}.unwrap();
         let swapchain_views : Vec<_> = swapchain_images.iter().map(|img|{
@@ -116,40 +223,142 @@ impl Renderer {
             unsafe{ device.create_image_view(&info, None) }.unwrap()
         }).collect();
 
-        (swapchain, swapchain_images, swapchain_views, swapchain_extent)
+        (swapchain, swapchain_images, swapchain_views, swapchain_extent, present_mode, image_usage)
     }
-    fn destroy_swapchain(&self){
+    fn destroy_swapchain(&mut self){
         // Note: swapchain images are owned by the the swapchain, so we only have to free the views
         for view in self.swapchain_views.iter() {
             unsafe{self.device.destroy_image_view(*view, None)};
         }
+        for image in self.swapchain_images.iter() {
+            self.image_states.remove(image);
+        }
         unsafe{self.khr_swapchain.destroy_swapchain(self.swapchain, None)};
     }
+    /// Call when the window size changes, the swapchain is recreated before the next frame.
+    /// Zero sized (minimized) windows keep the current swapchain.
+    pub fn resize(&mut self, window_extent: Extent2D){
+        if window_extent.width == 0 || window_extent.height == 0 || window_extent == self.window_extent { return }
+        self.window_extent = window_extent;
+        if swapchain_extent(&self.surface_capabilities(), window_extent) != self.swapchain_extent {
+            self.recreate_swapchain();
+        }
+    }
+
+    pub fn swapchain_config(&self) -> &SwapchainConfig { &self.swapchain_config }
+
+    /// What shaders writing to the swapchain have to encode their output for.
+    pub fn output_space(&self) -> OutputSpace {
+        OutputSpace::from_color_space(self.surface_format.color_space).expect("surface formats are only chosen for output spaces")
+    }
+
+    /// Recreates the swapchain, possibly with a different `surface_format` if the color space changed.
+    /// Shaders encoding for the previous `output_space()` have to be reselected when it changes.
+    pub fn set_swapchain_config(&mut self, config: SwapchainConfig){
+        if config == self.swapchain_config { return }
+        let surface_formats = unsafe{self.khr_surface.get_physical_device_surface_formats(self.gpu, self.surface)}.unwrap();
+        self.surface_format = config.surface_format(&surface_formats).unwrap_or(self.surface_format);
+        self.swapchain_config = config;
+        self.recreate_swapchain();
+    }
+
+    fn surface_capabilities(&self) -> vk::SurfaceCapabilitiesKHR {
+        unsafe{self.khr_surface.get_physical_device_surface_capabilities(self.gpu, self.surface)}.unwrap()
+    }
+
+    /// Dots per inch of the first display reporting its physical size.
+    pub fn display_dpi(&self) -> Option<f32> {
+        let displays = unsafe{self.khr_display.get_physical_device_display_properties(self.gpu)}.ok()?;
+        displays.iter().find_map(|display| display_dpi(display.physical_resolution, display.physical_dimensions))
+            .map(|(dpi_w, dpi_h)| (dpi_w+dpi_h)/2.0)
+    }
+
+    /// Factor to scale sizes given for a 96 dpi display by, e.g. with `text_engine::Style::scaled`.
+    /// This is the first display, not necessarily the window's, so prefer the windowing system's
+    /// scale factor and fall back to this where it has none.
+    pub fn display_scale(&self) -> Option<f32> {
+        self.display_dpi().map(|dpi| dpi/96.0)
+    }
+
     fn recreate_swapchain(&mut self){
+        // the last submitted frame may still reference the swapchain and depth images
+        unsafe{self.device.device_wait_idle()}.unwrap();
         self.destroy_swapchain();
-        let (swapchain, swapchain_images, swapchain_views, swapchain_extent) = Self::create_swapchain(&self.gpu, &self.device, &self.khr_swapchain, &self.khr_surface, self.surface, self.surface_format);
+        let (swapchain, swapchain_images, swapchain_views, swapchain_extent, present_mode, swapchain_usage) = Self::create_swapchain(&self.gpu, &self.device, &self.khr_swapchain, &self.khr_surface, self.surface, self.surface_format, self.window_extent, &self.swapchain_config);
+        self.present_mode = present_mode;
+        self.swapchain_usage = swapchain_usage;
         self.swapchain = swapchain;
         self.swapchain_images = swapchain_images;
         self.swapchain_views = swapchain_views;
         self.swapchain_extent = swapchain_extent;
+        if let Some(depth_stencil) = self.depth_stencil.take() {
+            self.free_image(depth_stencil);
+            self.depth_stencil = Some(self.alloc_depth_stencil(depth_stencil.desc.format));
+        }
+    }
+
+    /// Attach a depth/stencil image to `Frame::begin_rendering`, which follows the swapchain size.
+    pub fn enable_depth_stencil(&mut self){
+        if self.depth_stencil.is_some() { return }
+        let candidates = [vk::Format::D24_UNORM_S8_UINT, vk::Format::D32_SFLOAT_S8_UINT, vk::Format::D16_UNORM_S8_UINT];
+        let format = candidates.into_iter().find(|format|{
+            let properties = unsafe{self.instance.get_physical_device_format_properties(self.gpu, *format)};
+            properties.optimal_tiling_features.contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+        }).expect("no depth/stencil format supported");
+        self.depth_stencil = Some(self.alloc_depth_stencil(format));
+    }
+    pub fn disable_depth_stencil(&mut self){
+        if let Some(depth_stencil) = self.depth_stencil.take() {
+            unsafe{self.device.device_wait_idle()}.unwrap();
+            self.free_image(depth_stencil);
+        }
+    }
+    fn alloc_depth_stencil(&mut self, format: vk::Format) -> AllocatedImage {
+        self.alloc_image(&ImageDesc{
+            width:   self.swapchain_extent.width,
+            height:  self.swapchain_extent.height,
+            format,
+            usage:   vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            aspect:  vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+            samples: vk::SampleCountFlags::TYPE_1,
+        })
+    }
+
+    pub fn new(raw_window: RawWindowHandle, raw_display: RawDisplayHandle, window_extent: Extent2D, swapchain_config: SwapchainConfig) -> Self {
+        Self::with_window(Some((raw_window, raw_display)), window_extent, swapchain_config)
     }
 
-    pub fn new(raw_window: RawWindowHandle, raw_display: RawDisplayHandle) -> Self {
+    /// A renderer presenting to VK_EXT_headless_surface, for tests and offscreen rendering.
+    pub fn new_headless(extent: Extent2D, swapchain_config: SwapchainConfig) -> Self {
+        Self::with_window(None, extent, swapchain_config)
+    }
+
+    fn with_window(window: Option<(RawWindowHandle, RawDisplayHandle)>, window_extent: Extent2D, swapchain_config: SwapchainConfig) -> Self {
         let entry = unsafe{ash::Entry::load()}.expect("could not find Vulkan");
 
-        let (instance, surface) = Self::platform_specific_init(&entry, raw_window, raw_display, vec![
+        let mut instance_extensions = vec![
             khr::surface::NAME,
             khr::display::NAME,
-            khr::get_physical_device_properties2::NAME, // required for shader_object
-        ]);
+            khr::get_physical_device_properties2::NAME, // required for shader_object and dynamic_rendering
+        ];
+        // surfaces only report color spaces other than SRGB_NONLINEAR with this
+        let available = unsafe{entry.enumerate_instance_extension_properties(None)}.unwrap();
+        if available.iter().any(|ext| ext.extension_name_as_c_str() == Ok(ext::swapchain_colorspace::NAME)) {
+            instance_extensions.push(ext::swapchain_colorspace::NAME);
+        }
+        let debug_utils = available.iter().any(|ext| ext.extension_name_as_c_str() == Ok(ext::debug_utils::NAME));
+        if debug_utils {
+            instance_extensions.push(ext::debug_utils::NAME);
+        }
+        let (instance, surface) = Self::platform_specific_init(&entry, window, instance_extensions);
+        let validation = debug_utils.then(|| ValidationMessenger::new(&entry, &instance));
         let khr_display = khr::display::Instance::new(&entry, &instance);
         let khr_surface = khr::surface::Instance::new(&entry, &instance);
 
         let required_device_extensions = [
             khr::swapchain::NAME, 
 
-            // these are all required for shader_object
-            ext::shader_object::NAME,
+            // these are all required for dynamic_rendering, and by extension shader_object
             khr::dynamic_rendering::NAME,
             khr::depth_stencil_resolve::NAME,
             khr::create_renderpass2::NAME,
@@ -174,7 +383,7 @@ impl Renderer {
             let Some(fam_idx) = queueprop.iter().enumerate().filter_map(|(fam_idx,queue)|{
                 let fam_idx = fam_idx as u32;
                 println!("{fam_idx} {queue:?}");
-                if !queue.queue_flags.contains(vk::QueueFlags::GRAPHICS|vk::QueueFlags::TRANSFER) { 
+                if !queue.queue_flags.contains(vk::QueueFlags::GRAPHICS|vk::QueueFlags::COMPUTE|vk::QueueFlags::TRANSFER) { 
                     return None
                 };
                 if !unsafe{khr_surface.get_physical_device_surface_support(*gpu, fam_idx, surface)}.unwrap_or(false) {
@@ -184,13 +393,7 @@ impl Renderer {
             }).next() else { return None };
 
             let surface_formats = unsafe{khr_surface.get_physical_device_surface_formats(*gpu, surface)}.unwrap();
-            let Some(surface_format) = surface_formats.iter().filter_map(|format| {
-                match format.format {
-                    vk::Format::B8G8R8A8_SRGB => Some(format.clone()),
-                    vk::Format::R8G8B8A8_SRGB => Some(format.clone()),
-                    _ => None
-                }
-            }).next() else { return None };
+            let Some(surface_format) = swapchain_config.surface_format(&surface_formats) else { return None };
 
             Some((*gpu, fam_idx, surface_format))
         }).next().expect("no suitable gpu's found.");
@@ -202,25 +405,84 @@ impl Renderer {
                 .queue_priorities(&queue_priorities)
         ];
 
-        let required_device_extensions = required_device_extensions.map(|x|x.as_ptr());
+        // optional extensions only unlock extra dynamic state, enable them when present
+        let available_extensions = unsafe{instance.enumerate_device_extension_properties(gpu)}.unwrap();
+        let available_extensions : HashSet::<_> = available_extensions.iter().map(|x|x.extension_name_as_c_str().unwrap()).collect();
+        let mut extensions = OptionalExtensions::empty();
+        let mut device_extensions : Vec<_> = required_device_extensions.map(|x|x.as_ptr()).into();
+        for (flag, name) in OptionalExtensions::NAMES {
+            if available_extensions.contains(name) {
+                extensions |= flag;
+                device_extensions.push(name.as_ptr());
+            }
+        }
+        println!("optional extensions: {extensions:?}");
+
+        // the same goes for the core features, but each one we enable adds state that
+        // has to be set before drawing, so only take the ones we expose
+        let supported = unsafe{instance.get_physical_device_features(gpu)};
+        let features = vk::PhysicalDeviceFeatures::default()
+            .depth_bounds(supported.depth_bounds==vk::TRUE)
+            .logic_op(supported.logic_op==vk::TRUE)
+            .wide_lines(supported.wide_lines==vk::TRUE)
+            .fill_mode_non_solid(supported.fill_mode_non_solid==vk::TRUE)
+            .tessellation_shader(supported.tessellation_shader==vk::TRUE);
+
+        let mut feature_line_rasterization = vk::PhysicalDeviceLineRasterizationFeaturesEXT::default();
+        if extensions.contains(OptionalExtensions::LINE_RASTERIZATION) {
+            let mut supported = vk::PhysicalDeviceFeatures2::default().push_next(&mut feature_line_rasterization);
+            unsafe{instance.get_physical_device_features2(gpu, &mut supported)};
+            // stippled lines need vkCmdSetLineStipple, which we don't expose
+            feature_line_rasterization = feature_line_rasterization
+                .stippled_rectangular_lines(false)
+                .stippled_bresenham_lines(false)
+                .stippled_smooth_lines(false);
+        }
 
+        let mut supported_indexing = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+        let mut supported = vk::PhysicalDeviceFeatures2::default().push_next(&mut supported_indexing);
+        unsafe{instance.get_physical_device_features2(gpu, &mut supported)};
+        let bindless = supported_indexing.descriptor_binding_partially_bound==vk::TRUE
+            && supported_indexing.descriptor_binding_sampled_image_update_after_bind==vk::TRUE
+            && supported_indexing.shader_sampled_image_array_non_uniform_indexing==vk::TRUE
+            && supported_indexing.runtime_descriptor_array==vk::TRUE;
+        println!("bindless textures: {bindless}");
         let mut feature_descriptor_indexing = vk::PhysicalDeviceDescriptorIndexingFeaturesEXT::default()
-            .descriptor_binding_storage_buffer_update_after_bind(true);
+            .descriptor_binding_storage_buffer_update_after_bind(true)
+            .descriptor_binding_partially_bound(bindless)
+            .descriptor_binding_sampled_image_update_after_bind(bindless)
+            .shader_sampled_image_array_non_uniform_indexing(bindless)
+            .runtime_descriptor_array(bindless);
         let mut feature_shader_object     = vk::PhysicalDeviceShaderObjectFeaturesEXT::default().shader_object(true);
         let mut feature_dynamic_rendering = vk::PhysicalDeviceDynamicRenderingFeatures::default().dynamic_rendering(true);
-        let default_features = vk::PhysicalDeviceFeatures::default()
-            .dual_src_blend(true);
-        let device_info = vk::DeviceCreateInfo::default()
-            .enabled_features(&default_features)
+        let enabled_features = features.dual_src_blend(true);
+        let mut device_info = vk::DeviceCreateInfo::default()
+            .enabled_features(&enabled_features)
             .queue_create_infos(&queue_infos)
-            .enabled_extension_names(&required_device_extensions)
-            .push_next(&mut feature_shader_object)
+            .enabled_extension_names(&device_extensions)
             .push_next(&mut feature_dynamic_rendering)
             .push_next(&mut feature_descriptor_indexing);
+        if extensions.contains(OptionalExtensions::SHADER_OBJECT) {
+            device_info = device_info.push_next(&mut feature_shader_object);
+        }
+        if extensions.contains(OptionalExtensions::LINE_RASTERIZATION) {
+            device_info = device_info.push_next(&mut feature_line_rasterization);
+        }
         let device = unsafe{instance.create_device(gpu, &device_info, None)}.expect("unable to create vkdevice");
         let queue = unsafe{device.get_device_queue(fam_idx, 0)};
         let khr_dynamic_rendering = khr::dynamic_rendering::Device::new(&instance, &device);
-        let ext_shader_object     = ext::shader_object::Device::new(&instance, &device);
+        let ext_shader_object = extensions.contains(OptionalExtensions::SHADER_OBJECT)
+            .then(|| ext::shader_object::Device::new(&instance, &device));
+        let khr_push_descriptor = extensions.contains(OptionalExtensions::PUSH_DESCRIPTOR)
+            .then(|| khr::push_descriptor::Device::new(&instance, &device));
+        let pipeline_backend = match ext_shader_object {
+            Some(_) => None,
+            None => {
+                println!("VK_EXT_shader_object is not supported, falling back to pipelines");
+                let properties = unsafe{instance.get_physical_device_properties(gpu)};
+                Some(PipelineBackend::new(&device, &properties))
+            },
+        };
         let khr_swapchain = khr::swapchain::Device::new(&instance, &device);
         println!("device ready");
 
@@ -263,11 +525,12 @@ impl Renderer {
             } 
             println!("{select:>8}{i:>2}:{heap} {size} {location:<4} {flags:?}", heap=memtype.heap_index, size=fmt_size(heap.size), location=if device_local {"gpu"} else {"host"}, flags=memtype.property_flags );
         }
-        let Some(gpu_memory_idx) = gpu_memory_idx else {panic!("no device memory")};
+        // unified memory devices (integrated and software gpus) only have host visible device memory
+        let Some(gpu_memory_idx) = gpu_memory_idx.or(bar_memory_idx) else {panic!("no device memory")};
         println!("gpu: {gpu_memory_idx:?}");
         println!("bar: {bar_memory_idx:?}");
 
-        let (swapchain, swapchain_images, swapchain_views, swapchain_extent) = Self::create_swapchain(&gpu, &device, &khr_swapchain, &khr_surface, surface, surface_format);
+        let (swapchain, swapchain_images, swapchain_views, swapchain_extent, present_mode, swapchain_usage) = Self::create_swapchain(&gpu, &device, &khr_swapchain, &khr_surface, surface, surface_format, window_extent, &swapchain_config);
         println!("swapchain created");
 
         let command_pool_info = vk::CommandPoolCreateInfo::default()
@@ -288,18 +551,21 @@ impl Renderer {
         let fence_info = vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED);
         let ready_to_record = unsafe{device.create_fence(&fence_info, None)}.unwrap();
 
-        let descriptor_pool_sizes = [
-            vk::DescriptorPoolSize::default().ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER).descriptor_count(1),
-        ];
-        let descriptor_pool_info = vk::DescriptorPoolCreateInfo::default()
-            .pool_sizes(&descriptor_pool_sizes)
-            //.flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND_EXT)
-            .max_sets(1);
-        let descriptor_pool = unsafe{device.create_descriptor_pool(&descriptor_pool_info, None)}.unwrap();
+        let descriptors       = DescriptorAllocator::default();
+        let frame_descriptors = DescriptorAllocator::default();
+
 
 
+        let frame_index   = 0;
+        let depth_stencil = None;
+        let image_states  = HashMap::new();
+        let buffer_states = HashMap::new();
+        let transient_images = Vec::new();
 
-        Self{ raw_window, raw_display, entry, instance, gpu, memory_properties, bar_memory_idx, gpu_memory_idx, surface, device, queue, fam_idx, descriptor_pool, surface_format, swapchain, swapchain_extent, swapchain_images, swapchain_views, command_pool, command_buffer, ready_to_submit, ready_to_present, ready_to_record, khr_display, khr_surface,  khr_swapchain, khr_dynamic_rendering, ext_shader_object }
+        let (raw_window, raw_display) = window.unzip();
+        Self{ raw_window, raw_display, entry, instance, gpu, memory_properties, bar_memory_idx, gpu_memory_idx, surface, device, queue, fam_idx, descriptors, frame_descriptors, surface_format, swapchain, swapchain_extent, window_extent, swapchain_config, present_mode, swapchain_usage, swapchain_images, swapchain_views, command_pool, command_buffer, ready_to_submit, ready_to_present, ready_to_record, khr_display, khr_surface,  khr_swapchain, khr_dynamic_rendering, ext_shader_object, khr_push_descriptor, features, extensions, bindless, frame_index, depth_stencil, image_states, buffer_states, transient_images, pipeline_backend, captures: Vec::new(), validation, bindless_tables: Vec::new(),
+            #[cfg(feature="glsl")]
+            variant_cache: VariantCache::default() }
     }
 
 
@@ -327,71 +593,204 @@ impl Renderer {
 
 
     pub fn new_sampler_nearest(&self) -> vk::Sampler {
-        let sampler_info = vk::SamplerCreateInfo::default()
-            .mag_filter(vk::Filter::NEAREST)
-            .min_filter(vk::Filter::NEAREST)
-            .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
-            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
-            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
-            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_BORDER)
-            .anisotropy_enable(false)
-            .unnormalized_coordinates(true)
-            .compare_enable(false)
-            .compare_op(vk::CompareOp::ALWAYS)
-            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
-            .mip_lod_bias(0.0)
-            .min_lod(0.0)
-            .max_lod(0.0);
-        unsafe{self.device.create_sampler(&sampler_info, None)}.unwrap()
-    }
-
-    pub fn transition_image(&self, cmd: vk::CommandBuffer, image: vk::Image, from:vk::ImageLayout, to:vk::ImageLayout){
-        let barrier = [vk::ImageMemoryBarrier::default()
-            .image(image)
-            .old_layout(from)
-            .new_layout(to)
-            .src_access_mask(vk::AccessFlags::NONE)
-            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
-            .subresource_range(SUBRANGE)
-            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-        ];
-        unsafe{self.device.cmd_pipeline_barrier(cmd,
-            vk::PipelineStageFlags::TOP_OF_PIPE,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::DependencyFlags::BY_REGION,
-            &[], &[], &barrier)};
+        self.create_sampler(SamplerPreset::NearestUnnormalized)
     }
 
-    pub fn alloc_image_and_view(&self, width:u32, height:u32, format:vk::Format) -> (vk::Image,vk::ImageView) {
+    pub fn create_sampler(&self, preset: SamplerPreset) -> vk::Sampler {
+        unsafe{self.device.create_sampler(&preset.info(), None)}.unwrap()
+    }
+
+    /// Start tracking the state of an image. Untracked images are assumed to be color images in UNDEFINED layout.
+    pub fn track_image(&mut self, image: vk::Image, range: vk::ImageSubresourceRange, format: vk::Format, usage: vk::ImageUsageFlags){
+        self.image_states.insert(image, TrackedImage{ state: ResourceState::UNDEFINED, range, format, usage });
+    }
+    pub fn untrack_image(&mut self, image: vk::Image){
+        self.image_states.remove(&image);
+    }
+    pub fn image_state(&self, image: vk::Image) -> Option<ResourceState> {
+        self.image_states.get(&image).map(|tracked|tracked.state)
+    }
+
+    /// Records the barrier (if any) needed before `image` can be used as `usage`.
+    pub fn transition_image(&mut self, cmd: vk::CommandBuffer, image: vk::Image, usage: Usage){
+        let tracked = self.image_states.entry(image).or_insert(TrackedImage{ state: ResourceState::UNDEFINED, range: SUBRANGE, format: vk::Format::UNDEFINED, usage: vk::ImageUsageFlags::empty() });
+        if let Some(barrier) = tracked.state.transition(usage) {
+            resource::cmd_image_barrier(&self.device, cmd, image, tracked.range, barrier);
+        }
+    }
+
+    /// Like `transition_image`, but the current contents of `image` may be thrown away.
+    pub fn transition_image_discard(&mut self, cmd: vk::CommandBuffer, image: vk::Image, usage: Usage){
+        if let Some(tracked) = self.image_states.get_mut(&image) {
+            tracked.state.discard();
+        }
+        self.transition_image(cmd, image, usage);
+    }
+
+    /// Records the barrier (if any) needed before `buffer` can be used as `usage`.
+    pub fn transition_buffer(&mut self, cmd: vk::CommandBuffer, buffer: vk::Buffer, usage: Usage){
+        let state = self.buffer_states.entry(buffer).or_default();
+        if let Some(barrier) = state.transition(usage) {
+            resource::cmd_buffer_barrier(&self.device, cmd, buffer, barrier);
+        }
+    }
+
+    pub fn alloc_image_and_view(&mut self, width:u32, height:u32, format:vk::Format) -> (vk::Image,vk::ImageView) {
+        let desc = ImageDesc{ width, height, format,
+            usage:  vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            aspect: vk::ImageAspectFlags::COLOR,
+            samples: vk::SampleCountFlags::TYPE_1 };
+        let AllocatedImage{image, view, ..} = self.alloc_image(&desc);
+        (image,view)
+    }
+
+    pub fn alloc_image(&mut self, desc: &ImageDesc) -> AllocatedImage {
         let img_info = vk::ImageCreateInfo::default()
             .image_type(vk::ImageType::TYPE_2D)
-            .extent(vk::Extent3D{width, height, depth:1})
+            .extent(vk::Extent3D{width:desc.width, height:desc.height, depth:1})
             .mip_levels(1)
             .array_layers(1)
-            .format(format)
+            .format(desc.format)
             .tiling(vk::ImageTiling::OPTIMAL)
             .initial_layout(vk::ImageLayout::UNDEFINED)
-            .usage(vk::ImageUsageFlags::TRANSFER_DST
-                  |vk::ImageUsageFlags::SAMPLED)
+            .usage(desc.usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .samples(vk::SampleCountFlags::TYPE_1);
+            .samples(desc.samples);
         let image = unsafe{self.device.create_image(&img_info, None)}.unwrap();
         let req = unsafe{self.device.get_image_memory_requirements(image)};
         let alloc = vk::MemoryAllocateInfo::default()
             .allocation_size(req.size)
             .memory_type_index(self.gpu_memory_idx);
-        let mem = unsafe{self.device.allocate_memory(&alloc, None)}.unwrap();
-        unsafe{self.device.bind_image_memory(image, mem, 0)}.unwrap();
+        let memory = unsafe{self.device.allocate_memory(&alloc, None)}.unwrap();
+        unsafe{self.device.bind_image_memory(image, memory, 0)}.unwrap();
 
         let view_info = vk::ImageViewCreateInfo::default()
             .image(image)
             .view_type(vk::ImageViewType::TYPE_2D)
-            .format(format)
-            .subresource_range(SUBRANGE);
+            .format(desc.format)
+            .subresource_range(desc.subresource_range());
         let view = unsafe{self.device.create_image_view(&view_info, None)}.unwrap();
+        self.track_image(image, desc.subresource_range(), desc.format, desc.usage);
 
-        (image,view)
+        AllocatedImage{ image, view, memory, desc: *desc }
+    }
+
+    /// The image must no longer be in use by the gpu.
+    pub fn free_image(&mut self, image: AllocatedImage){
+        self.untrack_image(image.image);
+        unsafe{self.device.destroy_image_view(image.view, None)};
+        unsafe{self.device.destroy_image(image.image, None)};
+        unsafe{self.device.free_memory(image.memory, None)};
+    }
+
+    /// An empty texture, its contents are undefined.
+    pub fn create_texture(&mut self, desc: &TextureDesc) -> Texture {
+        let img_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .extent(vk::Extent3D{width:desc.width, height:desc.height, depth:1})
+            .mip_levels(desc.mip_levels)
+            .array_layers(desc.array_layers)
+            .format(desc.format)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .usage(desc.usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .samples(vk::SampleCountFlags::TYPE_1);
+        let image = unsafe{self.device.create_image(&img_info, None)}.unwrap();
+        let req = unsafe{self.device.get_image_memory_requirements(image)};
+        let alloc = vk::MemoryAllocateInfo::default()
+            .allocation_size(req.size)
+            .memory_type_index(self.gpu_memory_idx);
+        let memory = unsafe{self.device.allocate_memory(&alloc, None)}.unwrap();
+        unsafe{self.device.bind_image_memory(image, memory, 0)}.unwrap();
+
+        let view_info = vk::ImageViewCreateInfo::default()
+            .image(image)
+            .view_type(desc.view_type())
+            .format(desc.format)
+            .subresource_range(desc.subresource_range());
+        let view = unsafe{self.device.create_image_view(&view_info, None)}.unwrap();
+        self.track_image(image, desc.subresource_range(), desc.format, desc.usage);
+        Texture{ image, view, memory, desc: *desc }
+    }
+
+    /// Uploads `data` into mip 0 of every layer, rows and layers tightly packed, and generates
+    /// the other mips. Blocks until the upload finished, the texture is left in `Usage::SampledFragment`.
+    pub fn create_texture_with_data(&mut self, desc: &TextureDesc, data: &[u8]) -> Texture {
+        let Some(texel_size) = texel_size(desc.format) else {
+            panic!("\n{ERR_STR} can not upload texture data in {:?}\n", desc.format)
+        };
+        let size = desc.width as u64*desc.height as u64*desc.array_layers as u64*texel_size as u64;
+        assert_eq!(data.len() as u64, size, "texture data does not match a {}x{}x{} {:?} texture", desc.width, desc.height, desc.array_layers, desc.format);
+        let filter = if desc.mip_levels <= 1 { vk::Filter::NEAREST } else {
+            let properties = unsafe{self.instance.get_physical_device_format_properties(self.gpu, desc.format)};
+            let blit = vk::FormatFeatureFlags::BLIT_SRC | vk::FormatFeatureFlags::BLIT_DST;
+            if !properties.optimal_tiling_features.contains(blit) {
+                panic!("\n{ERR_STR} mips of {:?} can not be generated by blitting\n", desc.format)
+            }
+            if properties.optimal_tiling_features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR) { vk::Filter::LINEAR } else { vk::Filter::NEAREST }
+        };
+
+        let texture = self.create_texture(desc);
+        let (staging, staging_memory) = self.alloc_buffer(size, vk::BufferUsageFlags::TRANSFER_SRC, self.host_memory_idx());
+        let ptr = unsafe{self.device.map_memory(staging_memory, 0, size, vk::MemoryMapFlags::empty())}.expect("memory map failed");
+        unsafe{core::ptr::copy_nonoverlapping(data.as_ptr(), ptr.cast::<u8>(), data.len())};
+        unsafe{self.device.unmap_memory(staging_memory)};
+
+        let cmd = self.begin_oneshot_cmd();
+        self.transition_image(cmd, texture.image, Usage::TransferDst);
+        let region = [vk::BufferImageCopy{
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers{
+                aspect_mask: vk::ImageAspectFlags::COLOR, mip_level: 0, base_array_layer: 0, layer_count: desc.array_layers },
+            image_offset: vk::Offset3D::default(),
+            image_extent: vk::Extent3D{ width: desc.width, height: desc.height, depth: 1 },
+        }];
+        unsafe{self.device.cmd_copy_buffer_to_image(cmd, staging, texture.image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &region)};
+        texture::cmd_generate_mips(&self.device, cmd, texture.image, desc, filter);
+        self.transition_image(cmd, texture.image, Usage::SampledFragment);
+        self.end_oneshot_cmd(cmd);
+
+        unsafe{self.device.destroy_buffer(staging, None)};
+        unsafe{self.device.free_memory(staging_memory, None)};
+        texture
+    }
+
+    /// An sRGB texture of a decoded image, with mips if `mips` is set.
+    pub fn create_texture_rgba(&mut self, image: &RgbaImage, mips: bool) -> Texture {
+        let desc = TextureDesc::rgba8_srgb(image.width, image.height);
+        let desc = if mips { desc.with_mips() } else { desc };
+        self.create_texture_with_data(&desc, &image.pixels)
+    }
+
+    /// The texture must no longer be in use by the gpu.
+    pub fn free_texture(&mut self, texture: Texture){
+        self.untrack_image(texture.image);
+        unsafe{self.device.destroy_image_view(texture.view, None)};
+        unsafe{self.device.destroy_image(texture.image, None)};
+        unsafe{self.device.free_memory(texture.memory, None)};
+    }
+
+    /// Waits for the frame in flight and delivers its captures. Call between frames.
+    pub fn finish_captures(&mut self){
+        unsafe{self.device.wait_for_fences(&[self.ready_to_record], true, u64::MAX)}.unwrap();
+        self.complete_captures();
+    }
+
+    // the frame that recorded the captures must have finished
+    fn complete_captures(&mut self){
+        for capture in self.captures.drain(..) {
+            capture.complete(&self.device);
+        }
+    }
+
+    fn host_memory_idx(&self) -> u32 {
+        let flags = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        self.memory_properties.memory_types_as_slice().iter()
+            .position(|memtype| memtype.property_flags.contains(flags))
+            .expect("no host visible memory") as u32
     }
 
     pub fn alloc_buffer(&self, size:u64,usage: vk::BufferUsageFlags, mem_idx:u32) -> (vk::Buffer,vk::DeviceMemory) {
@@ -422,41 +821,158 @@ impl Renderer {
         Some((buffer,ptr))
     }
 
+    /// Compiles `variant` for `stage`, reusing the SPIR-V if its source did not change since the last call.
+    #[cfg(feature="glsl")]
+    pub fn compile_glsl(&mut self, variant: &ShaderVariant, stage: vk::ShaderStageFlags) -> Result<Vec<u8>, String> {
+        self.variant_cache.compile(variant, stage).map(<[u8]>::to_vec)
+    }
+
+    /// `vs` and `fs` are paths or `ShaderVariant`s.
     #[cfg(feature="glsl")]
-    pub fn load_glsl_vs_fs<P:?Sized+AsRef<std::path::Path>> (&self,
-            vs_glsl_path: &P, 
-            fs_glsl_path: &P, 
+    pub fn load_glsl_vs_fs<V:Into<ShaderVariant>> (&mut self,
+            vs: V,
+            fs: V,
             push_constant_ranges : &[vk::PushConstantRange],
             descriptor_set_layout : &[vk::DescriptorSetLayout]) -> (ShaderEXT,ShaderEXT) {
-        use shaderc;
-        let compiler = shaderc::Compiler::new().unwrap();
-        let options  = shaderc::CompileOptions::new().unwrap();
-
-        let vert_src = std::fs::read_to_string(vs_glsl_path).expect("could not read vertex shader");
-        let vert = compiler.compile_into_spirv(
-            &vert_src, 
-            shaderc::ShaderKind::Vertex,
-            &vs_glsl_path.as_ref().to_string_lossy(),
-            "main",
-            Some(&options)
-        ).expect("vert shader failed to compile");
-
-        let frag_src = std::fs::read_to_string(fs_glsl_path).expect("could not read fragment shader");
-        let frag = compiler.compile_into_spirv(
-            &frag_src, 
-            shaderc::ShaderKind::Fragment,
-            &fs_glsl_path.as_ref().to_string_lossy(),
-            "main",
-            Some(&options)
-        ).expect("vert shader failed to compile");
-        self.load_spirv_vs_fs(vert.as_binary_u8(), frag.as_binary_u8(), push_constant_ranges, descriptor_set_layout)
-    }
-
-    pub fn load_spirv_vs_fs (&self, 
+        self.try_load_glsl_vs_fs(vs, fs, push_constant_ranges, descriptor_set_layout)
+            .unwrap_or_else(|err| panic!("\n{ERR_STR} {err}\n"))
+    }
+
+    /// Like `load_glsl_vs_fs`, but returns compile errors instead of panicking.
+    #[cfg(feature="glsl")]
+    pub fn try_load_glsl_vs_fs<V:Into<ShaderVariant>> (&mut self,
+            vs: V,
+            fs: V,
+            push_constant_ranges : &[vk::PushConstantRange],
+            descriptor_set_layout : &[vk::DescriptorSetLayout]) -> Result<(ShaderEXT,ShaderEXT), String> {
+        let vert = self.compile_glsl(&vs.into(), vk::ShaderStageFlags::VERTEX)?;
+        let frag = self.compile_glsl(&fs.into(), vk::ShaderStageFlags::FRAGMENT)?;
+        self.try_load_spirv_vs_fs(&vert, &frag, push_constant_ranges, descriptor_set_layout)
+    }
+
+    /// Like `load_glsl_vs_fs`, but derives the layouts from the shaders.
+    #[cfg(feature="glsl")]
+    pub fn load_glsl_vs_fs_reflected<V:Into<ShaderVariant>> (&mut self, vs: V, fs: V) -> (ShaderEXT,ShaderEXT,ShaderLayout) {
+        let mut compile = |variant: V, stage| self.compile_glsl(&variant.into(), stage).unwrap_or_else(|err| panic!("\n{ERR_STR} {err}\n"));
+        let vert = compile(vs, vk::ShaderStageFlags::VERTEX);
+        let frag = compile(fs, vk::ShaderStageFlags::FRAGMENT);
+        self.load_spirv_vs_fs_reflected(&vert, &frag)
+    }
+
+    /// `cs` is a path or a `ShaderVariant`.
+    #[cfg(feature="glsl")]
+    pub fn load_glsl_cs<V:Into<ShaderVariant>> (&mut self,
+            cs: V,
+            push_constant_ranges : &[vk::PushConstantRange],
+            descriptor_set_layout : &[vk::DescriptorSetLayout]) -> ShaderEXT {
+        self.try_load_glsl_cs(cs, push_constant_ranges, descriptor_set_layout)
+            .unwrap_or_else(|err| panic!("\n{ERR_STR} {err}\n"))
+    }
+
+    /// Like `load_glsl_cs`, but returns compile errors instead of panicking.
+    #[cfg(feature="glsl")]
+    pub fn try_load_glsl_cs<V:Into<ShaderVariant>> (&mut self,
+            cs: V,
+            push_constant_ranges : &[vk::PushConstantRange],
+            descriptor_set_layout : &[vk::DescriptorSetLayout]) -> Result<ShaderEXT, String> {
+        let comp = self.compile_glsl(&cs.into(), vk::ShaderStageFlags::COMPUTE)?;
+        self.try_load_spirv_cs(&comp, push_constant_ranges, descriptor_set_layout)
+    }
+
+    /// Like `load_glsl_cs`, but derives the layouts from the shader.
+    #[cfg(feature="glsl")]
+    pub fn load_glsl_cs_reflected<V:Into<ShaderVariant>> (&mut self, cs: V) -> (ShaderEXT,ShaderLayout) {
+        let comp = self.compile_glsl(&cs.into(), vk::ShaderStageFlags::COMPUTE).unwrap_or_else(|err| panic!("\n{ERR_STR} {err}\n"));
+        self.load_spirv_cs_reflected(&comp)
+    }
+
+    /// Like `load_spirv_vs_fs`, but derives the layouts from the shaders.
+    pub fn load_spirv_vs_fs_reflected (&mut self, vs_spv : &[u8], fs_spv : &[u8]) -> (ShaderEXT,ShaderEXT,ShaderLayout) {
+        let reflect = |spv, stage| ShaderReflection::new(spv)
+            .unwrap_or_else(|err| panic!("\n{ERR_STR} could not reflect {stage} shader\n{err}\n"));
+        let layout = self.create_shader_layout(&[&reflect(vs_spv, "vertex"), &reflect(fs_spv, "fragment")]);
+        let (vs, fs) = self.load_spirv_vs_fs(vs_spv, fs_spv, &layout.push_constant_ranges, &layout.set_layouts);
+        (vs, fs, layout)
+    }
+
+    /// Like `load_spirv_cs`, but derives the layouts from the shader.
+    pub fn load_spirv_cs_reflected (&mut self, cs_spv : &[u8]) -> (ShaderEXT,ShaderLayout) {
+        let reflection = ShaderReflection::new(cs_spv)
+            .unwrap_or_else(|err| panic!("\n{ERR_STR} could not reflect compute shader\n{err}\n"));
+        let layout = self.create_shader_layout(&[&reflection]);
+        let cs = self.load_spirv_cs(cs_spv, &layout.push_constant_ranges, &layout.set_layouts);
+        (cs, layout)
+    }
+
+    /// Creates the descriptor set layouts and pipeline layout the given stages declare.
+    pub fn create_shader_layout(&self, stages: &[&ShaderReflection]) -> ShaderLayout {
+        self.create_shader_layout_with(stages, None)
+    }
+
+    /// Like `create_shader_layout`, but set `push_set` is written with `Frame::push_descriptor_set`
+    /// instead of being allocated. Without VK_KHR_push_descriptor, the frame allocates a set per push.
+    pub fn create_push_descriptor_layout(&self, stages: &[&ShaderReflection], push_set: u32) -> ShaderLayout {
+        self.create_shader_layout_with(stages, Some(push_set))
+    }
+
+    /// Destroys the descriptor set layouts and the pipeline layout. Shaders loaded with them stay valid,
+    /// but a frame that is still executing must not use the layout anymore.
+    pub fn destroy_shader_layout(&self, layout: ShaderLayout){
+        unsafe{self.device.destroy_pipeline_layout(layout.pipeline_layout, None)};
+        for set_layout in layout.set_layouts {
+            unsafe{self.device.destroy_descriptor_set_layout(set_layout, None)};
+        }
+    }
+
+    fn create_shader_layout_with(&self, stages: &[&ShaderReflection], push_set: Option<u32>) -> ShaderLayout {
+        let bindings = merge_bindings(stages).unwrap_or_else(|err| panic!("\n{ERR_STR} shader stages disagree\n{err}\n"));
+        let set_count = bindings.iter().map(|binding| binding.set+1).max().unwrap_or(0);
+        let set_layouts : Vec<_> = (0..set_count).map(|set| {
+            let set_bindings : Vec<_> = bindings.iter().filter(|binding| binding.set == set).map(|binding| {
+                if binding.count == 0 {
+                    panic!("\n{ERR_STR} set {set} binding {} is an unsized array, create its layout by hand\n", binding.binding)
+                }
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(binding.binding)
+                    .descriptor_type(binding.ty)
+                    .descriptor_count(binding.count)
+                    .stage_flags(binding.stages)
+            }).collect();
+            let mut set_layout_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&set_bindings);
+            if push_set == Some(set) && self.khr_push_descriptor.is_some() {
+                set_layout_info = set_layout_info.flags(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR);
+            }
+            unsafe{self.device.create_descriptor_set_layout(&set_layout_info, None)}.unwrap()
+        }).collect();
+        let push_constant_ranges : Vec<_> = stages.iter().filter_map(|stage| stage.push_constants).collect();
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        let pipeline_layout = unsafe{self.device.create_pipeline_layout(&pipeline_layout_info, None)}.unwrap();
+        let vertex_inputs = stages.iter()
+            .find(|stage| stage.stage == vk::ShaderStageFlags::VERTEX)
+            .map_or(Vec::new(), |stage| stage.inputs.clone());
+        ShaderLayout{ push_constant_ranges, bindings, set_layouts, pipeline_layout, vertex_inputs, push_descriptor_set: push_set }
+    }
+
+    pub fn load_spirv_vs_fs (&mut self, 
             vs_spv : &[u8],
             fs_spv : &[u8],
             push_constant_ranges : &[vk::PushConstantRange],
             descriptor_set_layout : &[vk::DescriptorSetLayout]) -> (ShaderEXT,ShaderEXT) {
+        self.try_load_spirv_vs_fs(vs_spv, fs_spv, push_constant_ranges, descriptor_set_layout)
+            .unwrap_or_else(|err| panic!("\n{ERR_STR} {err}\n"))
+    }
+
+    /// Like `load_spirv_vs_fs`, but returns errors instead of panicking.
+    pub fn try_load_spirv_vs_fs (&mut self, 
+            vs_spv : &[u8],
+            fs_spv : &[u8],
+            push_constant_ranges : &[vk::PushConstantRange],
+            descriptor_set_layout : &[vk::DescriptorSetLayout]) -> Result<(ShaderEXT,ShaderEXT), String> {
+        let Some(ext_shader_object) = &self.ext_shader_object else {
+            return self.load_spirv_vs_fs_fallback(vs_spv, fs_spv, push_constant_ranges, descriptor_set_layout);
+        };
 
         let shader_infos = [
             vk::ShaderCreateInfoEXT::default()
@@ -478,22 +994,127 @@ impl Renderer {
                 .push_constant_ranges(&push_constant_ranges)
                 .set_layouts(&descriptor_set_layout),
         ];
-        match unsafe{ self.ext_shader_object.create_shaders(&shader_infos, None) } {
-            Ok(ret) => (ret[0], ret[1]),
+        match unsafe{ ext_shader_object.create_shaders(&shader_infos, None) } {
+            Ok(ret) => Ok((ret[0], ret[1])),
             Err((ret,err)) => {
+                for shader in ret.iter().filter(|shader| !shader.is_null()) {
+                    unsafe{ext_shader_object.destroy_shader(*shader, None)};
+                }
                 if ret[0].is_null() {
-                    panic!("\n{ERR_STR} vertex shader failed to compile\n{err}\n")
+                    Err(format!("vertex shader failed to compile\n{err}"))
                 }else if ret[1].is_null() {
-                    panic!("\n{ERR_STR} fragment shader failed to compile\n{err}\n")
+                    Err(format!("fragment shader failed to compile\n{err}"))
                 }else {
-                    panic!("\n{ERR_STR} shader compilation failed\n{err}\n")
+                    Err(format!("shader compilation failed\n{err}"))
                 }
             }
         }
     }
 
+    pub fn load_spirv_cs (&mut self,
+            cs_spv : &[u8],
+            push_constant_ranges : &[vk::PushConstantRange],
+            descriptor_set_layout : &[vk::DescriptorSetLayout]) -> ShaderEXT {
+        self.try_load_spirv_cs(cs_spv, push_constant_ranges, descriptor_set_layout)
+            .unwrap_or_else(|err| panic!("\n{ERR_STR} {err}\n"))
+    }
+
+    /// Like `load_spirv_cs`, but returns errors instead of panicking.
+    pub fn try_load_spirv_cs (&mut self,
+            cs_spv : &[u8],
+            push_constant_ranges : &[vk::PushConstantRange],
+            descriptor_set_layout : &[vk::DescriptorSetLayout]) -> Result<ShaderEXT, String> {
+        let Some(ext_shader_object) = &self.ext_shader_object else {
+            // compute pipelines have no other state, so the fallback builds them right away
+            let layout_info = vk::PipelineLayoutCreateInfo::default()
+                .set_layouts(&descriptor_set_layout)
+                .push_constant_ranges(&push_constant_ranges);
+            let layout = unsafe{self.device.create_pipeline_layout(&layout_info, None)}.unwrap();
+            let backend = self.pipeline_backend.as_mut().expect("pipeline backend without shader objects");
+            return backend.create_shader(&self.device, vk::ShaderStageFlags::COMPUTE, cs_spv, layout)
+                .map_err(|err| { unsafe{self.device.destroy_pipeline_layout(layout, None)}; format!("compute shader failed to load\n{err}") });
+        };
+        let shader_info = [
+            vk::ShaderCreateInfoEXT::default()
+                .stage(vk::ShaderStageFlags::COMPUTE)
+                .code_type(vk::ShaderCodeTypeEXT::SPIRV)
+                .code(cs_spv)
+                .name(c"main")
+                .push_constant_ranges(&push_constant_ranges)
+                .set_layouts(&descriptor_set_layout),
+        ];
+        match unsafe{ ext_shader_object.create_shaders(&shader_info, None) } {
+            Ok(ret) => Ok(ret[0]),
+            Err((_,err)) => Err(format!("compute shader failed to compile\n{err}")),
+        }
+    }
+
+    /// Points a storage buffer binding of `set` at `buffer`.
+    pub fn write_storage_buffer(&self, set: vk::DescriptorSet, binding: u32, buffer: vk::Buffer, offset: u64, range: u64){
+        DescriptorWriter::new(set).storage_buffer(binding, buffer, offset, range).update(&self.device);
+    }
+
+    /// Points a storage image binding of `set` at `view`, which has to be used in `GENERAL` layout,
+    /// see `Usage::ComputeImageRead` and `Usage::ComputeImageWrite`.
+    pub fn write_storage_image(&self, set: vk::DescriptorSet, binding: u32, view: vk::ImageView){
+        DescriptorWriter::new(set).storage_image(binding, view).update(&self.device);
+    }
+
+    /// Allocates a set that stays valid across frames, see `Frame::allocate_descriptor_set` for per-frame sets.
+    pub fn allocate_descriptor_set(&mut self, layout: vk::DescriptorSetLayout) -> vk::DescriptorSet {
+        self.descriptors.allocate(&self.device, layout)
+    }
+
+    /// A bindless table with room for `capacity` textures, visible to `stages`.
+    /// It is destroyed by `destroy_bindless_textures`, or together with the renderer.
+    pub fn create_bindless_textures(&mut self, capacity: u32, stages: vk::ShaderStageFlags) -> BindlessTextures {
+        assert!(self.bindless, "\n{ERR_STR} the gpu does not support the descriptor indexing features bindless textures need\n");
+        let textures = BindlessTextures::new(&self.device, capacity, stages);
+        self.bindless_tables.push((textures.pool, textures.set_layout));
+        textures
+    }
+
+    /// The table must no longer be in use by the gpu.
+    pub fn destroy_bindless_textures(&mut self, mut textures: BindlessTextures){
+        self.bindless_tables.retain(|&(pool,_)| pool != textures.pool);
+        textures.destroy(&self.device);
+    }
+
+    // without shader objects, the shaders are turned into pipelines when drawing
+    fn load_spirv_vs_fs_fallback (&mut self,
+            vs_spv : &[u8],
+            fs_spv : &[u8],
+            push_constant_ranges : &[vk::PushConstantRange],
+            descriptor_set_layout : &[vk::DescriptorSetLayout]) -> Result<(ShaderEXT,ShaderEXT), String> {
+        let layout_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&descriptor_set_layout)
+            .push_constant_ranges(&push_constant_ranges);
+        let layout = unsafe{self.device.create_pipeline_layout(&layout_info, None)}.unwrap();
+        let backend = self.pipeline_backend.as_mut().expect("pipeline backend without shader objects");
+        let vs = backend.create_shader(&self.device, vk::ShaderStageFlags::VERTEX, vs_spv, layout)
+            .map_err(|err| { unsafe{self.device.destroy_pipeline_layout(layout, None)}; format!("vertex shader failed to load\n{err}") })?;
+        let fs = backend.create_shader(&self.device, vk::ShaderStageFlags::FRAGMENT, fs_spv, layout)
+            .map_err(|err| { backend.destroy_shader(&self.device, vs); format!("fragment shader failed to load\n{err}") })?;
+        Ok((vs, fs))
+    }
+
+    /// The shader must not be in use by a frame that is still executing.
+    pub fn destroy_shader(&mut self, shader: ShaderEXT){
+        match (&self.ext_shader_object, self.pipeline_backend.as_mut()) {
+            (Some(ext_shader_object), _) => unsafe{ext_shader_object.destroy_shader(shader, None)},
+            (None, Some(backend)) => backend.destroy_shader(&self.device, shader),
+            (None, None) => unreachable!(),
+        }
+    }
+
     pub fn wait_and_begin_frame(&mut self) -> Frame { Frame::new(self) }
 
+    /// Number of warnings and errors the validation layers reported so far,
+    /// None if they can't be counted without VK_EXT_debug_utils.
+    pub fn validation_messages(&self) -> Option<u32> {
+        self.validation.as_ref().map(ValidationMessenger::count)
+    }
+
     pub fn debug_print(&self){
         let properties = unsafe{self.instance.get_physical_device_properties(self.gpu)};
         let name = properties.device_name_as_c_str().unwrap().to_str().unwrap();
@@ -504,8 +1125,7 @@ impl Renderer {
             let name = unsafe{display_properties.display_name_as_c_str()}.unwrap().to_str().unwrap();
             let mm = display_properties.physical_dimensions;
             let px = display_properties.physical_resolution;
-            let dpi_w = 25.4*px.width  as f32/mm.width  as f32;
-            let dpi_h = 25.4*px.height as f32/mm.height as f32;
+            let (dpi_w, dpi_h) = display_dpi(px, mm).unwrap_or_default();
             println!("-> {:>4}x{:>4}px  {:3>}x{:3>}mm  {dpi_w:>3.0}x{dpi_h:>3.0}dpi  {name}", 
                 px.width, px.height, mm.width, mm.height);
         }
@@ -513,8 +1133,41 @@ impl Renderer {
 }
 
 impl Drop for Renderer {
+    /// Destroys everything the renderer created. Textures, buffers and shaders handed out
+    /// to the user have to be freed before.
     fn drop(&mut self){
-        println!("todo! implement drop for renderer");
+        unsafe{self.device.device_wait_idle()}.unwrap();
+        // every submitted frame finished, so pending captures can be delivered
+        self.complete_captures();
+        for (image, _) in core::mem::take(&mut self.transient_images) {
+            self.free_image(image);
+        }
+        if let Some(depth_stencil) = self.depth_stencil.take() {
+            self.free_image(depth_stencil);
+        }
+        for (pool, set_layout) in self.bindless_tables.drain(..) {
+            unsafe{self.device.destroy_descriptor_pool(pool, None)};
+            unsafe{self.device.destroy_descriptor_set_layout(set_layout, None)};
+        }
+        self.descriptors.destroy(&self.device);
+        self.frame_descriptors.destroy(&self.device);
+        if let Some(mut backend) = self.pipeline_backend.take() {
+            backend.destroy(&self.device);
+        }
+        self.destroy_swapchain();
+        unsafe{
+            self.device.destroy_semaphore(self.ready_to_submit, None);
+            self.device.destroy_semaphore(self.ready_to_present, None);
+            self.device.destroy_fence(self.ready_to_record, None);
+            // frees the command buffers allocated from it
+            self.device.destroy_command_pool(self.command_pool, None);
+            self.device.destroy_device(None);
+            self.khr_surface.destroy_surface(self.surface, None);
+        }
+        if let Some(validation) = &self.validation {
+            validation.destroy();
+        }
+        unsafe{self.instance.destroy_instance(None)};
     }
 }
 
@@ -522,7 +1175,36 @@ impl Drop for Renderer {
 pub struct Frame<'a>{
     renderer : &'a mut Renderer,
     swap_idx : u32,
+    render_area : vk::Rect2D,
+    render_samples : vk::SampleCountFlags,
+    color_attachment_count : u32,
+    color_write_masks : Vec<vk::ColorComponentFlags>,
+    has_stencil : bool,
+    clip_depth : u32,
+    bound_stages : vk::ShaderStageFlags,
+    conditions : StateConditions,
     dynamic_state_flags : DynamicStateFlags,
+    defaults_applied : Option<(DynamicStateFlags, vk::ShaderStageFlags, StateConditions, u32)>,
+    cache : StateCache,
+    stats : CommandStats,
+    attachment_formats : AttachmentFormats,
+    color_target : Option<(vk::Image, vk::Rect2D)>, // what capture reads back
+    rendering : bool, // between begin_rendering and end_rendering
+}
+
+/// The parts of the dynamic state that decide which other state is needed for a draw.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct StateConditions {
+    rasterizer_discard: bool,
+    depth_test:         bool,
+    depth_bounds_test:  bool,
+    depth_bias:         bool,
+    stencil_test:       bool,
+    logic_op:           bool,
+    line_topology:      bool,
+    line_polygon_mode:  bool,
+    blend_constants:    bool,
+    overestimate:       bool,
 }
 impl<'a> Frame<'a> {
 
@@ -534,6 +1216,10 @@ impl<'a> Frame<'a> {
         //  - ready_to_present: signaled by vkQueueSubmit, awaited by vkQueuePresentKHR
         unsafe{renderer.device.wait_for_fences(&[renderer.ready_to_record], true, u64::MAX)}.unwrap();
         unsafe{renderer.device.reset_fences(&[renderer.ready_to_record])}.unwrap();
+        renderer.frame_index += 1;
+        renderer.complete_captures();
+        // with one frame in flight, the sets of the previous frame are done once the fence signaled
+        renderer.frame_descriptors.reset(&renderer.device);
 
         let swap_idx = loop{
             match unsafe{renderer.khr_swapchain.acquire_next_image(renderer.swapchain, u64::MAX, renderer.ready_to_submit, Fence::null())} {
@@ -557,210 +1243,564 @@ impl<'a> Frame<'a> {
         let begin_info = vk::CommandBufferBeginInfo::default();
         unsafe{renderer.device.begin_command_buffer(renderer.command_buffer, &begin_info)}.unwrap();
 
-        // transition swapchain image from present-optimal to render-optimal
-        let range = vk::ImageSubresourceRange::default()
-            .aspect_mask(vk::ImageAspectFlags::COLOR)
-            .base_mip_level(0)
-            .level_count(1)
-            .base_array_layer(0)
-            .layer_count(1);
-        let image_memory_barriers = [
-            vk::ImageMemoryBarrier::default()
-                .image(renderer.swapchain_images[swap_idx as usize])
-                .old_layout(vk::ImageLayout::UNDEFINED)
-                .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
-                .subresource_range(range),
-        ];
-        unsafe{renderer.device.cmd_pipeline_barrier(renderer.command_buffer,
-            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, // (changed from TOP_OF_PIPE)
-            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-            vk::DependencyFlags::empty(),
-            &[], &[], &image_memory_barriers)};
-
+        // the acquired image has undefined contents, and may only be touched after the
+        // ready_to_submit semaphore wait, which happens at COLOR_ATTACHMENT_OUTPUT
+        renderer.image_states.insert(renderer.swapchain_images[swap_idx as usize], TrackedImage{
+            state: ResourceState::after_semaphore(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT),
+            range: SUBRANGE,
+            format: renderer.surface_format.format,
+            usage: renderer.swapchain_usage,
+        });
+
+        let render_area = renderer.swapchain_extent.into();
+        let render_samples = vk::SampleCountFlags::TYPE_1;
+        let color_attachment_count = 1;
+        let color_write_masks = Vec::new();
+        let has_stencil = false;
+        let clip_depth = 0;
+        let bound_stages = vk::ShaderStageFlags::empty();
+        let conditions = StateConditions::default();
         let dynamic_state_flags = DynamicStateFlags::empty();
-        Self{ renderer, swap_idx, dynamic_state_flags}
+        let defaults_applied = None;
+        let cache = StateCache::default();
+        let stats = CommandStats::default();
+        let attachment_formats = AttachmentFormats::default();
+        let color_target = None;
+        let rendering = false;
+        Self{ renderer, swap_idx, render_area, render_samples, color_attachment_count, color_write_masks, has_stencil, clip_depth, bound_stages, conditions, dynamic_state_flags, defaults_applied, cache, stats, attachment_formats, color_target, rendering}
     }
 
-    pub fn buffer_to_image(&self, buffer: vk::Buffer, image: vk::Image, regions: &[vk::BufferImageCopy]){
-        if regions.len() == 0 { return; }
-        let to_write_optimal = [vk::ImageMemoryBarrier::default()
-            .image(image)
-            .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-            .src_access_mask(vk::AccessFlags::NONE)
-            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
-            .subresource_range(SUBRANGE)
-            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-        ];
-        unsafe{self.renderer.device.cmd_pipeline_barrier(
-            self.renderer.command_buffer,
-            vk::PipelineStageFlags::TOP_OF_PIPE,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::DependencyFlags::BY_REGION,
-            &[], &[], &to_write_optimal)};
+    pub fn renderer(&mut self) -> &mut Renderer { self.renderer }
+    pub fn swapchain_image(&self) -> Image { self.renderer.swapchain_images[self.swap_idx as usize] }
+    pub fn swapchain_view(&self)  -> ImageView { self.renderer.swapchain_views[self.swap_idx as usize] }
+    pub fn swapchain_extent(&self) -> Extent2D { self.renderer.swapchain_extent }
+    /// state commands recorded vs. filtered out as redundant so far this frame
+    pub fn command_stats(&self) -> CommandStats { self.stats }
+    /// The renderer managed depth/stencil image, cleared to depth 1.0 and stencil 0
+    pub fn swapchain_depth_stencil(&self) -> Option<DepthStencilAttachment> {
+        self.renderer.depth_stencil.map(|image| DepthStencilAttachment{
+            image:   image.image,
+            view:    image.view,
+            depth:   Some(AttachmentLoad::Clear(1.0)),
+            stencil: Some(AttachmentLoad::Clear(0)),
+            store:   false,
+        })
+    }
 
+    /// Declare the next usage of an image, inserting a barrier if required.
+    /// Must be called outside of begin_rendering/end_rendering.
+    pub fn use_image(&mut self, image: vk::Image, usage: Usage){
+        self.renderer.transition_image(self.renderer.command_buffer, image, usage);
+    }
+    /// Like `use_image`, but the current contents of the image may be thrown away.
+    pub fn use_image_discard(&mut self, image: vk::Image, usage: Usage){
+        self.renderer.transition_image_discard(self.renderer.command_buffer, image, usage);
+    }
+    pub fn use_buffer(&mut self, buffer: vk::Buffer, usage: Usage){
+        self.renderer.transition_buffer(self.renderer.command_buffer, buffer, usage);
+    }
+
+    /// Leaves `image` in `Usage::TransferDst`, declare the next usage with `use_image`.
+    pub fn buffer_to_image(&mut self, buffer: vk::Buffer, image: vk::Image, regions: &[vk::BufferImageCopy]){
+        if regions.len() == 0 { return; }
+        self.use_buffer(buffer, Usage::TransferSrc);
+        self.use_image(image, Usage::TransferDst);
         unsafe{self.renderer.device.cmd_copy_buffer_to_image(self.renderer.command_buffer,
             buffer,
             image,
             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
             regions)};
+    }
 
-        let to_display_optimal = [vk::ImageMemoryBarrier::default()
-            .image(image)
-            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .src_access_mask(vk::AccessFlags::NONE)
-            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
-            .subresource_range(SUBRANGE)
-            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-        ];
-        unsafe{self.renderer.device.cmd_pipeline_barrier(
-            self.renderer.command_buffer,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::PipelineStageFlags::FRAGMENT_SHADER,
-            vk::DependencyFlags::BY_REGION,
-            &[], &[], &to_display_optimal)};
-    }
-
-
-    pub fn begin_rendering(&self, color: [f32;4]) {
-        // begin rendering
-        let mut clear_color_value = vk::ClearColorValue::default();
-        clear_color_value.float32 = color; 
-        let mut clear_color = vk::ClearValue::default();
-        clear_color.color = clear_color_value;
-        let color_attachments = [
-            vk::RenderingAttachmentInfo::default()
-                .image_view(self.renderer.swapchain_views[self.swap_idx as usize])
-                .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                .resolve_image_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-                .load_op(vk::AttachmentLoadOp::CLEAR)
-                .store_op(vk::AttachmentStoreOp::STORE)
-                .clear_value(clear_color),
-        ];
-        let rendering_info = vk::RenderingInfo::default()
-            .render_area(self.renderer.swapchain_extent.into())
+
+    /// Clears the swapchain image to `color` and starts rendering into it,
+    /// together with the depth/stencil image if it is enabled.
+    pub fn begin_rendering(&mut self, color: [f32;4]) {
+        let colors = [ColorAttachment::new(self.swapchain_image(), self.swapchain_view(), LoadOp::Clear(color))];
+        let target = RenderTarget{
+            depth_stencil: self.swapchain_depth_stencil(),
+            ..RenderTarget::new(self.swapchain_extent(), &colors)
+        };
+        self.begin_rendering_with(&target);
+    }
+
+    /// Transitions all attachments and starts rendering into them.
+    /// Viewport, scissor and sample state that was not set since the last begin_rendering
+    /// is derived from the render target.
+    pub fn begin_rendering_with(&mut self, target: &RenderTarget) {
+        for attachment in target.colors {
+            if attachment.load.discards() {
+                self.use_image_discard(attachment.image, Usage::ColorAttachment);
+            } else {
+                self.use_image(attachment.image, Usage::ColorAttachment);
+            }
+            if let Some(resolve) = attachment.resolve {
+                self.use_image_discard(resolve.image, Usage::ColorAttachment);
+            }
+        }
+        if let Some(attachment) = target.depth_stencil {
+            let discards = attachment.depth.map_or(true, |load|load.discards())
+                        && attachment.stencil.map_or(true, |load|load.discards());
+            if discards {
+                self.use_image_discard(attachment.image, Usage::DepthStencilAttachment);
+            } else {
+                self.use_image(attachment.image, Usage::DepthStencilAttachment);
+            }
+        }
+
+        let color_attachments : Vec<_> = target.colors.iter().map(target::color_info).collect();
+        let depth_attachment   = target.depth_stencil.as_ref().and_then(target::depth_info);
+        let stencil_attachment = target.depth_stencil.as_ref().and_then(target::stencil_info);
+        let mut rendering_info = vk::RenderingInfo::default()
+            .render_area(target.area)
             .layer_count(1)
             .color_attachments(&color_attachments);
+        if let Some(depth) = depth_attachment.as_ref() {
+            rendering_info = rendering_info.depth_attachment(depth);
+        }
+        if let Some(stencil) = stencil_attachment.as_ref() {
+            rendering_info = rendering_info.stencil_attachment(stencil);
+        }
         unsafe{self.renderer.khr_dynamic_rendering.cmd_begin_rendering(self.renderer.command_buffer, &rendering_info)};
+        self.rendering = true;
+
+        // this state persists between passes, re-derive defaults from the new target
+        self.render_area = target.area;
+        self.render_samples = target.samples;
+        self.color_attachment_count = target.colors.len() as u32;
+        self.has_stencil = target.depth_stencil.is_some_and(|attachment|attachment.stencil.is_some());
+        // multisampled targets are captured from their resolve image
+        self.color_target = target.colors.first()
+            .map(|attachment| (attachment.resolve.map_or(attachment.image, |resolve| resolve.image), target.area));
+        let format = |image| self.renderer.image_states.get(&image).map_or(vk::Format::UNDEFINED, |tracked|tracked.format);
+        let depth_stencil_format = target.depth_stencil.map_or(vk::Format::UNDEFINED, |attachment| format(attachment.image));
+        self.attachment_formats = AttachmentFormats{
+            colors:  target.colors.iter().map(|attachment| format(attachment.image)).collect(),
+            depth:   if target.depth_stencil.is_some_and(|attachment|attachment.depth.is_some())   { depth_stencil_format } else { vk::Format::UNDEFINED },
+            stencil: if target.depth_stencil.is_some_and(|attachment|attachment.stencil.is_some()) { depth_stencil_format } else { vk::Format::UNDEFINED },
+        };
+        self.clip_depth = 0;
+        self.color_write_masks.clear();
+        self.dynamic_state_flags -= DynamicStateFlags::VIEWPORTS | DynamicStateFlags::SCISSORS
+                                  | DynamicStateFlags::RASTERIZATION_SAMPLES | DynamicStateFlags::SAMPLE_MASK
+                                  | DynamicStateFlags::COLOR_BLEND_ENABLE | DynamicStateFlags::COLOR_BLEND_EQUATION
+                                  | DynamicStateFlags::COLOR_WRITE_MASK;
+    }
+
+    /// Restricts drawing to the intersection of all pushed clip shapes, using the stencil attachment.
+    /// `shape` draws the clip geometry with the bound shaders, only its rasterized coverage counts.
+    /// The clip stack is reset by begin_rendering.
+    pub fn push_clip(&mut self, shape: impl FnOnce(&mut Self)){
+        assert!(self.has_stencil, "clipping requires a stencil attachment");
+        self.draw_clip_shape(shape, vk::StencilOp::INCREMENT_AND_CLAMP);
+        self.clip_depth += 1;
+        self.apply_clip();
+    }
+    /// Undoes the matching push_clip, `shape` must draw the same geometry.
+    pub fn pop_clip(&mut self, shape: impl FnOnce(&mut Self)){
+        assert!(self.clip_depth > 0, "pop_clip without matching push_clip");
+        self.draw_clip_shape(shape, vk::StencilOp::DECREMENT_AND_CLAMP);
+        self.clip_depth -= 1;
+        self.apply_clip();
+    }
+    pub fn clip_depth(&self) -> u32 { self.clip_depth }
+
+    fn draw_clip_shape(&mut self, shape: impl FnOnce(&mut Self), op: vk::StencilOp){
+        let face = vk::StencilFaceFlags::FRONT_AND_BACK;
+        let write_masks = self.color_write_masks.clone();
+        self.set_color_write_mask(&vec![vk::ColorComponentFlags::empty(); self.color_attachment_count as usize]);
+        self.set_stencil_test_enable(true);
+        self.set_stencil_compare_mask(face, 0xFF);
+        self.set_stencil_write_mask(face, 0xFF);
+        self.set_stencil_reference(face, self.clip_depth);
+        self.set_stencil_op(face, vk::StencilOp::KEEP, op, vk::StencilOp::KEEP, vk::CompareOp::EQUAL);
+        shape(self);
+        if write_masks.is_empty() {
+            self.dynamic_state_flags -= DynamicStateFlags::COLOR_WRITE_MASK;
+        } else {
+            self.set_color_write_mask(&write_masks);
+        }
+    }
+    fn apply_clip(&mut self){
+        if self.clip_depth == 0 {
+            self.set_stencil_test_enable(false);
+            return;
+        }
+        let face = vk::StencilFaceFlags::FRONT_AND_BACK;
+        self.set_stencil_reference(face, self.clip_depth);
+        self.set_stencil_op(face, vk::StencilOp::KEEP, vk::StencilOp::KEEP, vk::StencilOp::KEEP, vk::CompareOp::EQUAL);
     }
 
     pub fn set_viewports(&mut self, viewports : &[vk::Viewport]){
         self.dynamic_state_flags |= DynamicStateFlags::VIEWPORTS;
-        unsafe{self.renderer.ext_shader_object.cmd_set_viewport_with_count(self.renderer.command_buffer, &viewports)};
+        if !self.stats.count(self.cache.viewports.replace(viewports.iter().map(viewport_key).collect())) { return }
+        match &self.renderer.ext_shader_object {
+            Some(ext) => unsafe{ext.cmd_set_viewport_with_count(self.renderer.command_buffer, &viewports)},
+            None => unsafe{self.renderer.device.cmd_set_viewport(self.renderer.command_buffer, 0, &viewports)},
+        }
     }
     pub fn set_scissors(&mut self, scissors : &[vk::Rect2D]){
         self.dynamic_state_flags |= DynamicStateFlags::SCISSORS;
-        unsafe{self.renderer.ext_shader_object.cmd_set_scissor_with_count(self.renderer.command_buffer, &scissors)};
+        if !self.stats.count(self.cache.scissors.replace(scissors.to_vec())) { return }
+        match &self.renderer.ext_shader_object {
+            Some(ext) => unsafe{ext.cmd_set_scissor_with_count(self.renderer.command_buffer, &scissors)},
+            None => unsafe{self.renderer.device.cmd_set_scissor(self.renderer.command_buffer, 0, &scissors)},
+        }
     }
     pub fn set_polygon_mode(&mut self, mode : vk::PolygonMode){
+        self.conditions.line_polygon_mode = mode==vk::PolygonMode::LINE;
         self.dynamic_state_flags |= DynamicStateFlags::POLYGON_MODE;
-        unsafe{self.renderer.ext_shader_object.cmd_set_polygon_mode(self.renderer.command_buffer, mode)};
+        if !self.stats.count(self.cache.polygon_mode.replace(mode)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_polygon_mode(self.renderer.command_buffer, mode)} }
     }
     pub fn set_primitive_topology(&mut self, topology : vk::PrimitiveTopology){
+        self.conditions.line_topology = matches!(topology,
+            vk::PrimitiveTopology::LINE_LIST | vk::PrimitiveTopology::LINE_STRIP |
+            vk::PrimitiveTopology::LINE_LIST_WITH_ADJACENCY | vk::PrimitiveTopology::LINE_STRIP_WITH_ADJACENCY);
         self.dynamic_state_flags |= DynamicStateFlags::PRIMITIVE_TOPOLOGY;
-        unsafe{self.renderer.ext_shader_object.cmd_set_primitive_topology(self.renderer.command_buffer, topology)};
+        if !self.stats.count(self.cache.primitive_topology.replace(topology)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_primitive_topology(self.renderer.command_buffer, topology)} }
     }
     pub fn set_primitive_restart_enable(&mut self, enabled: bool){
         self.dynamic_state_flags |= DynamicStateFlags::PRIMITIVE_RESTART_ENABLE;
-        unsafe{self.renderer.ext_shader_object.cmd_set_primitive_restart_enable(self.renderer.command_buffer, enabled)};
+        if !self.stats.count(self.cache.primitive_restart_enable.replace(enabled)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_primitive_restart_enable(self.renderer.command_buffer, enabled)} }
     }
     pub fn set_depth_test_enable(&mut self, enabled: bool){
+        self.conditions.depth_test = enabled;
         self.dynamic_state_flags |= DynamicStateFlags::DEPTH_TEST_ENABLE;
-        unsafe{self.renderer.ext_shader_object.cmd_set_depth_test_enable(self.renderer.command_buffer, enabled)};
+        if !self.stats.count(self.cache.depth_test_enable.replace(enabled)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_depth_test_enable(self.renderer.command_buffer, enabled)} }
     }
     pub fn set_depth_write_enable(&mut self, enabled: bool){
         self.dynamic_state_flags |= DynamicStateFlags::DEPTH_WRITE_ENABLE;
-        unsafe{self.renderer.ext_shader_object.cmd_set_depth_write_enable(self.renderer.command_buffer, enabled)};
+        if !self.stats.count(self.cache.depth_write_enable.replace(enabled)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_depth_write_enable(self.renderer.command_buffer, enabled)} }
     }
     pub fn set_depth_bias_enable(&mut self, enabled: bool){
+        self.conditions.depth_bias = enabled;
         self.dynamic_state_flags |= DynamicStateFlags::DEPTH_BIAS_ENABLE;
-        unsafe{self.renderer.ext_shader_object.cmd_set_depth_bias_enable(self.renderer.command_buffer, enabled)};
+        if !self.stats.count(self.cache.depth_bias_enable.replace(enabled)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_depth_bias_enable(self.renderer.command_buffer, enabled)} }
     }
     pub fn set_stencil_test_enable(&mut self, enabled: bool){
+        self.conditions.stencil_test = enabled;
         self.dynamic_state_flags |= DynamicStateFlags::STENCIL_TEST_ENABLE;
-        unsafe{self.renderer.ext_shader_object.cmd_set_stencil_test_enable(self.renderer.command_buffer, enabled)};
+        if !self.stats.count(self.cache.stencil_test_enable.replace(enabled)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_stencil_test_enable(self.renderer.command_buffer, enabled)} }
     }
     pub fn set_rasterizer_discard_enable(&mut self, enabled: bool){
+        self.conditions.rasterizer_discard = enabled;
         self.dynamic_state_flags |= DynamicStateFlags::RASTERIZER_DISCARD_ENABLE;
-        unsafe{self.renderer.ext_shader_object.cmd_set_rasterizer_discard_enable(self.renderer.command_buffer, enabled)};
+        if !self.stats.count(self.cache.rasterizer_discard_enable.replace(enabled)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_rasterizer_discard_enable(self.renderer.command_buffer, enabled)} }
     }
     pub fn set_rasterization_samples(&mut self, sample_count_flags: vk::SampleCountFlags){
         self.dynamic_state_flags |= DynamicStateFlags::RASTERIZATION_SAMPLES;
-        unsafe{self.renderer.ext_shader_object.cmd_set_rasterization_samples(self.renderer.command_buffer, sample_count_flags)};
+        if !self.stats.count(self.cache.rasterization_samples.replace(sample_count_flags)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_rasterization_samples(self.renderer.command_buffer, sample_count_flags)} }
     }
     pub fn set_sample_mask(&mut self, samples: vk::SampleCountFlags, sample_mask: &[vk::SampleMask]){
         self.dynamic_state_flags |= DynamicStateFlags::SAMPLE_MASK;
-        unsafe{self.renderer.ext_shader_object.cmd_set_sample_mask(self.renderer.command_buffer, samples, sample_mask)};
+        if !self.stats.count(self.cache.sample_mask.replace((samples, sample_mask.to_vec()))) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_sample_mask(self.renderer.command_buffer, samples, sample_mask)} }
     }
     pub fn set_alpha_to_coverage_enable(&mut self, enable: bool){
         self.dynamic_state_flags |= DynamicStateFlags::ALPHA_TO_COVERAGE_ENABLE ;
-        unsafe{self.renderer.ext_shader_object.cmd_set_alpha_to_coverage_enable(self.renderer.command_buffer, enable)};
+        if !self.stats.count(self.cache.alpha_to_coverage_enable.replace(enable)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_alpha_to_coverage_enable(self.renderer.command_buffer, enable)} }
     }
     pub fn set_cull_mode(&mut self, cullmode: vk::CullModeFlags){
         self.dynamic_state_flags |= DynamicStateFlags::SET_CULL_MODE;
-        unsafe{self.renderer.ext_shader_object.cmd_set_cull_mode(self.renderer.command_buffer, cullmode)};
+        if !self.stats.count(self.cache.cull_mode.replace(cullmode)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_cull_mode(self.renderer.command_buffer, cullmode)} }
     }
     pub fn set_color_blend_enable(&mut self, enables: &[u32]){
         self.dynamic_state_flags |= DynamicStateFlags::COLOR_BLEND_ENABLE;
-        unsafe{self.renderer.ext_shader_object.cmd_set_color_blend_enable(self.renderer.command_buffer, 0, &enables)};
+        if !self.stats.count(self.cache.color_blend_enable.replace(enables.to_vec())) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_color_blend_enable(self.renderer.command_buffer, 0, &enables)} }
     }
     pub fn set_color_blend_equation(&mut self, equations: &[vk::ColorBlendEquationEXT]){
+        self.conditions.blend_constants = equations.iter().any(|eq| [eq.src_color_blend_factor, eq.dst_color_blend_factor, eq.src_alpha_blend_factor, eq.dst_alpha_blend_factor]
+            .iter().any(|factor| matches!(*factor,
+                vk::BlendFactor::CONSTANT_COLOR | vk::BlendFactor::ONE_MINUS_CONSTANT_COLOR |
+                vk::BlendFactor::CONSTANT_ALPHA | vk::BlendFactor::ONE_MINUS_CONSTANT_ALPHA)));
         self.dynamic_state_flags |= DynamicStateFlags::COLOR_BLEND_EQUATION;
-        unsafe{self.renderer.ext_shader_object.cmd_set_color_blend_equation(self.renderer.command_buffer, 0, &equations)};
+        if !self.stats.count(self.cache.color_blend_equation.replace(equations.iter().map(blend_equation_key).collect())) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_color_blend_equation(self.renderer.command_buffer, 0, &equations)} }
+    }
+    /// Sets blending for every color attachment of the current rendering.
+    pub fn set_blend_mode(&mut self, mode: BlendMode){
+        let count = self.color_attachment_count as usize;
+        match mode.equation() {
+            Some(equation) => {
+                self.set_color_blend_enable(&vec![vk::TRUE; count]);
+                self.set_color_blend_equation(&vec![equation; count]);
+            },
+            None => self.set_color_blend_enable(&vec![vk::FALSE; count]),
+        }
     }
     pub fn set_color_write_mask(&mut self, write_masks: &[vk::ColorComponentFlags]){
         self.dynamic_state_flags |= DynamicStateFlags::COLOR_WRITE_MASK;
-        unsafe{self.renderer.ext_shader_object.cmd_set_color_write_mask(self.renderer.command_buffer, 0, &write_masks)};
+        self.color_write_masks = write_masks.to_vec();
+        if !self.stats.count(self.cache.color_write_mask.replace(write_masks.to_vec())) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_color_write_mask(self.renderer.command_buffer, 0, &write_masks)} }
+    }
+    pub fn set_stencil_op(&mut self, faces: vk::StencilFaceFlags, fail: vk::StencilOp, pass: vk::StencilOp, depth_fail: vk::StencilOp, compare: vk::CompareOp){
+        self.dynamic_state_flags |= DynamicStateFlags::STENCIL_OP;
+        if !self.stats.count(replace_faces(&mut self.cache.stencil_op, faces, (fail, pass, depth_fail, compare))) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_stencil_op(self.renderer.command_buffer, faces, fail, pass, depth_fail, compare)} }
+    }
+    pub fn set_stencil_compare_mask(&mut self, faces: vk::StencilFaceFlags, mask: u32){
+        self.dynamic_state_flags |= DynamicStateFlags::STENCIL_COMPARE_MASK;
+        if !self.stats.count(replace_faces(&mut self.cache.stencil_compare_mask, faces, mask)) { return }
+        unsafe{self.renderer.device.cmd_set_stencil_compare_mask(self.renderer.command_buffer, faces, mask)};
+    }
+    pub fn set_stencil_write_mask(&mut self, faces: vk::StencilFaceFlags, mask: u32){
+        self.dynamic_state_flags |= DynamicStateFlags::STENCIL_WRITE_MASK;
+        if !self.stats.count(replace_faces(&mut self.cache.stencil_write_mask, faces, mask)) { return }
+        unsafe{self.renderer.device.cmd_set_stencil_write_mask(self.renderer.command_buffer, faces, mask)};
+    }
+    pub fn set_stencil_reference(&mut self, faces: vk::StencilFaceFlags, reference: u32){
+        self.dynamic_state_flags |= DynamicStateFlags::STENCIL_REFERENCE;
+        if !self.stats.count(replace_faces(&mut self.cache.stencil_reference, faces, reference)) { return }
+        unsafe{self.renderer.device.cmd_set_stencil_reference(self.renderer.command_buffer, faces, reference)};
     }
 
-    // vulkan requires us to set these things before rendering
+    pub fn set_front_face(&mut self, front_face: vk::FrontFace){
+        self.dynamic_state_flags |= DynamicStateFlags::FRONT_FACE;
+        if !self.stats.count(self.cache.front_face.replace(front_face)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_front_face(self.renderer.command_buffer, front_face)} }
+    }
+    pub fn set_depth_compare_op(&mut self, compare: vk::CompareOp){
+        self.dynamic_state_flags |= DynamicStateFlags::DEPTH_COMPARE_OP;
+        if !self.stats.count(self.cache.depth_compare_op.replace(compare)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_depth_compare_op(self.renderer.command_buffer, compare)} }
+    }
+    /// requires `Renderer::features.depth_bounds`
+    pub fn set_depth_bounds_test_enable(&mut self, enabled: bool){
+        self.dynamic_state_flags |= DynamicStateFlags::DEPTH_BOUNDS_TEST_ENABLE;
+        self.conditions.depth_bounds_test = enabled;
+        if !self.stats.count(self.cache.depth_bounds_test_enable.replace(enabled)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_depth_bounds_test_enable(self.renderer.command_buffer, enabled)} }
+    }
+    pub fn set_depth_bounds(&mut self, min: f32, max: f32){
+        self.dynamic_state_flags |= DynamicStateFlags::DEPTH_BOUNDS;
+        if !self.stats.count(self.cache.depth_bounds.replace([min, max])) { return }
+        unsafe{self.renderer.device.cmd_set_depth_bounds(self.renderer.command_buffer, min, max)};
+    }
+    pub fn set_depth_bias(&mut self, constant_factor: f32, clamp: f32, slope_factor: f32){
+        self.dynamic_state_flags |= DynamicStateFlags::DEPTH_BIAS;
+        if !self.stats.count(self.cache.depth_bias.replace([constant_factor, clamp, slope_factor])) { return }
+        unsafe{self.renderer.device.cmd_set_depth_bias(self.renderer.command_buffer, constant_factor, clamp, slope_factor)};
+    }
+    pub fn set_blend_constants(&mut self, constants: [f32;4]){
+        self.dynamic_state_flags |= DynamicStateFlags::BLEND_CONSTANTS;
+        if !self.stats.count(self.cache.blend_constants.replace(constants)) { return }
+        unsafe{self.renderer.device.cmd_set_blend_constants(self.renderer.command_buffer, &constants)};
+    }
+    /// requires `Renderer::features.logic_op`
+    pub fn set_logic_op_enable(&mut self, enabled: bool){
+        self.dynamic_state_flags |= DynamicStateFlags::LOGIC_OP_ENABLE;
+        self.conditions.logic_op = enabled;
+        if !self.stats.count(self.cache.logic_op_enable.replace(enabled)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_logic_op_enable(self.renderer.command_buffer, enabled)} }
+    }
+    pub fn set_logic_op(&mut self, op: vk::LogicOp){
+        self.dynamic_state_flags |= DynamicStateFlags::LOGIC_OP;
+        if !self.stats.count(self.cache.logic_op.replace(op)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_logic_op(self.renderer.command_buffer, op)} }
+    }
+    /// widths other than 1.0 require `Renderer::features.wide_lines`
+    pub fn set_line_width(&mut self, width: f32){
+        self.dynamic_state_flags |= DynamicStateFlags::LINE_WIDTH;
+        if !self.stats.count(self.cache.line_width.replace(width)) { return }
+        unsafe{self.renderer.device.cmd_set_line_width(self.renderer.command_buffer, width)};
+    }
+    /// requires `OptionalExtensions::LINE_RASTERIZATION`
+    pub fn set_line_rasterization_mode(&mut self, mode: vk::LineRasterizationModeEXT){
+        self.dynamic_state_flags |= DynamicStateFlags::LINE_RASTERIZATION_MODE;
+        if !self.stats.count(self.cache.line_rasterization_mode.replace(mode)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_line_rasterization_mode(self.renderer.command_buffer, mode)} }
+    }
+    /// requires `OptionalExtensions::LINE_RASTERIZATION`
+    pub fn set_line_stipple_enable(&mut self, enabled: bool){
+        self.dynamic_state_flags |= DynamicStateFlags::LINE_STIPPLE_ENABLE;
+        if !self.stats.count(self.cache.line_stipple_enable.replace(enabled)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_line_stipple_enable(self.renderer.command_buffer, enabled)} }
+    }
+    /// requires `OptionalExtensions::CONSERVATIVE_RASTERIZATION`
+    pub fn set_conservative_rasterization_mode(&mut self, mode: vk::ConservativeRasterizationModeEXT){
+        self.dynamic_state_flags |= DynamicStateFlags::CONSERVATIVE_RASTERIZATION_MODE;
+        self.conditions.overestimate = mode==vk::ConservativeRasterizationModeEXT::OVERESTIMATE;
+        if !self.stats.count(self.cache.conservative_rasterization_mode.replace(mode)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_conservative_rasterization_mode(self.renderer.command_buffer, mode)} }
+    }
+    pub fn set_extra_primitive_overestimation_size(&mut self, size: f32){
+        self.dynamic_state_flags |= DynamicStateFlags::EXTRA_PRIMITIVE_OVERESTIMATION_SIZE;
+        if !self.stats.count(self.cache.extra_primitive_overestimation_size.replace(size)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_extra_primitive_overestimation_size(self.renderer.command_buffer, size)} }
+    }
+    pub fn set_tessellation_domain_origin(&mut self, origin: vk::TessellationDomainOrigin){
+        self.dynamic_state_flags |= DynamicStateFlags::TESSELLATION_DOMAIN_ORIGIN;
+        if !self.stats.count(self.cache.tessellation_domain_origin.replace(origin)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_tessellation_domain_origin(self.renderer.command_buffer, origin)} }
+    }
+    pub fn set_patch_control_points(&mut self, control_points: u32){
+        self.dynamic_state_flags |= DynamicStateFlags::PATCH_CONTROL_POINTS;
+        if !self.stats.count(self.cache.patch_control_points.replace(control_points)) { return }
+        if let Some(ext) = &self.renderer.ext_shader_object { unsafe{ext.cmd_set_patch_control_points(self.renderer.command_buffer, control_points)} }
+    }
+
+    // vulkan requires us to set these things before drawing, which ones depends on the bound
+    // stages and on other state, see "Setting State" in the VK_EXT_shader_object spec
+    fn unset(&self, flag: DynamicStateFlags) -> bool { !self.dynamic_state_flags.contains(flag) }
+
     fn apply_unset_defaults(&mut self){
+        // nothing that decides which defaults are needed changed since the last draw
+        let inputs = (self.dynamic_state_flags, self.bound_stages, self.conditions, self.color_attachment_count);
+        if self.defaults_applied == Some(inputs) { return }
+        self.apply_needed_defaults();
+        self.defaults_applied = Some((self.dynamic_state_flags, self.bound_stages, self.conditions, self.color_attachment_count));
+    }
+
+    fn apply_needed_defaults(&mut self){
+        use DynamicStateFlags as F;
+        let area = self.render_area;
         let default_viewport : vk::Viewport = 
             vk::Viewport::default()
-                .x(0.0).y(0.0).min_depth(0.0).max_depth(1.0)
-                .width(self.renderer.swapchain_extent.width as f32)
-                .height(self.renderer.swapchain_extent.height as f32);
-        if !self.dynamic_state_flags.contains(DynamicStateFlags::VIEWPORTS                ){ self.set_viewports(&[default_viewport]); }
-        if !self.dynamic_state_flags.contains(DynamicStateFlags::SCISSORS                 ){ self.set_scissors(&[self.renderer.swapchain_extent.into()]); }
-        if !self.dynamic_state_flags.contains(DynamicStateFlags::POLYGON_MODE             ){ self.set_polygon_mode(vk::PolygonMode::FILL); }
-        if !self.dynamic_state_flags.contains(DynamicStateFlags::PRIMITIVE_TOPOLOGY       ){ self.set_primitive_topology(vk::PrimitiveTopology::TRIANGLE_LIST); }
-        if !self.dynamic_state_flags.contains(DynamicStateFlags::PRIMITIVE_RESTART_ENABLE ){ self.set_primitive_restart_enable(false); }
-        if !self.dynamic_state_flags.contains(DynamicStateFlags::DEPTH_TEST_ENABLE        ){ self.set_depth_test_enable(false); }
-        if !self.dynamic_state_flags.contains(DynamicStateFlags::DEPTH_WRITE_ENABLE       ){ self.set_depth_write_enable(false); }
-        if !self.dynamic_state_flags.contains(DynamicStateFlags::DEPTH_BIAS_ENABLE        ){ self.set_depth_bias_enable(false); }
-        if !self.dynamic_state_flags.contains(DynamicStateFlags::STENCIL_TEST_ENABLE      ){ self.set_stencil_test_enable(false); }
-        if !self.dynamic_state_flags.contains(DynamicStateFlags::RASTERIZER_DISCARD_ENABLE){ self.set_rasterizer_discard_enable(false); }
-        if !self.dynamic_state_flags.contains(DynamicStateFlags::RASTERIZATION_SAMPLES    ){ self.set_rasterization_samples(vk::SampleCountFlags::TYPE_1); }
-        if !self.dynamic_state_flags.contains(DynamicStateFlags::SAMPLE_MASK              ){ self.set_sample_mask(vk::SampleCountFlags::TYPE_1, &[vk::SampleMask::max_value()]); }
-        if !self.dynamic_state_flags.contains(DynamicStateFlags::ALPHA_TO_COVERAGE_ENABLE ){ self.set_alpha_to_coverage_enable(false); }
-        if !self.dynamic_state_flags.contains(DynamicStateFlags::SET_CULL_MODE            ){ self.set_cull_mode(vk::CullModeFlags::NONE); }
-        if !self.dynamic_state_flags.contains(DynamicStateFlags::COLOR_BLEND_ENABLE       ){ self.set_color_blend_enable(&[0]); }
-        if !self.dynamic_state_flags.contains(DynamicStateFlags::COLOR_BLEND_EQUATION     ){ self.set_color_blend_equation(&[vk::ColorBlendEquationEXT::default()]); }
-        if !self.dynamic_state_flags.contains(DynamicStateFlags::COLOR_WRITE_MASK         ){ self.set_color_write_mask(&[vk::ColorComponentFlags::RGBA]);}
-        // TODO: match this with the exact rules when things should be defined
+                .x(area.offset.x as f32).y(area.offset.y as f32).min_depth(0.0).max_depth(1.0)
+                .width(area.extent.width as f32)
+                .height(area.extent.height as f32);
+        if self.unset(F::VIEWPORTS                ){ self.set_viewports(&[default_viewport]); }
+        if self.unset(F::SCISSORS                 ){ self.set_scissors(&[area]); }
+        if self.unset(F::RASTERIZER_DISCARD_ENABLE){ self.set_rasterizer_discard_enable(false); }
+
+        let stages = self.bound_stages;
+        if stages.contains(vk::ShaderStageFlags::VERTEX) {
+            if self.unset(F::PRIMITIVE_TOPOLOGY       ){ self.set_primitive_topology(vk::PrimitiveTopology::TRIANGLE_LIST); }
+            if self.unset(F::PRIMITIVE_RESTART_ENABLE ){ self.set_primitive_restart_enable(false); }
+        }
+        if stages.contains(vk::ShaderStageFlags::TESSELLATION_CONTROL) {
+            if self.unset(F::PATCH_CONTROL_POINTS     ){ self.set_patch_control_points(3); }
+        }
+        if stages.contains(vk::ShaderStageFlags::TESSELLATION_EVALUATION) {
+            if self.unset(F::TESSELLATION_DOMAIN_ORIGIN){ self.set_tessellation_domain_origin(vk::TessellationDomainOrigin::UPPER_LEFT); }
+        }
+        if self.conditions.rasterizer_discard { return }
+
+        let features   = self.renderer.features;
+        let extensions = self.renderer.extensions;
+        if self.unset(F::RASTERIZATION_SAMPLES    ){ self.set_rasterization_samples(self.render_samples); }
+        if self.unset(F::SAMPLE_MASK              ){ self.set_sample_mask(self.render_samples, &[vk::SampleMask::max_value()]); }
+        if self.unset(F::ALPHA_TO_COVERAGE_ENABLE ){ self.set_alpha_to_coverage_enable(false); }
+        if self.unset(F::POLYGON_MODE             ){ self.set_polygon_mode(vk::PolygonMode::FILL); }
+        if self.unset(F::SET_CULL_MODE            ){ self.set_cull_mode(vk::CullModeFlags::NONE); }
+        if self.unset(F::FRONT_FACE               ){ self.set_front_face(vk::FrontFace::COUNTER_CLOCKWISE); }
+        if self.unset(F::DEPTH_TEST_ENABLE        ){ self.set_depth_test_enable(false); }
+        if self.unset(F::DEPTH_WRITE_ENABLE       ){ self.set_depth_write_enable(false); }
+        if self.unset(F::DEPTH_BIAS_ENABLE        ){ self.set_depth_bias_enable(false); }
+        if self.unset(F::STENCIL_TEST_ENABLE      ){ self.set_stencil_test_enable(false); }
+        if self.conditions.depth_test {
+            if self.unset(F::DEPTH_COMPARE_OP     ){ self.set_depth_compare_op(vk::CompareOp::LESS_OR_EQUAL); }
+        }
+        if self.conditions.depth_bias {
+            if self.unset(F::DEPTH_BIAS           ){ self.set_depth_bias(0.0, 0.0, 0.0); }
+        }
+        if features.depth_bounds==vk::TRUE {
+            if self.unset(F::DEPTH_BOUNDS_TEST_ENABLE){ self.set_depth_bounds_test_enable(false); }
+            if self.conditions.depth_bounds_test && self.unset(F::DEPTH_BOUNDS){ self.set_depth_bounds(0.0, 1.0); }
+        }
+        if self.conditions.stencil_test {
+            let stencil = vk::StencilFaceFlags::FRONT_AND_BACK;
+            if self.unset(F::STENCIL_OP           ){ self.set_stencil_op(stencil, vk::StencilOp::KEEP, vk::StencilOp::KEEP, vk::StencilOp::KEEP, vk::CompareOp::ALWAYS); }
+            if self.unset(F::STENCIL_COMPARE_MASK ){ self.set_stencil_compare_mask(stencil, 0xFF); }
+            if self.unset(F::STENCIL_WRITE_MASK   ){ self.set_stencil_write_mask(stencil, 0xFF); }
+            if self.unset(F::STENCIL_REFERENCE    ){ self.set_stencil_reference(stencil, 0); }
+        }
+        if self.conditions.line_topology || self.conditions.line_polygon_mode {
+            if self.unset(F::LINE_WIDTH           ){ self.set_line_width(1.0); }
+            if extensions.contains(OptionalExtensions::LINE_RASTERIZATION) {
+                if self.unset(F::LINE_RASTERIZATION_MODE){ self.set_line_rasterization_mode(vk::LineRasterizationModeEXT::DEFAULT); }
+                if self.unset(F::LINE_STIPPLE_ENABLE    ){ self.set_line_stipple_enable(false); }
+            }
+        }
+        if extensions.contains(OptionalExtensions::CONSERVATIVE_RASTERIZATION) {
+            if self.unset(F::CONSERVATIVE_RASTERIZATION_MODE){ self.set_conservative_rasterization_mode(vk::ConservativeRasterizationModeEXT::DISABLED); }
+            if self.conditions.overestimate && self.unset(F::EXTRA_PRIMITIVE_OVERESTIMATION_SIZE){ self.set_extra_primitive_overestimation_size(0.0); }
+        }
+        if features.logic_op==vk::TRUE {
+            if self.unset(F::LOGIC_OP_ENABLE      ){ self.set_logic_op_enable(false); }
+            if self.conditions.logic_op && self.unset(F::LOGIC_OP){ self.set_logic_op(vk::LogicOp::COPY); }
+        }
+        if stages.contains(vk::ShaderStageFlags::FRAGMENT) {
+            let attachments = self.color_attachment_count as usize;
+            if self.unset(F::COLOR_BLEND_ENABLE   ){ self.set_color_blend_enable(&vec![0; attachments]); }
+            if self.unset(F::COLOR_BLEND_EQUATION ){ self.set_color_blend_equation(&vec![vk::ColorBlendEquationEXT::default(); attachments]); }
+            if self.unset(F::COLOR_WRITE_MASK     ){ self.set_color_write_mask(&vec![vk::ColorComponentFlags::RGBA; attachments]);}
+            if self.conditions.blend_constants && self.unset(F::BLEND_CONSTANTS){ self.set_blend_constants([0.0;4]); }
+        }
+    }
+
+    /// without shader objects, look up the pipeline matching the current state
+    fn bind_fallback_pipeline(&mut self){
+        let Some(backend) = self.renderer.pipeline_backend.as_mut() else { return };
+        let extensions = self.renderer.extensions;
+        let key = PipelineKey::new(&self.cache, &self.attachment_formats,
+            extensions.contains(OptionalExtensions::LINE_RASTERIZATION),
+            extensions.contains(OptionalExtensions::CONSERVATIVE_RASTERIZATION));
+        let pipeline = backend.pipeline(&self.renderer.device, &key);
+        if !self.stats.count(self.cache.pipeline.replace(pipeline)) { return }
+        unsafe{self.renderer.device.cmd_bind_pipeline(self.renderer.command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline)};
     }
 
     pub fn draw(&mut self, vertex_count:u32, first_vertex:u32){
         self.apply_unset_defaults();
+        self.bind_fallback_pipeline();
         unsafe{self.renderer.device.cmd_draw(self.renderer.command_buffer, vertex_count, 1, first_vertex, 0)};
     }
 
     pub fn draw_indexed(&mut self, index_count:u32, first_index:u32, vertex_offset:i32){
         self.apply_unset_defaults();
+        self.bind_fallback_pipeline();
         unsafe{self.renderer.device.cmd_draw_indexed(self.renderer.command_buffer, index_count, 1, first_index, vertex_offset, 0)};
     }
 
-    pub fn bind_vs_fs(&self, vs: ShaderEXT, fs: ShaderEXT){
-        let stages  = [vk::ShaderStageFlags::VERTEX, vk::ShaderStageFlags::FRAGMENT];
-        let shaders = [vs, fs];
-        unsafe{self.renderer.ext_shader_object.cmd_bind_shaders(self.renderer.command_buffer, &stages, &shaders)};
+    pub fn bind_vs_fs(&mut self, vs: ShaderEXT, fs: ShaderEXT){
+        // unbind the tessellation and geometry stages so they don't linger from earlier binds
+        let null = ShaderEXT::null();
+        self.bind_shaders(&[
+            (vk::ShaderStageFlags::VERTEX,                  vs),
+            (vk::ShaderStageFlags::TESSELLATION_CONTROL,    null),
+            (vk::ShaderStageFlags::TESSELLATION_EVALUATION, null),
+            (vk::ShaderStageFlags::GEOMETRY,                null),
+            (vk::ShaderStageFlags::FRAGMENT,                fs),
+        ]);
+    }
+
+    /// binds each shader to its stage, a null shader unbinds the stage
+    pub fn bind_shaders(&mut self, shaders: &[(vk::ShaderStageFlags, ShaderEXT)]){
+        for (stage, shader) in shaders {
+            if shader.is_null() { self.bound_stages &= !*stage } else { self.bound_stages |= *stage }
+        }
+        // rebind only the stages that changed, stages that were never bound count as changed
+        // since even a null shader has to be bound explicitly before drawing
+        let bound = &mut self.cache.shaders;
+        let changed : Vec<_> = shaders.iter().copied().filter(|&(stage, shader)| {
+            match bound.iter_mut().find(|(s,_)| *s==stage) {
+                Some((_, current)) => core::mem::replace(current, shader)!=shader,
+                None => { bound.push((stage, shader)); true },
+            }
+        }).collect();
+        if !self.stats.count(!changed.is_empty()) { return }
+        let (stages, shaders) : (Vec<_>, Vec<_>) = changed.into_iter().unzip();
+        if let Some(ext) = &self.renderer.ext_shader_object {
+            unsafe{ext.cmd_bind_shaders(self.renderer.command_buffer, &stages, &shaders)};
+        }
     }
 
-    pub fn set_vertex_input(&self, vertex_stride:u32, offsets:&[(u32,vk::Format)]){
+    pub fn set_vertex_input(&mut self, vertex_stride:u32, offsets:&[(u32,vk::Format)]){
+        if !self.stats.count(self.cache.vertex_input.replace((vertex_stride, offsets.to_vec()))) { return }
         let binding = [vk::VertexInputBindingDescription2EXT::default()
             .binding(0)
             .stride(vertex_stride)
@@ -772,8 +1812,14 @@ impl<'a> Frame<'a> {
             .binding(0)
             .format(*fmt)
             .offset(*off) ).collect();
-        unsafe{self.renderer.ext_shader_object.cmd_set_vertex_input(self.renderer.command_buffer,
-            &binding, &attribute)};
+        if let Some(ext) = &self.renderer.ext_shader_object {
+            unsafe{ext.cmd_set_vertex_input(self.renderer.command_buffer, &binding, &attribute)};
+        }
+    }
+
+    /// `set_vertex_input` with the layout of a Rust vertex type
+    pub fn set_vertex_layout<V: VertexLayout>(&mut self){
+        self.set_vertex_input(V::STRIDE, V::ATTRIBUTES);
     }
 
     pub fn bind_index_buffer(&self, buffer: vk::Buffer, offset:u64){
@@ -786,7 +1832,18 @@ impl<'a> Frame<'a> {
         unsafe{self.renderer.device.cmd_bind_vertex_buffers(self.renderer.command_buffer, 0, &buffers, &offsets)};//, Some(&sizes), Some(&strides))};
     }
 
-    pub fn bind_descriptor_set(&self, descriptor_set:vk::DescriptorSet, pipeline_layout:vk::PipelineLayout){
+    /// The set is only valid until the end of this frame.
+    pub fn allocate_descriptor_set(&mut self, layout: vk::DescriptorSetLayout) -> vk::DescriptorSet {
+        self.renderer.frame_descriptors.allocate(&self.renderer.device, layout)
+    }
+
+    /// The pools `allocate_descriptor_set` takes sets from, they are reset and reused every frame.
+    pub fn descriptor_pools(&self) -> Vec<vk::DescriptorPool> {
+        self.renderer.frame_descriptors.pools()
+    }
+
+    pub fn bind_descriptor_set(&mut self, descriptor_set:vk::DescriptorSet, pipeline_layout:vk::PipelineLayout){
+        if !self.stats.count(self.cache.descriptor_set.replace((descriptor_set, pipeline_layout))) { return }
         let descriptor_set = [descriptor_set];
         unsafe{self.renderer.device.cmd_bind_descriptor_sets(
             self.renderer.command_buffer, vk::PipelineBindPoint::GRAPHICS,
@@ -797,39 +1854,129 @@ impl<'a> Frame<'a> {
     }
 
     pub fn push_constant<T>(&self, pipeline_layout: vk::PipelineLayout, data:&T){
+        self.push_constant_stages(pipeline_layout, vk::ShaderStageFlags::VERTEX, data);
+    }
+
+    /// `stages` has to match the push constant range of `pipeline_layout`.
+    pub fn push_constant_stages<T>(&self, pipeline_layout: vk::PipelineLayout, stages: vk::ShaderStageFlags, data:&T){
         let ptr = core::ptr::from_ref(data);
         let byte_ptr = unsafe{core::mem::transmute::<*const T,*const u8>(ptr)};
         let bytes = unsafe{core::slice::from_raw_parts(byte_ptr, size_of::<T>())};
         unsafe{self.renderer.device.cmd_push_constants(
             self.renderer.command_buffer,
             pipeline_layout,
-            vk::ShaderStageFlags::VERTEX, 0, bytes)};
+            stages, 0, bytes)};
+    }
+
+    /// Binds a shader created by `load_spirv_cs`, graphics shaders stay bound.
+    pub fn bind_cs(&mut self, cs: ShaderEXT){
+        if !self.stats.count(self.cache.compute_shader.replace(cs)) { return }
+        match (&self.renderer.ext_shader_object, self.renderer.pipeline_backend.as_ref()) {
+            (Some(ext), _) => unsafe{ext.cmd_bind_shaders(self.renderer.command_buffer, &[vk::ShaderStageFlags::COMPUTE], &[cs])},
+            (None, Some(backend)) => unsafe{self.renderer.device.cmd_bind_pipeline(
+                self.renderer.command_buffer, vk::PipelineBindPoint::COMPUTE, backend.compute_pipeline(cs))},
+            (None, None) => unreachable!(),
+        }
+    }
+
+    pub fn bind_compute_descriptor_set(&mut self, descriptor_set:vk::DescriptorSet, pipeline_layout:vk::PipelineLayout){
+        if !self.stats.count(self.cache.compute_descriptor_set.replace((descriptor_set, pipeline_layout))) { return }
+        let descriptor_set = [descriptor_set];
+        unsafe{self.renderer.device.cmd_bind_descriptor_sets(
+            self.renderer.command_buffer, vk::PipelineBindPoint::COMPUTE,
+            pipeline_layout,
+            0,
+            &descriptor_set,
+            &[])};
+    }
+
+    /// Writes the push descriptor set of `layout` inline, the writer's own set is ignored.
+    pub fn push_descriptor_set(&mut self, layout: &ShaderLayout, writer: DescriptorWriter){
+        self.cache.descriptor_set = Default::default();
+        self.push_descriptors(vk::PipelineBindPoint::GRAPHICS, layout, writer);
+    }
+
+    pub fn push_compute_descriptor_set(&mut self, layout: &ShaderLayout, writer: DescriptorWriter){
+        self.cache.compute_descriptor_set = Default::default();
+        self.push_descriptors(vk::PipelineBindPoint::COMPUTE, layout, writer);
     }
 
-    pub fn end_rendering(&self) {
+    fn push_descriptors(&mut self, bind_point: vk::PipelineBindPoint, layout: &ShaderLayout, writer: DescriptorWriter){
+        let Some(set) = layout.push_descriptor_set else {
+            panic!("\n{ERR_STR} the layout has no push descriptor set, create it with create_push_descriptor_layout\n")
+        };
+        self.stats.count(true);
+        let cmd = self.renderer.command_buffer;
+        match &self.renderer.khr_push_descriptor {
+            Some(khr) => unsafe{khr.cmd_push_descriptor_set(cmd, bind_point, layout.pipeline_layout, set, &writer.writes(vk::DescriptorSet::null()))},
+            None => {
+                // emulated with a set that lives until the end of the frame
+                let descriptor_set = self.allocate_descriptor_set(layout.set_layouts[set as usize]);
+                unsafe{self.renderer.device.update_descriptor_sets(&writer.writes(descriptor_set), &[])};
+                unsafe{self.renderer.device.cmd_bind_descriptor_sets(cmd, bind_point, layout.pipeline_layout, set, &[descriptor_set], &[])};
+            },
+        }
+    }
+
+    /// Runs the bound compute shader. Must be called outside of begin_rendering/end_rendering,
+    /// declare what it reads and writes with `use_buffer` and `use_image` first.
+    pub fn dispatch(&mut self, group_count_x: u32, group_count_y: u32, group_count_z: u32){
+        assert!(!self.rendering, "\n{ERR_STR} dispatch must be called outside of begin_rendering/end_rendering\n");
+        unsafe{self.renderer.device.cmd_dispatch(self.renderer.command_buffer, group_count_x, group_count_y, group_count_z)};
+    }
+
+    pub fn end_rendering(&mut self) {
         // end rendering
         unsafe{self.renderer.khr_dynamic_rendering.cmd_end_rendering(self.renderer.command_buffer)};
+        self.rendering = false;
+    }
+
+    /// Copies what the last `begin_rendering` drew into its first color attachment to the host.
+    /// Must be called after end_rendering, the pixels arrive once the frame finished on the gpu.
+    /// Only 8 bit RGBA and BGRA targets with TRANSFER_SRC usage can be captured.
+    pub fn capture(&mut self) -> Capture {
+        let Some((image, area)) = self.color_target else {
+            panic!("\n{ERR_STR} nothing was rendered to capture\n")
+        };
+        let (format, usage) = self.renderer.image_states.get(&image)
+            .map_or((vk::Format::UNDEFINED, vk::ImageUsageFlags::empty()), |tracked|(tracked.format, tracked.usage));
+        if !capture::supports(format) {
+            panic!("\n{ERR_STR} can not capture {format:?} images\n")
+        }
+        if !usage.contains(vk::ImageUsageFlags::TRANSFER_SRC) {
+            panic!("\n{ERR_STR} can not capture images without TRANSFER_SRC usage, see SwapchainConfig::image_usage\n")
+        }
+        let size = area.extent.width as u64*area.extent.height as u64*4;
+        let (buffer, memory) = self.renderer.alloc_buffer(size, vk::BufferUsageFlags::TRANSFER_DST, self.renderer.host_memory_idx());
+        self.use_image(image, Usage::TransferSrc);
+        self.use_buffer(buffer, Usage::TransferDst);
+        let region = [vk::BufferImageCopy{
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers{ aspect_mask: vk::ImageAspectFlags::COLOR, mip_level: 0, base_array_layer: 0, layer_count: 1 },
+            image_offset: vk::Offset3D{ x: area.offset.x, y: area.offset.y, z: 0 },
+            image_extent: vk::Extent3D{ width: area.extent.width, height: area.extent.height, depth: 1 },
+        }];
+        unsafe{self.renderer.device.cmd_copy_image_to_buffer(self.renderer.command_buffer, image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, buffer, &region)};
+        self.use_buffer(buffer, Usage::HostRead);
+        // the buffer is freed once read, it doesn't need tracking beyond this frame
+        self.renderer.buffer_states.remove(&buffer);
+        let (pending, capture) = PendingCapture::new(buffer, memory, area.extent, format);
+        self.renderer.captures.push(pending);
+        capture
     }
 
     /// returns false if window redraw is required
-    pub fn end_frame(self) -> bool {
-        let renderer = &self.renderer;
+    pub fn end_frame(mut self) -> bool {
         let swap_idx = self.swap_idx;
 
         // end frame
-        let image_memory_barriers = [
-            vk::ImageMemoryBarrier::default()
-                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
-                .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-                .image(renderer.swapchain_images[swap_idx as usize])
-                .subresource_range(SUBRANGE)
-        ];
-        unsafe{renderer.device.cmd_pipeline_barrier(renderer.command_buffer,
-            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-            vk::DependencyFlags::empty(),
-            &[], &[], &image_memory_barriers)};
+        self.use_image(self.renderer.swapchain_images[swap_idx as usize], Usage::Present);
+        if let Some(backend) = self.renderer.pipeline_backend.as_mut() {
+            backend.save_cache(&self.renderer.device);
+        }
+        let renderer = &self.renderer;
 
 
         // end command buffer
@@ -871,7 +2018,8 @@ impl<'a> Frame<'a> {
 }
 
 bitflags!{
-    pub struct DynamicStateFlags: u32 {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct DynamicStateFlags: u64 {
         const VIEWPORTS                 = 1<< 0;
         const SCISSORS                  = 1<< 1;
         const POLYGON_MODE              = 1<< 2;
@@ -889,6 +2037,44 @@ bitflags!{
         const COLOR_BLEND_ENABLE        = 1<<14;
         const COLOR_BLEND_EQUATION      = 1<<15;
         const COLOR_WRITE_MASK          = 1<<16;
+        const STENCIL_OP                = 1<<17;
+        const STENCIL_COMPARE_MASK      = 1<<18;
+        const STENCIL_WRITE_MASK        = 1<<19;
+        const STENCIL_REFERENCE         = 1<<20;
+        const FRONT_FACE                = 1<<21;
+        const DEPTH_COMPARE_OP          = 1<<22;
+        const DEPTH_BOUNDS_TEST_ENABLE  = 1<<23;
+        const DEPTH_BOUNDS              = 1<<24;
+        const DEPTH_BIAS                = 1<<25;
+        const BLEND_CONSTANTS           = 1<<26;
+        const LOGIC_OP_ENABLE           = 1<<27;
+        const LOGIC_OP                  = 1<<28;
+        const LINE_WIDTH                = 1<<29;
+        const LINE_RASTERIZATION_MODE   = 1<<30;
+        const LINE_STIPPLE_ENABLE       = 1<<31;
+        const CONSERVATIVE_RASTERIZATION_MODE     = 1<<32;
+        const EXTRA_PRIMITIVE_OVERESTIMATION_SIZE = 1<<33;
+        const TESSELLATION_DOMAIN_ORIGIN = 1<<34;
+        const PATCH_CONTROL_POINTS      = 1<<35;
     }
 }
 
+bitflags!{
+    /// Device extensions that are enabled when the gpu supports them.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct OptionalExtensions: u32 {
+        const LINE_RASTERIZATION         = 1<<0;
+        const CONSERVATIVE_RASTERIZATION = 1<<1;
+        const SHADER_OBJECT              = 1<<2;
+        const PUSH_DESCRIPTOR            = 1<<3;
+    }
+}
+impl OptionalExtensions {
+    const NAMES: [(Self, &'static ffi::CStr); 4] = [
+        (Self::LINE_RASTERIZATION,         ext::line_rasterization::NAME),
+        (Self::CONSERVATIVE_RASTERIZATION, ext::conservative_rasterization::NAME),
+        (Self::SHADER_OBJECT,              ext::shader_object::NAME),
+        (Self::PUSH_DESCRIPTOR,            khr::push_descriptor::NAME),
+    ];
+}
+
@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use ash::vk::{self, Handle, ShaderEXT};
+use crate::ERR_STR;
+use crate::state::StateCache;
+
+/// Stand-in for a `ShaderEXT` on devices without VK_EXT_shader_object.
+/// `Frame` passes the fake handle around like a real shader object,
+/// and it only becomes part of a pipeline when something is drawn with it.
+struct FallbackShader {
+    module: vk::ShaderModule,
+    stage:  vk::ShaderStageFlags,
+    layout: vk::PipelineLayout,
+    compute: vk::Pipeline, // null for graphics stages
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub(crate) struct AttachmentFormats {
+    pub colors:  Vec<vk::Format>,
+    pub depth:   vk::Format,
+    pub stencil: vk::Format,
+}
+
+/// All state a graphics pipeline bakes in, which shader objects would leave dynamic.
+/// Viewports, scissors, stencil masks and reference, depth bias and bounds, line width
+/// and blend constants stay dynamic in every pipeline, so they are not part of the key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct PipelineKey {
+    shaders:           Vec<(vk::ShaderStageFlags, ShaderEXT)>,
+    vertex_input:      Option<(u32, Vec<(u32, vk::Format)>)>,
+    topology:          vk::PrimitiveTopology,
+    primitive_restart: bool,
+    patch_control_points: u32,
+    domain_origin:     vk::TessellationDomainOrigin,
+    viewport_count:    u32,
+    rasterizer_discard: bool,
+    polygon_mode:      vk::PolygonMode,
+    cull_mode:         vk::CullModeFlags,
+    front_face:        vk::FrontFace,
+    depth_bias:        bool,
+    line_mode:         Option<vk::LineRasterizationModeEXT>,
+    conservative:      Option<(vk::ConservativeRasterizationModeEXT, u32)>, // overestimation size as f32 bits
+    samples:           vk::SampleCountFlags,
+    sample_mask:       Vec<vk::SampleMask>,
+    alpha_to_coverage: bool,
+    depth_test:        bool,
+    depth_write:       bool,
+    depth_compare:     vk::CompareOp,
+    depth_bounds_test: bool,
+    stencil_test:      bool,
+    stencil_ops:       [(vk::StencilOp, vk::StencilOp, vk::StencilOp, vk::CompareOp);2],
+    logic_op:          Option<vk::LogicOp>,
+    blend:             Vec<(u32, [i32;6], vk::ColorComponentFlags)>, // (enable, equation, write mask) per attachment
+    formats:           AttachmentFormats,
+}
+
+impl PipelineKey {
+    /// Reads the pipeline state from what the frame recorded, state that was never set
+    /// isn't needed by the bound stages and takes the same defaults as `apply_unset_defaults`.
+    pub(crate) fn new(cache: &StateCache, formats: &AttachmentFormats, line_rasterization: bool, conservative_rasterization: bool) -> Self {
+        let mut shaders : Vec<_> = cache.shaders.iter().copied().filter(|(_, shader)| !shader.is_null()).collect();
+        shaders.sort_by_key(|(stage, _)| stage.as_raw());
+        let keep = vk::StencilOp::KEEP;
+        let default_ops = (keep, keep, keep, vk::CompareOp::ALWAYS);
+        let blend = (0..formats.colors.len()).map(|i| (
+            cache.color_blend_enable.get().and_then(|enables| enables.get(i).copied()).unwrap_or(0),
+            cache.color_blend_equation.get().and_then(|equations| equations.get(i).copied()).unwrap_or([0;6]),
+            cache.color_write_mask.get().and_then(|masks| masks.get(i).copied()).unwrap_or(vk::ColorComponentFlags::RGBA),
+        )).collect();
+        Self{
+            shaders,
+            vertex_input:      cache.vertex_input.get().cloned(),
+            topology:          cache.primitive_topology.get().copied().unwrap_or(vk::PrimitiveTopology::TRIANGLE_LIST),
+            primitive_restart: cache.primitive_restart_enable.get().copied().unwrap_or(false),
+            patch_control_points: cache.patch_control_points.get().copied().unwrap_or(3),
+            domain_origin:     cache.tessellation_domain_origin.get().copied().unwrap_or(vk::TessellationDomainOrigin::UPPER_LEFT),
+            viewport_count:    cache.viewports.get().map_or(1, |viewports| viewports.len() as u32),
+            rasterizer_discard: cache.rasterizer_discard_enable.get().copied().unwrap_or(false),
+            polygon_mode:      cache.polygon_mode.get().copied().unwrap_or(vk::PolygonMode::FILL),
+            cull_mode:         cache.cull_mode.get().copied().unwrap_or(vk::CullModeFlags::NONE),
+            front_face:        cache.front_face.get().copied().unwrap_or(vk::FrontFace::COUNTER_CLOCKWISE),
+            depth_bias:        cache.depth_bias_enable.get().copied().unwrap_or(false),
+            line_mode:         line_rasterization.then(|| cache.line_rasterization_mode.get().copied().unwrap_or(vk::LineRasterizationModeEXT::DEFAULT)),
+            conservative:      conservative_rasterization.then(|| (
+                cache.conservative_rasterization_mode.get().copied().unwrap_or(vk::ConservativeRasterizationModeEXT::DISABLED),
+                cache.extra_primitive_overestimation_size.get().copied().unwrap_or(0.0).to_bits())),
+            samples:           cache.rasterization_samples.get().copied().unwrap_or(vk::SampleCountFlags::TYPE_1),
+            sample_mask:       cache.sample_mask.get().map_or(vec![vk::SampleMask::MAX], |(_, mask)| mask.clone()),
+            alpha_to_coverage: cache.alpha_to_coverage_enable.get().copied().unwrap_or(false),
+            depth_test:        cache.depth_test_enable.get().copied().unwrap_or(false),
+            depth_write:       cache.depth_write_enable.get().copied().unwrap_or(false),
+            depth_compare:     cache.depth_compare_op.get().copied().unwrap_or(vk::CompareOp::LESS_OR_EQUAL),
+            depth_bounds_test: cache.depth_bounds_test_enable.get().copied().unwrap_or(false),
+            stencil_test:      cache.stencil_test_enable.get().copied().unwrap_or(false),
+            stencil_ops:       [cache.stencil_op[0].get().copied().unwrap_or(default_ops), cache.stencil_op[1].get().copied().unwrap_or(default_ops)],
+            logic_op:          cache.logic_op_enable.get().copied().unwrap_or(false).then(|| cache.logic_op.get().copied().unwrap_or(vk::LogicOp::COPY)),
+            blend,
+            formats:           formats.clone(),
+        }
+    }
+}
+
+fn stencil_state((fail_op, pass_op, depth_fail_op, compare_op): (vk::StencilOp, vk::StencilOp, vk::StencilOp, vk::CompareOp)) -> vk::StencilOpState {
+    vk::StencilOpState{ fail_op, pass_op, depth_fail_op, compare_op, ..Default::default() }
+}
+
+/// Emulates shader objects with lazily built pipelines, cached in memory by `PipelineKey`
+/// and across runs in a `VkPipelineCache` file.
+pub(crate) struct PipelineBackend {
+    shaders:     HashMap<ShaderEXT, FallbackShader>,
+    next_shader: u64,
+    pipelines:   HashMap<PipelineKey, vk::Pipeline>,
+    cache:       vk::PipelineCache,
+    cache_path:  PathBuf,
+    cache_dirty: bool,
+}
+
+impl PipelineBackend {
+    pub(crate) fn new(device: &ash::Device, properties: &vk::PhysicalDeviceProperties) -> Self {
+        let cache_path = cache_path(&cache_dir(), properties);
+        // the driver checks the header and ignores data from other devices or driver versions
+        let initial_data = std::fs::read(&cache_path).unwrap_or_default();
+        let cache_info = vk::PipelineCacheCreateInfo::default().initial_data(&initial_data);
+        let cache = unsafe{device.create_pipeline_cache(&cache_info, None)}
+            .or_else(|_| unsafe{device.create_pipeline_cache(&vk::PipelineCacheCreateInfo::default(), None)})
+            .unwrap();
+        Self{ shaders: HashMap::new(), next_shader: 1, pipelines: HashMap::new(), cache, cache_path, cache_dirty: false }
+    }
+
+    pub(crate) fn create_shader(&mut self, device: &ash::Device, stage: vk::ShaderStageFlags, spv: &[u8], layout: vk::PipelineLayout) -> Result<ShaderEXT, String> {
+        let code = ash::util::read_spv(&mut std::io::Cursor::new(spv)).map_err(|err| format!("invalid SPIR-V: {err}"))?;
+        let module_info = vk::ShaderModuleCreateInfo::default().code(&code);
+        let module = unsafe{device.create_shader_module(&module_info, None)}
+            .map_err(|err| format!("shader module creation failed: {err}"))?;
+        let mut compute = vk::Pipeline::null();
+        if stage == vk::ShaderStageFlags::COMPUTE {
+            let pipeline_info = vk::ComputePipelineCreateInfo::default()
+                .stage(vk::PipelineShaderStageCreateInfo::default().stage(stage).module(module).name(c"main"))
+                .layout(layout);
+            compute = match unsafe{device.create_compute_pipelines(self.cache, &[pipeline_info], None)} {
+                Ok(pipelines) => pipelines[0],
+                Err((_, err)) => {
+                    unsafe{device.destroy_shader_module(module, None)};
+                    return Err(format!("compute pipeline creation failed: {err}"))
+                },
+            };
+            self.cache_dirty = true;
+        }
+        let shader = ShaderEXT::from_raw(self.next_shader);
+        self.next_shader += 1;
+        self.shaders.insert(shader, FallbackShader{ module, stage, layout, compute });
+        Ok(shader)
+    }
+
+    /// Destroys the shader module and every pipeline built from it, and the
+    /// pipeline layout once no other shader uses it.
+    pub(crate) fn destroy_shader(&mut self, device: &ash::Device, shader: ShaderEXT) {
+        let Some(destroyed) = self.shaders.remove(&shader) else { return };
+        self.pipelines.retain(|key, pipeline| {
+            let keep = !key.shaders.iter().any(|(_, other)| *other == shader);
+            if !keep { unsafe{device.destroy_pipeline(*pipeline, None)} }
+            keep
+        });
+        unsafe{device.destroy_pipeline(destroyed.compute, None)};
+        unsafe{device.destroy_shader_module(destroyed.module, None)};
+        if !self.shaders.values().any(|other| other.layout == destroyed.layout) {
+            unsafe{device.destroy_pipeline_layout(destroyed.layout, None)};
+        }
+    }
+
+    pub(crate) fn compute_pipeline(&self, shader: ShaderEXT) -> vk::Pipeline {
+        self.shaders[&shader].compute
+    }
+
+    /// returns the pipeline for `key`, building it on first use
+    pub(crate) fn pipeline(&mut self, device: &ash::Device, key: &PipelineKey) -> vk::Pipeline {
+        if let Some(pipeline) = self.pipelines.get(key) { return *pipeline }
+        let pipeline = self.create_pipeline(device, key);
+        self.pipelines.insert(key.clone(), pipeline);
+        self.cache_dirty = true;
+        pipeline
+    }
+
+    fn create_pipeline(&self, device: &ash::Device, key: &PipelineKey) -> vk::Pipeline {
+        let shaders : Vec<_> = key.shaders.iter().map(|(_, shader)| &self.shaders[shader]).collect();
+        let Some(layout) = shaders.first().map(|shader| shader.layout) else {
+            panic!("\n{ERR_STR} draw without any bound shaders\n")
+        };
+        let stages : Vec<_> = shaders.iter().map(|shader| vk::PipelineShaderStageCreateInfo::default()
+            .stage(shader.stage)
+            .module(shader.module)
+            .name(c"main")).collect();
+
+        let (bindings, attributes) = match &key.vertex_input {
+            None => (vec![], vec![]),
+            Some((stride, offsets)) => (
+                vec![vk::VertexInputBindingDescription{ binding: 0, stride: *stride, input_rate: vk::VertexInputRate::VERTEX }],
+                offsets.iter().enumerate().map(|(i,(offset,format))| vk::VertexInputAttributeDescription{
+                    location: i as u32, binding: 0, format: *format, offset: *offset }).collect(),
+            ),
+        };
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_binding_descriptions(&bindings)
+            .vertex_attribute_descriptions(&attributes);
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(key.topology)
+            .primitive_restart_enable(key.primitive_restart);
+        let mut domain_origin = vk::PipelineTessellationDomainOriginStateCreateInfo::default()
+            .domain_origin(key.domain_origin);
+        let tessellation = vk::PipelineTessellationStateCreateInfo::default()
+            .patch_control_points(key.patch_control_points)
+            .push_next(&mut domain_origin);
+        let viewport = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(key.viewport_count)
+            .scissor_count(key.viewport_count);
+
+        let mut line = vk::PipelineRasterizationLineStateCreateInfoEXT::default()
+            .line_rasterization_mode(key.line_mode.unwrap_or_default());
+        let mut conservative = vk::PipelineRasterizationConservativeStateCreateInfoEXT::default();
+        if let Some((mode, size)) = key.conservative {
+            conservative = conservative
+                .conservative_rasterization_mode(mode)
+                .extra_primitive_overestimation_size(f32::from_bits(size));
+        }
+        let mut rasterization = vk::PipelineRasterizationStateCreateInfo::default()
+            .rasterizer_discard_enable(key.rasterizer_discard)
+            .polygon_mode(key.polygon_mode)
+            .cull_mode(key.cull_mode)
+            .front_face(key.front_face)
+            .depth_bias_enable(key.depth_bias)
+            .line_width(1.0);
+        if key.line_mode.is_some() {
+            rasterization = rasterization.push_next(&mut line);
+        }
+        if key.conservative.is_some() {
+            rasterization = rasterization.push_next(&mut conservative);
+        }
+
+        let multisample = vk::PipelineMultisampleStateCreateInfo::default()
+            .rasterization_samples(key.samples)
+            .sample_mask(&key.sample_mask)
+            .alpha_to_coverage_enable(key.alpha_to_coverage);
+        let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::default()
+            .depth_test_enable(key.depth_test)
+            .depth_write_enable(key.depth_write)
+            .depth_compare_op(key.depth_compare)
+            .depth_bounds_test_enable(key.depth_bounds_test)
+            .stencil_test_enable(key.stencil_test)
+            .front(stencil_state(key.stencil_ops[0]))
+            .back(stencil_state(key.stencil_ops[1]));
+        let blend_attachments : Vec<_> = key.blend.iter().map(|(enable, eq, write_mask)| vk::PipelineColorBlendAttachmentState{
+            blend_enable:           *enable,
+            src_color_blend_factor: vk::BlendFactor::from_raw(eq[0]),
+            dst_color_blend_factor: vk::BlendFactor::from_raw(eq[1]),
+            color_blend_op:         vk::BlendOp::from_raw(eq[2]),
+            src_alpha_blend_factor: vk::BlendFactor::from_raw(eq[3]),
+            dst_alpha_blend_factor: vk::BlendFactor::from_raw(eq[4]),
+            alpha_blend_op:         vk::BlendOp::from_raw(eq[5]),
+            color_write_mask:       *write_mask,
+        }).collect();
+        let color_blend = vk::PipelineColorBlendStateCreateInfo::default()
+            .logic_op_enable(key.logic_op.is_some())
+            .logic_op(key.logic_op.unwrap_or(vk::LogicOp::COPY))
+            .attachments(&blend_attachments);
+
+        // the state core Vulkan can set dynamically is kept dynamic in every pipeline
+        let dynamic_states = [
+            vk::DynamicState::VIEWPORT,
+            vk::DynamicState::SCISSOR,
+            vk::DynamicState::LINE_WIDTH,
+            vk::DynamicState::DEPTH_BIAS,
+            vk::DynamicState::BLEND_CONSTANTS,
+            vk::DynamicState::DEPTH_BOUNDS,
+            vk::DynamicState::STENCIL_COMPARE_MASK,
+            vk::DynamicState::STENCIL_WRITE_MASK,
+            vk::DynamicState::STENCIL_REFERENCE,
+        ];
+        let dynamic = vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+        let mut rendering = vk::PipelineRenderingCreateInfo::default()
+            .color_attachment_formats(&key.formats.colors)
+            .depth_attachment_format(key.formats.depth)
+            .stencil_attachment_format(key.formats.stencil);
+
+        let pipeline_info = vk::GraphicsPipelineCreateInfo::default()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .tessellation_state(&tessellation)
+            .viewport_state(&viewport)
+            .rasterization_state(&rasterization)
+            .multisample_state(&multisample)
+            .depth_stencil_state(&depth_stencil)
+            .color_blend_state(&color_blend)
+            .dynamic_state(&dynamic)
+            .layout(layout)
+            .push_next(&mut rendering);
+        match unsafe{device.create_graphics_pipelines(self.cache, &[pipeline_info], None)} {
+            Ok(pipelines) => pipelines[0],
+            Err((_, err)) => panic!("\n{ERR_STR} pipeline creation failed\n{err}\n"),
+        }
+    }
+
+    /// Saves the pipeline cache and destroys every shader, pipeline and layout. The device must be idle.
+    pub(crate) fn destroy(&mut self, device: &ash::Device) {
+        self.save_cache(device);
+        let shaders : Vec<_> = self.shaders.keys().copied().collect();
+        for shader in shaders {
+            self.destroy_shader(device, shader);
+        }
+        unsafe{device.destroy_pipeline_cache(self.cache, None)};
+    }
+
+    /// writes the pipeline cache to disk if pipelines were created since the last save
+    pub(crate) fn save_cache(&mut self, device: &ash::Device) {
+        if !self.cache_dirty { return }
+        self.cache_dirty = false;
+        let Ok(data) = (unsafe{device.get_pipeline_cache_data(self.cache)}) else { return };
+        // other processes may use the same file, replace it in one step
+        let partial = self.cache_path.with_extension(format!("{}.tmp", std::process::id()));
+        let written = self.cache_path.parent().map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&partial, data))
+            .and_then(|_| std::fs::rename(&partial, &self.cache_path));
+        if let Err(err) = written {
+            println!("{ERR_STR} could not write pipeline cache {}: {err}", self.cache_path.display());
+        }
+    }
+}
+
+/// The user's cache directory for the renderer, e.g. ~/.cache/renderer
+fn cache_dir() -> PathBuf {
+    let env = |name| std::env::var_os(name).filter(|dir| !dir.is_empty()).map(PathBuf::from);
+    env("XDG_CACHE_HOME")
+        .or_else(|| env("HOME").map(|home| home.join(".cache")))
+        .or_else(|| env("LOCALAPPDATA"))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("renderer")
+}
+
+/// One cache file per gpu and driver build, so processes on different devices don't
+/// keep replacing each other's cache.
+fn cache_path(dir: &Path, properties: &vk::PhysicalDeviceProperties) -> PathBuf {
+    let uuid : String = properties.pipeline_cache_uuid.iter().map(|byte| format!("{byte:02x}")).collect();
+    dir.join(format!("pipeline-cache-{:04x}-{:04x}-{uuid}.bin", properties.vendor_id, properties.device_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_files_are_per_device() {
+        let mut properties = vk::PhysicalDeviceProperties{ vendor_id: 0x10de, device_id: 0x2684, ..Default::default() };
+        properties.pipeline_cache_uuid[0] = 0xab;
+        let path = cache_path(Path::new("/cache"), &properties);
+        assert_eq!(path, Path::new("/cache/pipeline-cache-10de-2684-ab000000000000000000000000000000.bin"));
+        properties.device_id = 0x2704;
+        assert_ne!(cache_path(Path::new("/cache"), &properties), path);
+        properties.device_id = 0x2684;
+        properties.pipeline_cache_uuid[15] = 1;
+        assert_ne!(cache_path(Path::new("/cache"), &properties), path);
+    }
+}
@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+use ash::vk;
+
+// the handful of SPIR-V opcodes and enums the reflection needs
+mod op {
+    pub const ENTRY_POINT:      u16 = 15;
+    pub const TYPE_INT:         u16 = 21;
+    pub const TYPE_FLOAT:       u16 = 22;
+    pub const TYPE_VECTOR:      u16 = 23;
+    pub const TYPE_MATRIX:      u16 = 24;
+    pub const TYPE_IMAGE:       u16 = 25;
+    pub const TYPE_SAMPLER:     u16 = 26;
+    pub const TYPE_SAMPLED_IMAGE: u16 = 27;
+    pub const TYPE_ARRAY:       u16 = 28;
+    pub const TYPE_RUNTIME_ARRAY: u16 = 29;
+    pub const TYPE_STRUCT:      u16 = 30;
+    pub const TYPE_POINTER:     u16 = 32;
+    pub const CONSTANT:         u16 = 43;
+    pub const VARIABLE:         u16 = 59;
+    pub const DECORATE:         u16 = 71;
+    pub const MEMBER_DECORATE:  u16 = 72;
+}
+mod decoration {
+    pub const BUFFER_BLOCK:   u32 = 3;
+    pub const ARRAY_STRIDE:   u32 = 6;
+    pub const MATRIX_STRIDE:  u32 = 7;
+    pub const BUILT_IN:       u32 = 11;
+    pub const LOCATION:       u32 = 30;
+    pub const BINDING:        u32 = 33;
+    pub const DESCRIPTOR_SET: u32 = 34;
+    pub const OFFSET:         u32 = 35;
+}
+mod storage {
+    pub const UNIFORM_CONSTANT: u32 = 0;
+    pub const INPUT:            u32 = 1;
+    pub const UNIFORM:          u32 = 2;
+    pub const PUSH_CONSTANT:    u32 = 9;
+    pub const STORAGE_BUFFER:   u32 = 12;
+}
+const MAGIC: u32 = 0x0723_0203;
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DescriptorBinding {
+    pub set:     u32,
+    pub binding: u32,
+    pub ty:      vk::DescriptorType,
+    /// unsized arrays have a count of 0, the caller has to pick a size
+    pub count:   u32,
+    pub stages:  vk::ShaderStageFlags,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VertexInput {
+    pub location: u32,
+    pub format:   vk::Format,
+}
+
+/// The interface of one shader stage, as declared in its SPIR-V.
+#[derive(Debug, Clone)]
+pub struct ShaderReflection {
+    pub stage:          vk::ShaderStageFlags,
+    pub push_constants: Option<vk::PushConstantRange>,
+    pub bindings:       Vec<DescriptorBinding>,
+    /// only filled in for vertex shaders, sorted by location
+    pub inputs:         Vec<VertexInput>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Type {
+    Scalar{ float: bool, signed: bool, width: u32 },
+    Vector{ component: u32, count: u32 },
+    Matrix{ column: u32, count: u32 },
+    Image{ dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array{ element: u32, length: u32 },
+    RuntimeArray{ element: u32 },
+    Struct,
+    Pointer{ pointee: u32 },
+}
+
+#[derive(Default)]
+struct Module {
+    stage:       vk::ShaderStageFlags,
+    types:       HashMap<u32, Type>,
+    members:     HashMap<u32, Vec<u32>>,
+    constants:   HashMap<u32, u32>,
+    variables:   Vec<(u32, u32, u32)>, // (pointer type, id, storage class)
+    decorations: HashMap<(u32, u32), u32>, // (id, decoration) -> first literal
+    member_decorations: HashMap<(u32, u32, u32), u32>, // (struct, member, decoration) -> first literal
+}
+
+impl Module {
+    fn parse(spv: &[u8]) -> Result<Self, String> {
+        if spv.len()%4 != 0 || spv.len() < 20 { return Err("not a SPIR-V module".into()) }
+        let mut words : Vec<u32> = spv.chunks_exact(4).map(|b| u32::from_le_bytes([b[0],b[1],b[2],b[3]])).collect();
+        if words[0] == MAGIC.swap_bytes() {
+            words.iter_mut().for_each(|word| *word = word.swap_bytes());
+        }
+        if words[0] != MAGIC { return Err("not a SPIR-V module".into()) }
+
+        let mut module = Module::default();
+        let mut i = 5;
+        while i < words.len() {
+            let (count, opcode) = ((words[i]>>16) as usize, (words[i]&0xFFFF) as u16);
+            if count == 0 || i+count > words.len() { return Err(format!("truncated instruction at word {i}")) }
+            let args = &words[i+1..i+count];
+            let arg = |n: usize| args.get(n).copied().ok_or_else(|| format!("malformed instruction at word {i}"));
+            match opcode {
+                op::ENTRY_POINT if module.stage.is_empty() => {
+                    module.stage = match arg(0)? {
+                        0 => vk::ShaderStageFlags::VERTEX,
+                        1 => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+                        2 => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+                        3 => vk::ShaderStageFlags::GEOMETRY,
+                        4 => vk::ShaderStageFlags::FRAGMENT,
+                        5 => vk::ShaderStageFlags::COMPUTE,
+                        model => return Err(format!("unsupported execution model {model}")),
+                    };
+                },
+                op::TYPE_INT     => { module.types.insert(arg(0)?, Type::Scalar{ float: false, signed: arg(2)?==1, width: arg(1)? }); },
+                op::TYPE_FLOAT   => { module.types.insert(arg(0)?, Type::Scalar{ float: true, signed: true, width: arg(1)? }); },
+                op::TYPE_VECTOR  => { module.types.insert(arg(0)?, Type::Vector{ component: arg(1)?, count: arg(2)? }); },
+                op::TYPE_MATRIX  => { module.types.insert(arg(0)?, Type::Matrix{ column: arg(1)?, count: arg(2)? }); },
+                op::TYPE_IMAGE   => { module.types.insert(arg(0)?, Type::Image{ dim: arg(2)?, sampled: arg(6)? }); },
+                op::TYPE_SAMPLER => { module.types.insert(arg(0)?, Type::Sampler); },
+                op::TYPE_SAMPLED_IMAGE => { module.types.insert(arg(0)?, Type::SampledImage); },
+                op::TYPE_ARRAY   => { module.types.insert(arg(0)?, Type::Array{ element: arg(1)?, length: arg(2)? }); },
+                op::TYPE_RUNTIME_ARRAY => { module.types.insert(arg(0)?, Type::RuntimeArray{ element: arg(1)? }); },
+                op::TYPE_STRUCT  => {
+                    module.types.insert(arg(0)?, Type::Struct);
+                    module.members.insert(arg(0)?, args[1..].to_vec());
+                },
+                op::TYPE_POINTER => { module.types.insert(arg(0)?, Type::Pointer{ pointee: arg(2)? }); },
+                op::CONSTANT     => { module.constants.insert(arg(1)?, arg(2)?); },
+                op::VARIABLE     => { module.variables.push((arg(0)?, arg(1)?, arg(2)?)); },
+                op::DECORATE     => { module.decorations.insert((arg(0)?, arg(1)?), args.get(2).copied().unwrap_or(0)); },
+                op::MEMBER_DECORATE => { module.member_decorations.insert((arg(0)?, arg(1)?, arg(2)?), args.get(3).copied().unwrap_or(0)); },
+                _ => (),
+            }
+            i += count;
+        }
+        if module.stage.is_empty() { return Err("module has no entry point".into()) }
+        Ok(module)
+    }
+
+    fn ty(&self, id: u32) -> Result<Type, String> {
+        self.types.get(&id).copied().ok_or_else(|| format!("unknown type %{id}"))
+    }
+    fn decoration(&self, id: u32, decoration: u32) -> Option<u32> {
+        self.decorations.get(&(id, decoration)).copied()
+    }
+
+    /// size in bytes of a type inside a buffer block, as laid out by its offset decorations
+    fn size_of(&self, id: u32) -> Result<u32, String> {
+        Ok(match self.ty(id)? {
+            Type::Scalar{ width, .. }  => width/8,
+            Type::Vector{ component, count } => count*self.size_of(component)?,
+            Type::Matrix{ column, count } => count*self.size_of(column)?,
+            Type::Array{ element, length } => {
+                let length = self.constants.get(&length).copied().unwrap_or(0);
+                let stride = match self.decoration(id, decoration::ARRAY_STRIDE) {
+                    Some(stride) => stride,
+                    None => self.size_of(element)?,
+                };
+                length*stride
+            },
+            Type::Struct => {
+                let mut size = 0;
+                for (member, ty) in self.members[&id].iter().enumerate() {
+                    let offset = self.member_decorations.get(&(id, member as u32, decoration::OFFSET)).copied().unwrap_or(0);
+                    let member_size = match (self.ty(*ty)?, self.member_decorations.get(&(id, member as u32, decoration::MATRIX_STRIDE))) {
+                        (Type::Matrix{ count, .. }, Some(stride)) => count*stride,
+                        _ => self.size_of(*ty)?,
+                    };
+                    size = size.max(offset+member_size);
+                }
+                size
+            },
+            Type::RuntimeArray{..} => 0,
+            ty => return Err(format!("{ty:?} has no size")),
+        })
+    }
+
+    fn descriptor_type(&self, storage_class: u32, id: u32) -> Result<vk::DescriptorType, String> {
+        Ok(match self.ty(id)? {
+            Type::Array{ element, .. } | Type::RuntimeArray{ element } => self.descriptor_type(storage_class, element)?,
+            Type::Sampler      => vk::DescriptorType::SAMPLER,
+            Type::SampledImage => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            Type::Image{ dim: DIM_SUBPASS_DATA, .. } => vk::DescriptorType::INPUT_ATTACHMENT,
+            Type::Image{ dim: DIM_BUFFER, sampled: 2 } => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+            Type::Image{ dim: DIM_BUFFER, .. } => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+            Type::Image{ sampled: 2, .. } => vk::DescriptorType::STORAGE_IMAGE,
+            Type::Image{ .. } => vk::DescriptorType::SAMPLED_IMAGE,
+            Type::Struct if storage_class == storage::STORAGE_BUFFER => vk::DescriptorType::STORAGE_BUFFER,
+            Type::Struct if self.decoration(id, decoration::BUFFER_BLOCK).is_some() => vk::DescriptorType::STORAGE_BUFFER,
+            Type::Struct => vk::DescriptorType::UNIFORM_BUFFER,
+            ty => return Err(format!("{ty:?} can't be bound to a descriptor")),
+        })
+    }
+
+    fn descriptor_count(&self, id: u32) -> Result<u32, String> {
+        Ok(match self.ty(id)? {
+            Type::Array{ element, length } => self.constants.get(&length).copied().unwrap_or(1)*self.descriptor_count(element)?,
+            Type::RuntimeArray{..} => 0,
+            _ => 1,
+        })
+    }
+
+    fn input_format(&self, id: u32) -> Result<vk::Format, String> {
+        let (component, count) = match self.ty(id)? {
+            Type::Vector{ component, count } => (component, count),
+            Type::Scalar{..} => (id, 1),
+            ty => return Err(format!("{ty:?} is not a valid vertex input")),
+        };
+        let Type::Scalar{ float, signed, width } = self.ty(component)? else { unreachable!() };
+        use vk::Format as F;
+        let formats = match (float, signed, width) {
+            (true,  _,     32) => [F::R32_SFLOAT, F::R32G32_SFLOAT, F::R32G32B32_SFLOAT, F::R32G32B32A32_SFLOAT],
+            (true,  _,     64) => [F::R64_SFLOAT, F::R64G64_SFLOAT, F::R64G64B64_SFLOAT, F::R64G64B64A64_SFLOAT],
+            (false, true,  32) => [F::R32_SINT,   F::R32G32_SINT,   F::R32G32B32_SINT,   F::R32G32B32A32_SINT],
+            (false, false, 32) => [F::R32_UINT,   F::R32G32_UINT,   F::R32G32B32_UINT,   F::R32G32B32A32_UINT],
+            _ => return Err(format!("unsupported {width} bit vertex input")),
+        };
+        Ok(formats[count as usize - 1])
+    }
+}
+
+impl ShaderReflection {
+    pub fn new(spv: &[u8]) -> Result<Self, String> {
+        let module = Module::parse(spv)?;
+        let mut reflection = ShaderReflection{ stage: module.stage, push_constants: None, bindings: Vec::new(), inputs: Vec::new() };
+        for &(pointer, id, storage_class) in &module.variables {
+            let Type::Pointer{ pointee } = module.ty(pointer)? else { return Err(format!("variable %{id} is not a pointer")) };
+            match storage_class {
+                storage::PUSH_CONSTANT => {
+                    // push constant blocks usually start at offset 0, but may skip the part another stage uses
+                    let offset = module.members[&pointee].iter().enumerate()
+                        .filter_map(|(member,_)| module.member_decorations.get(&(pointee, member as u32, decoration::OFFSET)).copied())
+                        .min().unwrap_or(0);
+                    let size = module.size_of(pointee)?;
+                    reflection.push_constants = Some(vk::PushConstantRange{ stage_flags: module.stage, offset, size: size-offset });
+                },
+                storage::UNIFORM_CONSTANT | storage::UNIFORM | storage::STORAGE_BUFFER => {
+                    let (Some(set), Some(binding)) = (module.decoration(id, decoration::DESCRIPTOR_SET), module.decoration(id, decoration::BINDING)) else {
+                        continue
+                    };
+                    reflection.bindings.push(DescriptorBinding{
+                        set, binding,
+                        ty:     module.descriptor_type(storage_class, pointee)?,
+                        count:  module.descriptor_count(pointee)?,
+                        stages: module.stage,
+                    });
+                },
+                storage::INPUT if module.stage == vk::ShaderStageFlags::VERTEX => {
+                    if module.decoration(id, decoration::BUILT_IN).is_some() { continue }
+                    let Some(location) = module.decoration(id, decoration::LOCATION) else {
+                        return Err(format!("vertex input %{id} has no location"))
+                    };
+                    reflection.inputs.push(VertexInput{ location, format: module.input_format(pointee)? });
+                },
+                _ => (),
+            }
+        }
+        reflection.bindings.sort_by_key(|binding| (binding.set, binding.binding));
+        reflection.inputs.sort_by_key(|input| input.location);
+        Ok(reflection)
+    }
+
+    /// Checks that `V` provides every input this vertex shader reads, with a matching numeric type.
+    pub fn validate_vertex_input<V: VertexLayout>(&self) -> Result<(), String> {
+        validate_vertex_input::<V>(&self.inputs)
+    }
+}
+
+/// Combines the bindings of several stages, a binding used by more than one stage has to agree on its type.
+pub fn merge_bindings(reflections: &[&ShaderReflection]) -> Result<Vec<DescriptorBinding>, String> {
+    let mut merged : Vec<DescriptorBinding> = Vec::new();
+    for binding in reflections.iter().flat_map(|reflection| reflection.bindings.iter()) {
+        match merged.iter_mut().find(|other| (other.set, other.binding) == (binding.set, binding.binding)) {
+            None => merged.push(*binding),
+            Some(other) if other.ty == binding.ty => {
+                other.stages |= binding.stages;
+                other.count = other.count.max(binding.count);
+            },
+            Some(other) => return Err(format!("set {} binding {} is a {:?} in {:?} but a {:?} in {:?}",
+                binding.set, binding.binding, other.ty, other.stages, binding.ty, binding.stages)),
+        }
+    }
+    merged.sort_by_key(|binding| (binding.set, binding.binding));
+    Ok(merged)
+}
+
+/// The vertex buffer layout of a Rust vertex type, `ATTRIBUTES[location] = (offset, format)`.
+pub trait VertexLayout {
+    const STRIDE: u32;
+    const ATTRIBUTES: &'static [(u32, vk::Format)];
+}
+
+/// for shaders without vertex inputs
+impl VertexLayout for () {
+    const STRIDE: u32 = 0;
+    const ATTRIBUTES: &'static [(u32, vk::Format)] = &[];
+}
+
+impl VertexLayout for common::Vertex {
+    const STRIDE: u32 = core::mem::size_of::<common::Vertex>() as u32;
+    const ATTRIBUTES: &'static [(u32, vk::Format)] = &[
+        (0, vk::Format::R16G16_SINT),    // x, y
+        (4, vk::Format::R16G16_UINT),    // u, v
+        (8, vk::Format::R16G16B16A16_SFLOAT),  // color, linear
+        (16, vk::Format::R16G16_SINT),         // local, 1/8 pixels
+        (20, vk::Format::R16G16B16A16_UINT),   // shape
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NumericType { Float, Integer, Double }
+
+/// Integers only have to agree in width, SPIR-V signedness is decided per instruction.
+fn numeric_type(format: vk::Format) -> Option<NumericType> {
+    use vk::Format as F;
+    Some(match format {
+        F::R32_SINT | F::R32G32_SINT | F::R32G32B32_SINT | F::R32G32B32A32_SINT |
+        F::R32_UINT | F::R32G32_UINT | F::R32G32B32_UINT | F::R32G32B32A32_UINT |
+        F::R16_SINT | F::R16G16_SINT | F::R16G16B16_SINT | F::R16G16B16A16_SINT |
+        F::R16_UINT | F::R16G16_UINT | F::R16G16B16_UINT | F::R16G16B16A16_UINT |
+        F::R8_SINT  | F::R8G8_SINT   | F::R8G8B8_SINT    | F::R8G8B8A8_SINT     |
+        F::R8_UINT  | F::R8G8_UINT   | F::R8G8B8_UINT    | F::R8G8B8A8_UINT     => NumericType::Integer,
+        F::R64_SFLOAT | F::R64G64_SFLOAT | F::R64G64B64_SFLOAT | F::R64G64B64A64_SFLOAT => NumericType::Double,
+        F::UNDEFINED => return None,
+        _ => NumericType::Float,
+    })
+}
+
+pub(crate) fn validate_vertex_input<V: VertexLayout>(inputs: &[VertexInput]) -> Result<(), String> {
+    let mut errors = Vec::new();
+    for input in inputs {
+        match V::ATTRIBUTES.get(input.location as usize) {
+            None => errors.push(format!("location {} is read by the shader, but missing from {}", input.location, core::any::type_name::<V>())),
+            Some((offset, format)) if numeric_type(*format) != numeric_type(input.format) => errors.push(format!(
+                "location {} is a {:?} in the shader, but {} has a {format:?} at offset {offset}",
+                input.location, input.format, core::any::type_name::<V>())),
+            Some(_) => (),
+        }
+    }
+    if errors.is_empty() { Ok(()) } else { Err(errors.join("\n")) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // hand assembled equivalent of
+    //   layout(location=0) in ivec2 pos;  layout(location=1) in ivec2 uv;  layout(location=2) in vec4 color;
+    //   layout(push_constant) uniform _ { vec2 scale; vec2 offset; };
+    //   layout(set=0, binding=1) uniform sampler2D tex;
+    fn vertex_module() -> Vec<u8> {
+        let mut words = vec![MAGIC, 0x0001_0000, 0, 100, 0];
+        let mut inst = |opcode: u16, args: &[u32]| {
+            words.push(((args.len() as u32 + 1)<<16) | opcode as u32);
+            words.extend_from_slice(args);
+        };
+        inst(op::ENTRY_POINT, &[0, 1, u32::from_le_bytes(*b"main"), 0, 10, 11, 14]);
+        inst(op::DECORATE, &[10, decoration::LOCATION, 0]);
+        inst(op::DECORATE, &[11, decoration::LOCATION, 1]);
+        inst(op::DECORATE, &[14, decoration::LOCATION, 2]);
+        inst(op::DECORATE, &[12, decoration::DESCRIPTOR_SET, 0]);
+        inst(op::DECORATE, &[12, decoration::BINDING, 1]);
+        inst(op::MEMBER_DECORATE, &[30, 0, decoration::OFFSET, 0]);
+        inst(op::MEMBER_DECORATE, &[30, 1, decoration::OFFSET, 8]);
+        inst(op::TYPE_INT,    &[20, 32, 1]);
+        inst(op::TYPE_FLOAT,  &[21, 32]);
+        inst(op::TYPE_VECTOR, &[22, 20, 2]);
+        inst(op::TYPE_VECTOR, &[23, 21, 4]);
+        inst(op::TYPE_VECTOR, &[24, 21, 2]);
+        inst(op::TYPE_STRUCT, &[30, 24, 24]);
+        inst(op::TYPE_IMAGE,  &[31, 21, 1, 0, 0, 0, 1, 0]);
+        inst(op::TYPE_SAMPLED_IMAGE, &[32, 31]);
+        inst(op::TYPE_POINTER, &[40, storage::INPUT, 22]);
+        inst(op::TYPE_POINTER, &[41, storage::INPUT, 23]);
+        inst(op::TYPE_POINTER, &[42, storage::PUSH_CONSTANT, 30]);
+        inst(op::TYPE_POINTER, &[43, storage::UNIFORM_CONSTANT, 32]);
+        inst(op::VARIABLE, &[40, 10, storage::INPUT]);
+        inst(op::VARIABLE, &[40, 11, storage::INPUT]);
+        inst(op::VARIABLE, &[41, 14, storage::INPUT]);
+        inst(op::VARIABLE, &[42, 13, storage::PUSH_CONSTANT]);
+        inst(op::VARIABLE, &[43, 12, storage::UNIFORM_CONSTANT]);
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn reflects_vertex_interface() {
+        let reflection = ShaderReflection::new(&vertex_module()).unwrap();
+        assert_eq!(reflection.stage, vk::ShaderStageFlags::VERTEX);
+        let push_constants = reflection.push_constants.unwrap();
+        assert_eq!((push_constants.stage_flags, push_constants.offset, push_constants.size), (vk::ShaderStageFlags::VERTEX, 0, 16));
+        assert_eq!(reflection.bindings, [DescriptorBinding{
+            set: 0, binding: 1, ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER, count: 1, stages: vk::ShaderStageFlags::VERTEX }]);
+        assert_eq!(reflection.inputs, [
+            VertexInput{ location: 0, format: vk::Format::R32G32_SINT },
+            VertexInput{ location: 1, format: vk::Format::R32G32_SINT },
+            VertexInput{ location: 2, format: vk::Format::R32G32B32A32_SFLOAT },
+        ]);
+    }
+
+    #[test]
+    fn validates_against_rust_vertex() {
+        struct Swapped;
+        impl VertexLayout for Swapped {
+            const STRIDE: u32 = 12;
+            const ATTRIBUTES: &'static [(u32, vk::Format)] = &[(0, vk::Format::R8G8B8A8_UNORM), (4, vk::Format::R16G16_UINT), (8, vk::Format::R16G16_SINT)];
+        }
+        let reflection = ShaderReflection::new(&vertex_module()).unwrap();
+        assert!(reflection.validate_vertex_input::<common::Vertex>().is_ok());
+        assert!(reflection.validate_vertex_input::<Swapped>().is_err());
+        assert!(reflection.validate_vertex_input::<()>().is_err());
+    }
+}
@@ -0,0 +1,311 @@
+use ash::vk;
+
+/// Declares how a resource is about to be used.
+/// The tracker derives stages, access masks and layouts from this.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Usage {
+    TransferSrc,
+    TransferDst,
+    SampledVertex,
+    SampledFragment,
+    ColorAttachment,
+    DepthStencilAttachment,
+    DepthStencilRead,
+    Present,
+    VertexBuffer,
+    IndexBuffer,
+    HostRead,
+    SampledCompute,
+    ComputeImageRead,
+    ComputeImageWrite,
+    ComputeBufferRead,
+    ComputeBufferWrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsageInfo {
+    pub stage:  vk::PipelineStageFlags,
+    pub access: vk::AccessFlags,
+    pub layout: vk::ImageLayout,
+}
+
+const WRITE_ACCESS : vk::AccessFlags = vk::AccessFlags::from_raw(
+    vk::AccessFlags::SHADER_WRITE.as_raw()
+  | vk::AccessFlags::COLOR_ATTACHMENT_WRITE.as_raw()
+  | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw()
+  | vk::AccessFlags::TRANSFER_WRITE.as_raw()
+  | vk::AccessFlags::HOST_WRITE.as_raw()
+  | vk::AccessFlags::MEMORY_WRITE.as_raw());
+
+impl Usage {
+    pub const fn info(self) -> UsageInfo {
+        use vk::{PipelineStageFlags as S, AccessFlags as A, ImageLayout as L};
+        let (stage, access, layout) = match self {
+            Usage::TransferSrc            => (S::TRANSFER, A::TRANSFER_READ, L::TRANSFER_SRC_OPTIMAL),
+            Usage::TransferDst            => (S::TRANSFER, A::TRANSFER_WRITE, L::TRANSFER_DST_OPTIMAL),
+            Usage::SampledVertex          => (S::VERTEX_SHADER, A::SHADER_READ, L::SHADER_READ_ONLY_OPTIMAL),
+            Usage::SampledFragment        => (S::FRAGMENT_SHADER, A::SHADER_READ, L::SHADER_READ_ONLY_OPTIMAL),
+            Usage::ColorAttachment        => (S::COLOR_ATTACHMENT_OUTPUT,
+                                              A::from_raw(A::COLOR_ATTACHMENT_READ.as_raw() | A::COLOR_ATTACHMENT_WRITE.as_raw()),
+                                              L::COLOR_ATTACHMENT_OPTIMAL),
+            Usage::DepthStencilAttachment => (S::from_raw(S::EARLY_FRAGMENT_TESTS.as_raw() | S::LATE_FRAGMENT_TESTS.as_raw()),
+                                              A::from_raw(A::DEPTH_STENCIL_ATTACHMENT_READ.as_raw() | A::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw()),
+                                              L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+            Usage::DepthStencilRead       => (S::from_raw(S::EARLY_FRAGMENT_TESTS.as_raw() | S::LATE_FRAGMENT_TESTS.as_raw()),
+                                              A::DEPTH_STENCIL_ATTACHMENT_READ,
+                                              L::DEPTH_STENCIL_READ_ONLY_OPTIMAL),
+            // the semaphore handed to vkQueuePresentKHR takes care of visibility
+            Usage::Present                => (S::BOTTOM_OF_PIPE, A::NONE, L::PRESENT_SRC_KHR),
+            // buffers have no layout, UNDEFINED never causes a transition between buffer usages
+            Usage::VertexBuffer           => (S::VERTEX_INPUT, A::VERTEX_ATTRIBUTE_READ, L::UNDEFINED),
+            Usage::IndexBuffer            => (S::VERTEX_INPUT, A::INDEX_READ, L::UNDEFINED),
+            Usage::HostRead               => (S::HOST, A::HOST_READ, L::UNDEFINED),
+            Usage::SampledCompute         => (S::COMPUTE_SHADER, A::SHADER_READ, L::SHADER_READ_ONLY_OPTIMAL),
+            // storage images have to be in GENERAL
+            Usage::ComputeImageRead       => (S::COMPUTE_SHADER, A::SHADER_READ, L::GENERAL),
+            Usage::ComputeImageWrite      => (S::COMPUTE_SHADER, A::from_raw(A::SHADER_READ.as_raw() | A::SHADER_WRITE.as_raw()), L::GENERAL),
+            Usage::ComputeBufferRead      => (S::COMPUTE_SHADER, A::SHADER_READ, L::UNDEFINED),
+            Usage::ComputeBufferWrite     => (S::COMPUTE_SHADER, A::from_raw(A::SHADER_READ.as_raw() | A::SHADER_WRITE.as_raw()), L::UNDEFINED),
+        };
+        UsageInfo{ stage, access, layout }
+    }
+    pub const fn is_write(self) -> bool {
+        self.info().access.as_raw() & WRITE_ACCESS.as_raw() != 0
+    }
+}
+
+/// A single execution+memory dependency, as recorded by `vkCmdPipelineBarrier`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Barrier {
+    pub src_stage:  vk::PipelineStageFlags,
+    pub dst_stage:  vk::PipelineStageFlags,
+    pub src_access: vk::AccessFlags,
+    pub dst_access: vk::AccessFlags,
+    pub old_layout: vk::ImageLayout,
+    pub new_layout: vk::ImageLayout,
+}
+
+/// Tracks the last write to a resource, which stages have seen it since, and the current layout.
+/// `transition` returns the smallest barrier that makes the next usage safe, or None.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceState {
+    pub layout: vk::ImageLayout,
+    written:        bool,
+    write_stage:    vk::PipelineStageFlags,
+    write_access:   vk::AccessFlags,
+    visible_stages: vk::PipelineStageFlags,
+    visible_access: vk::AccessFlags,
+    read_stages:    vk::PipelineStageFlags,
+}
+
+impl Default for ResourceState {
+    fn default() -> Self { Self::UNDEFINED }
+}
+
+impl ResourceState {
+    pub const UNDEFINED : Self = Self{
+        layout:         vk::ImageLayout::UNDEFINED,
+        written:        false,
+        write_stage:    vk::PipelineStageFlags::empty(),
+        write_access:   vk::AccessFlags::empty(),
+        visible_stages: vk::PipelineStageFlags::empty(),
+        visible_access: vk::AccessFlags::empty(),
+        read_stages:    vk::PipelineStageFlags::empty(),
+    };
+
+    /// State of a resource whose contents are garbage, and which is only safe to use after `stage`
+    /// e.g. a freshly acquired swapchain image that is guarded by a semaphore wait.
+    pub const fn after_semaphore(stage: vk::PipelineStageFlags) -> Self {
+        Self{ read_stages: stage, ..Self::UNDEFINED }
+    }
+
+    /// Forget the contents, the next transition starts from UNDEFINED.
+    /// Outstanding accesses are still waited on.
+    pub fn discard(&mut self){
+        self.layout = vk::ImageLayout::UNDEFINED;
+    }
+
+    pub fn transition(&mut self, usage: Usage) -> Option<Barrier> {
+        let next = usage.info();
+        let is_write = usage.is_write();
+        let layout_change = next.layout != self.layout;
+
+        if !is_write && !layout_change {
+            // read after read: nothing to do unless a previous write is not yet visible to this stage
+            let visible = self.visible_stages.contains(next.stage) && self.visible_access.contains(next.access);
+            self.read_stages |= next.stage;
+            if !self.written || visible { return None }
+            self.visible_stages |= next.stage;
+            self.visible_access |= next.access;
+            return Some(Barrier{
+                src_stage:  self.write_stage,
+                dst_stage:  next.stage,
+                src_access: self.write_access,
+                dst_access: next.access,
+                old_layout: self.layout,
+                new_layout: next.layout,
+            });
+        }
+
+        // write-after-x or layout transition: wait on everything that touched the resource
+        let src_stage = self.write_stage | self.read_stages;
+        let barrier = Barrier{
+            src_stage:  if src_stage.is_empty() { vk::PipelineStageFlags::TOP_OF_PIPE } else { src_stage },
+            dst_stage:  next.stage,
+            src_access: self.write_access,
+            dst_access: next.access,
+            old_layout: self.layout,
+            new_layout: next.layout,
+        };
+        *self = Self{
+            layout:         next.layout,
+            written:        true,
+            write_stage:    next.stage,
+            // a layout transition is a write that has already been made visible to `next`
+            write_access:   if is_write { next.access & WRITE_ACCESS } else { vk::AccessFlags::empty() },
+            visible_stages: if is_write { vk::PipelineStageFlags::empty() } else { next.stage },
+            visible_access: if is_write { vk::AccessFlags::empty() } else { next.access },
+            read_stages:    vk::PipelineStageFlags::empty(),
+        };
+        Some(barrier)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TrackedImage {
+    pub state: ResourceState,
+    pub range: vk::ImageSubresourceRange,
+    pub format: vk::Format,
+    pub usage: vk::ImageUsageFlags, // empty if unknown
+}
+
+pub(crate) fn cmd_image_barrier(device: &ash::Device, cmd: vk::CommandBuffer, image: vk::Image, range: vk::ImageSubresourceRange, barrier: Barrier){
+    let image_memory_barriers = [vk::ImageMemoryBarrier::default()
+        .image(image)
+        .old_layout(barrier.old_layout)
+        .new_layout(barrier.new_layout)
+        .src_access_mask(barrier.src_access)
+        .dst_access_mask(barrier.dst_access)
+        .subresource_range(range)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+    ];
+    unsafe{device.cmd_pipeline_barrier(cmd,
+        barrier.src_stage,
+        barrier.dst_stage,
+        vk::DependencyFlags::empty(),
+        &[], &[], &image_memory_barriers)};
+}
+
+pub(crate) fn cmd_buffer_barrier(device: &ash::Device, cmd: vk::CommandBuffer, buffer: vk::Buffer, barrier: Barrier){
+    let buffer_memory_barriers = [vk::BufferMemoryBarrier::default()
+        .buffer(buffer)
+        .offset(0)
+        .size(vk::WHOLE_SIZE)
+        .src_access_mask(barrier.src_access)
+        .dst_access_mask(barrier.dst_access)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+    ];
+    unsafe{device.cmd_pipeline_barrier(cmd,
+        barrier.src_stage,
+        barrier.dst_stage,
+        vk::DependencyFlags::empty(),
+        &[], &buffer_memory_barriers, &[])};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vk::{PipelineStageFlags as S, AccessFlags as A, ImageLayout as L};
+
+    #[test]
+    fn upload_then_sample() {
+        let mut state = ResourceState::UNDEFINED;
+        let b = state.transition(Usage::TransferDst).unwrap();
+        assert_eq!((b.src_stage, b.dst_stage), (S::TOP_OF_PIPE, S::TRANSFER));
+        assert_eq!((b.old_layout, b.new_layout), (L::UNDEFINED, L::TRANSFER_DST_OPTIMAL));
+        assert_eq!(b.src_access, A::empty());
+
+        let b = state.transition(Usage::SampledFragment).unwrap();
+        assert_eq!((b.src_stage, b.dst_stage), (S::TRANSFER, S::FRAGMENT_SHADER));
+        assert_eq!((b.src_access, b.dst_access), (A::TRANSFER_WRITE, A::SHADER_READ));
+        assert_eq!((b.old_layout, b.new_layout), (L::TRANSFER_DST_OPTIMAL, L::SHADER_READ_ONLY_OPTIMAL));
+    }
+
+    #[test]
+    fn repeated_reads_are_free() {
+        let mut state = ResourceState::UNDEFINED;
+        state.transition(Usage::TransferDst);
+        assert!(state.transition(Usage::SampledFragment).is_some());
+        assert!(state.transition(Usage::SampledFragment).is_none());
+        assert!(state.transition(Usage::SampledFragment).is_none());
+    }
+
+    #[test]
+    fn write_made_visible_to_new_stage() {
+        let mut state = ResourceState::UNDEFINED;
+        state.transition(Usage::TransferDst);
+        state.transition(Usage::SampledFragment);
+        // same layout, but the vertex stage has not seen the transfer write and layout transition yet
+        let b = state.transition(Usage::SampledVertex).unwrap();
+        assert_eq!(b.old_layout, b.new_layout);
+        assert_eq!((b.src_stage, b.src_access), (S::FRAGMENT_SHADER, A::empty()));
+        assert_eq!((b.dst_stage, b.dst_access), (S::VERTEX_SHADER, A::SHADER_READ));
+        assert!(state.transition(Usage::SampledVertex).is_none());
+    }
+
+    #[test]
+    fn write_after_read_waits_on_readers() {
+        let mut state = ResourceState::UNDEFINED;
+        state.transition(Usage::TransferDst);
+        state.transition(Usage::SampledFragment);
+        state.transition(Usage::SampledVertex);
+        let b = state.transition(Usage::TransferDst).unwrap();
+        assert_eq!(b.src_stage, S::FRAGMENT_SHADER | S::VERTEX_SHADER);
+        assert_eq!(b.src_access, A::empty());
+        assert_eq!(b.old_layout, L::SHADER_READ_ONLY_OPTIMAL);
+    }
+
+    #[test]
+    fn unwritten_buffers_need_no_barriers() {
+        let mut state = ResourceState::UNDEFINED;
+        assert!(state.transition(Usage::VertexBuffer).is_none());
+        assert!(state.transition(Usage::IndexBuffer).is_none());
+    }
+
+    #[test]
+    fn compute_output_read_by_graphics() {
+        let mut state = ResourceState::UNDEFINED;
+        state.transition(Usage::ComputeBufferWrite);
+        let b = state.transition(Usage::VertexBuffer).unwrap();
+        assert_eq!((b.src_stage, b.dst_stage), (S::COMPUTE_SHADER, S::VERTEX_INPUT));
+        assert_eq!((b.src_access, b.dst_access), (A::SHADER_WRITE, A::VERTEX_ATTRIBUTE_READ));
+        assert!(state.transition(Usage::VertexBuffer).is_none());
+        // the next dispatch has to wait for the draw to finish reading
+        let b = state.transition(Usage::ComputeBufferWrite).unwrap();
+        assert_eq!(b.src_stage, S::COMPUTE_SHADER | S::VERTEX_INPUT);
+    }
+
+    #[test]
+    fn swapchain_cycle() {
+        let mut state = ResourceState::after_semaphore(S::COLOR_ATTACHMENT_OUTPUT);
+        let b = state.transition(Usage::ColorAttachment).unwrap();
+        assert_eq!((b.src_stage, b.dst_stage), (S::COLOR_ATTACHMENT_OUTPUT, S::COLOR_ATTACHMENT_OUTPUT));
+        assert_eq!(b.old_layout, L::UNDEFINED);
+        assert!(state.transition(Usage::ColorAttachment).is_some()); // write after write
+        let b = state.transition(Usage::Present).unwrap();
+        assert_eq!(b.src_access, A::COLOR_ATTACHMENT_WRITE);
+        assert_eq!(b.new_layout, L::PRESENT_SRC_KHR);
+    }
+
+    #[test]
+    fn discard_skips_layout_preservation() {
+        let mut state = ResourceState::UNDEFINED;
+        state.transition(Usage::TransferDst);
+        state.transition(Usage::SampledFragment);
+        state.discard();
+        let b = state.transition(Usage::SampledFragment).unwrap();
+        assert_eq!(b.old_layout, L::UNDEFINED);
+    }
+}
@@ -0,0 +1,123 @@
+use ash::vk::{self, ShaderEXT};
+
+/// How many state commands a `Frame` recorded, and how many it dropped because
+/// the value was already set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommandStats {
+    pub emitted: u64,
+    pub skipped: u64,
+}
+impl CommandStats {
+    /// counts one command, passes `changed` through
+    pub(crate) fn count(&mut self, changed: bool) -> bool {
+        if changed { self.emitted += 1 } else { self.skipped += 1 }
+        changed
+    }
+}
+
+/// The last value recorded for one piece of command buffer state.
+#[derive(Debug, Clone)]
+pub(crate) struct Cached<T>(Option<T>);
+impl<T> Default for Cached<T> {
+    fn default() -> Self { Self(None) }
+}
+impl<T: PartialEq> Cached<T> {
+    /// records `value`, returns whether it differs from the previous one
+    pub(crate) fn replace(&mut self, value: T) -> bool {
+        if self.0.as_ref() == Some(&value) { return false }
+        self.0 = Some(value);
+        true
+    }
+    pub(crate) fn get(&self) -> Option<&T> { self.0.as_ref() }
+}
+
+/// Per-face state, the front and back values are cached separately
+/// since `faces` may select only one of them.
+pub(crate) fn replace_faces<T: PartialEq + Copy>(cache: &mut [Cached<T>;2], faces: vk::StencilFaceFlags, value: T) -> bool {
+    let front = faces.contains(vk::StencilFaceFlags::FRONT) && cache[0].replace(value);
+    let back  = faces.contains(vk::StencilFaceFlags::BACK)  && cache[1].replace(value);
+    front || back
+}
+
+// vk::Viewport and vk::ColorBlendEquationEXT don't implement PartialEq
+pub(crate) fn viewport_key(viewport: &vk::Viewport) -> [f32;6] {
+    [viewport.x, viewport.y, viewport.width, viewport.height, viewport.min_depth, viewport.max_depth]
+}
+pub(crate) fn blend_equation_key(eq: &vk::ColorBlendEquationEXT) -> [i32;6] {
+    [eq.src_color_blend_factor.as_raw(), eq.dst_color_blend_factor.as_raw(), eq.color_blend_op.as_raw(),
+     eq.src_alpha_blend_factor.as_raw(), eq.dst_alpha_blend_factor.as_raw(), eq.alpha_blend_op.as_raw()]
+}
+
+/// Everything a `Frame` has recorded into the command buffer so far.
+/// Dynamic state persists across `begin_rendering` calls, so this lives as long as the frame.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StateCache {
+    pub viewports:                Cached<Vec<[f32;6]>>,
+    pub scissors:                 Cached<Vec<vk::Rect2D>>,
+    pub polygon_mode:             Cached<vk::PolygonMode>,
+    pub primitive_topology:       Cached<vk::PrimitiveTopology>,
+    pub primitive_restart_enable: Cached<bool>,
+    pub depth_test_enable:        Cached<bool>,
+    pub depth_write_enable:       Cached<bool>,
+    pub depth_bias_enable:        Cached<bool>,
+    pub stencil_test_enable:      Cached<bool>,
+    pub rasterizer_discard_enable: Cached<bool>,
+    pub rasterization_samples:    Cached<vk::SampleCountFlags>,
+    pub sample_mask:              Cached<(vk::SampleCountFlags, Vec<vk::SampleMask>)>,
+    pub alpha_to_coverage_enable: Cached<bool>,
+    pub cull_mode:                Cached<vk::CullModeFlags>,
+    pub color_blend_enable:       Cached<Vec<u32>>,
+    pub color_blend_equation:     Cached<Vec<[i32;6]>>,
+    pub color_write_mask:         Cached<Vec<vk::ColorComponentFlags>>,
+    pub stencil_op:               [Cached<(vk::StencilOp, vk::StencilOp, vk::StencilOp, vk::CompareOp)>;2],
+    pub stencil_compare_mask:     [Cached<u32>;2],
+    pub stencil_write_mask:       [Cached<u32>;2],
+    pub stencil_reference:        [Cached<u32>;2],
+    pub front_face:               Cached<vk::FrontFace>,
+    pub depth_compare_op:         Cached<vk::CompareOp>,
+    pub depth_bounds_test_enable: Cached<bool>,
+    pub depth_bounds:             Cached<[f32;2]>,
+    pub depth_bias:               Cached<[f32;3]>,
+    pub blend_constants:          Cached<[f32;4]>,
+    pub logic_op_enable:          Cached<bool>,
+    pub logic_op:                 Cached<vk::LogicOp>,
+    pub line_width:               Cached<f32>,
+    pub line_rasterization_mode:  Cached<vk::LineRasterizationModeEXT>,
+    pub line_stipple_enable:      Cached<bool>,
+    pub conservative_rasterization_mode: Cached<vk::ConservativeRasterizationModeEXT>,
+    pub extra_primitive_overestimation_size: Cached<f32>,
+    pub tessellation_domain_origin: Cached<vk::TessellationDomainOrigin>,
+    pub patch_control_points:     Cached<u32>,
+    pub shaders:                  Vec<(vk::ShaderStageFlags, ShaderEXT)>, // per stage
+    pub vertex_input:             Cached<(u32, Vec<(u32, vk::Format)>)>,
+    pub descriptor_set:           Cached<(vk::DescriptorSet, vk::PipelineLayout)>,
+    pub pipeline:                 Cached<vk::Pipeline>, // only used without shader objects
+    pub compute_shader:           Cached<ShaderEXT>,
+    pub compute_descriptor_set:   Cached<(vk::DescriptorSet, vk::PipelineLayout)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_values_are_skipped() {
+        let mut stats = CommandStats::default();
+        let mut cull_mode = Cached::default();
+        assert!( stats.count(cull_mode.replace(vk::CullModeFlags::NONE)));
+        assert!(!stats.count(cull_mode.replace(vk::CullModeFlags::NONE)));
+        assert!( stats.count(cull_mode.replace(vk::CullModeFlags::BACK)));
+        assert!(!stats.count(cull_mode.replace(vk::CullModeFlags::BACK)));
+        assert_eq!(stats, CommandStats{ emitted: 2, skipped: 2 });
+    }
+
+    #[test]
+    fn faces_are_tracked_separately() {
+        let mut reference : [Cached<u32>;2] = Default::default();
+        assert!( replace_faces(&mut reference, vk::StencilFaceFlags::FRONT, 1));
+        // the back face was never set, so setting both has to emit
+        assert!( replace_faces(&mut reference, vk::StencilFaceFlags::FRONT_AND_BACK, 1));
+        assert!(!replace_faces(&mut reference, vk::StencilFaceFlags::BACK, 1));
+        assert!(!replace_faces(&mut reference, vk::StencilFaceFlags::FRONT_AND_BACK, 1));
+    }
+}
@@ -0,0 +1,255 @@
+use ash::vk;
+
+/// Brightness of SDR white on HDR outputs, as recommended by ITU-R BT.2408.
+pub const SDR_WHITE_NITS : f32 = 203.0;
+
+/// How shaders have to encode linear sRGB colors (`common::LinearRgba`) for the swapchain,
+/// selected in shaders with the `OUTPUT` define, see `shaders/color.glsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutputSpace {
+    /// 8 bit sRGB formats, the hardware encodes
+    Srgb,
+    /// FP16 scRGB, linear with 1.0 at 80 nits
+    ExtendedSrgbLinear,
+    /// 10 bit BT.2020 primaries with the ST 2084 (PQ) curve, blending is not linear
+    Hdr10,
+}
+
+impl OutputSpace {
+    pub fn from_color_space(color_space: vk::ColorSpaceKHR) -> Option<Self> {
+        Some(match color_space {
+            vk::ColorSpaceKHR::SRGB_NONLINEAR           => OutputSpace::Srgb,
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => OutputSpace::ExtendedSrgbLinear,
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT         => OutputSpace::Hdr10,
+            _ => return None,
+        })
+    }
+
+    /// Swapchain formats in order of preference.
+    pub const fn formats(self) -> &'static [vk::Format] {
+        match self {
+            OutputSpace::Srgb               => &[vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_SRGB],
+            OutputSpace::ExtendedSrgbLinear => &[vk::Format::R16G16B16A16_SFLOAT],
+            OutputSpace::Hdr10              => &[vk::Format::A2B10G10R10_UNORM_PACK32, vk::Format::A2R10G10B10_UNORM_PACK32],
+        }
+    }
+
+    /// The value of the `OUTPUT` shader define.
+    pub const fn define(self) -> &'static str {
+        match self {
+            OutputSpace::Srgb               => "OUTPUT_SRGB",
+            OutputSpace::ExtendedSrgbLinear => "OUTPUT_SCRGB",
+            OutputSpace::Hdr10              => "OUTPUT_HDR10",
+        }
+    }
+
+    /// Encodes a linear sRGB color like the shaders do, e.g. for clear colors.
+    pub fn encode(self, [r, g, b]: [f32; 3]) -> [f32; 3] {
+        match self {
+            OutputSpace::Srgb               => [r, g, b],
+            OutputSpace::ExtendedSrgbLinear => [r, g, b].map(|c| c*SDR_WHITE_NITS/80.0),
+            OutputSpace::Hdr10 => {
+                let rec2020 = [
+                    0.627404*r + 0.329283*g + 0.043313*b,
+                    0.069097*r + 0.919541*g + 0.011362*b,
+                    0.016391*r + 0.088013*g + 0.895595*b,
+                ];
+                rec2020.map(|c| pq(c*SDR_WHITE_NITS/10000.0))
+            },
+        }
+    }
+}
+
+/// SMPTE ST 2084 inverse EOTF, 1.0 is 10000 nits
+fn pq(y: f32) -> f32 {
+    let (m1, m2) = (2610.0/16384.0, 2523.0/32.0);
+    let (c1, c2, c3) = (3424.0/4096.0, 2413.0/128.0, 2392.0/128.0);
+    let y = y.clamp(0.0, 1.0).powf(m1);
+    ((c1 + c2*y)/(1.0 + c3*y)).powf(m2)
+}
+
+/// How the swapchain is created, see `Renderer::set_swapchain_config`.
+/// Anything the surface does not support falls back to something it does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwapchainConfig {
+    /// MAILBOX falls back to FIFO, IMMEDIATE to MAILBOX then FIFO, FIFO_RELAXED to FIFO
+    pub present_mode:    vk::PresentModeKHR,
+    /// in addition to COLOR_ATTACHMENT, unsupported flags are dropped
+    pub image_usage:     vk::ImageUsageFlags,
+    /// PRE_MULTIPLIED for transparent windows, falls back to OPAQUE
+    pub composite_alpha: vk::CompositeAlphaFlagsKHR,
+    /// falls back to SRGB_NONLINEAR, the others need VK_EXT_swapchain_colorspace.
+    /// Only the color spaces of `OutputSpace` are supported.
+    pub color_space:     vk::ColorSpaceKHR,
+    /// clamped to the surface's limits
+    pub image_count:     u32,
+}
+
+impl Default for SwapchainConfig {
+    /// Vsync, triple buffering, and TRANSFER_SRC for `Frame::capture`.
+    fn default() -> Self {
+        Self{
+            present_mode:    vk::PresentModeKHR::FIFO,
+            image_usage:     vk::ImageUsageFlags::TRANSFER_SRC,
+            composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
+            color_space:     vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            image_count:     3,
+        }
+    }
+}
+
+impl SwapchainConfig {
+    pub const fn with_present_mode(mut self, present_mode: vk::PresentModeKHR) -> Self {
+        self.present_mode = present_mode;
+        self
+    }
+    pub const fn with_usage(mut self, usage: vk::ImageUsageFlags) -> Self {
+        self.image_usage = vk::ImageUsageFlags::from_raw(self.image_usage.as_raw() | usage.as_raw());
+        self
+    }
+    pub const fn with_composite_alpha(mut self, composite_alpha: vk::CompositeAlphaFlagsKHR) -> Self {
+        self.composite_alpha = composite_alpha;
+        self
+    }
+    pub const fn with_color_space(mut self, color_space: vk::ColorSpaceKHR) -> Self {
+        self.color_space = color_space;
+        self
+    }
+    pub const fn with_image_count(mut self, image_count: u32) -> Self {
+        self.image_count = image_count;
+        self
+    }
+
+    pub(crate) fn present_mode(&self, supported: &[vk::PresentModeKHR]) -> vk::PresentModeKHR {
+        let fallbacks : &[_] = match self.present_mode {
+            vk::PresentModeKHR::IMMEDIATE => &[vk::PresentModeKHR::IMMEDIATE, vk::PresentModeKHR::MAILBOX],
+            mode => &[mode],
+        };
+        // FIFO is the only mode every surface has to support
+        fallbacks.iter().copied().find(|mode| supported.contains(mode)).unwrap_or(vk::PresentModeKHR::FIFO)
+    }
+
+    pub(crate) fn composite_alpha(&self, supported: vk::CompositeAlphaFlagsKHR) -> vk::CompositeAlphaFlagsKHR {
+        [self.composite_alpha, vk::CompositeAlphaFlagsKHR::OPAQUE, vk::CompositeAlphaFlagsKHR::INHERIT,
+         vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED, vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED]
+            .into_iter().find(|alpha| supported.contains(*alpha)).unwrap_or(vk::CompositeAlphaFlagsKHR::OPAQUE)
+    }
+
+    pub(crate) fn image_usage(&self, supported: vk::ImageUsageFlags) -> vk::ImageUsageFlags {
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | (self.image_usage & supported)
+    }
+
+    pub(crate) fn image_count(&self, capabilities: &vk::SurfaceCapabilitiesKHR) -> u32 {
+        let count = self.image_count.max(capabilities.min_image_count);
+        // a max of 0 means there is no limit
+        if capabilities.max_image_count == 0 { count } else { count.min(capabilities.max_image_count) }
+    }
+
+    /// A format of the requested color space, or else an sRGB one.
+    pub(crate) fn surface_format(&self, supported: &[vk::SurfaceFormatKHR]) -> Option<vk::SurfaceFormatKHR> {
+        let usable = |color_space: vk::ColorSpaceKHR| {
+            let output = OutputSpace::from_color_space(color_space)?;
+            output.formats().iter().find_map(|format| supported.iter().copied()
+                .find(|supported| supported.format == *format && supported.color_space == color_space))
+        };
+        usable(self.color_space).or_else(|| usable(vk::ColorSpaceKHR::SRGB_NONLINEAR))
+    }
+}
+
+/// The surface's size if it dictates one, otherwise the window size clamped to what the surface supports.
+pub(crate) fn swapchain_extent(capabilities: &vk::SurfaceCapabilitiesKHR, window_extent: vk::Extent2D) -> vk::Extent2D {
+    match capabilities.current_extent {
+        vk::Extent2D{width:u32::MAX, height:u32::MAX} => {
+            let min = capabilities.min_image_extent;
+            let max = capabilities.max_image_extent;
+            vk::Extent2D{
+                width:  window_extent.width.clamp(min.width, max.width),
+                height: window_extent.height.clamp(min.height, max.height),
+            }
+        },
+        x => x,
+    }
+}
+
+/// Horizontal and vertical dots per inch, None if the display does not report its physical size.
+pub(crate) fn display_dpi(px: vk::Extent2D, mm: vk::Extent2D) -> Option<(f32, f32)> {
+    if mm.width == 0 || mm.height == 0 { return None }
+    Some((25.4*px.width as f32/mm.width as f32, 25.4*px.height as f32/mm.height as f32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_size_is_clamped_to_the_surface() {
+        let capabilities = vk::SurfaceCapabilitiesKHR{
+            current_extent:   vk::Extent2D{ width: u32::MAX, height: u32::MAX },
+            min_image_extent: vk::Extent2D{ width: 64,   height: 64 },
+            max_image_extent: vk::Extent2D{ width: 4096, height: 4096 },
+            ..Default::default()
+        };
+        let extent = |width, height| vk::Extent2D{ width, height };
+        assert_eq!(swapchain_extent(&capabilities, extent(800, 600)),  extent(800, 600));
+        assert_eq!(swapchain_extent(&capabilities, extent(8000, 10)),  extent(4096, 64));
+        let fixed = vk::SurfaceCapabilitiesKHR{ current_extent: extent(1920, 1080), ..capabilities };
+        assert_eq!(swapchain_extent(&fixed, extent(800, 600)), extent(1920, 1080));
+    }
+
+    #[test]
+    fn dpi_needs_physical_size() {
+        let (w, h) = display_dpi(vk::Extent2D{ width: 3840, height: 2160 }, vk::Extent2D{ width: 600, height: 340 }).unwrap();
+        assert!((w-162.6).abs() < 0.1 && (h-161.4).abs() < 0.1);
+        assert_eq!(display_dpi(vk::Extent2D{ width: 1920, height: 1080 }, vk::Extent2D::default()), None);
+    }
+
+    #[test]
+    fn unsupported_config_falls_back() {
+        use vk::PresentModeKHR as Mode;
+        let only_fifo = [Mode::FIFO];
+        let no_mailbox = [Mode::FIFO, Mode::IMMEDIATE];
+        let no_immediate = [Mode::FIFO, Mode::MAILBOX];
+        let config = SwapchainConfig::default();
+        assert_eq!(config.with_present_mode(Mode::MAILBOX).present_mode(&no_mailbox), Mode::FIFO);
+        assert_eq!(config.with_present_mode(Mode::IMMEDIATE).present_mode(&no_immediate), Mode::MAILBOX);
+        assert_eq!(config.with_present_mode(Mode::IMMEDIATE).present_mode(&only_fifo), Mode::FIFO);
+        assert_eq!(config.with_present_mode(Mode::FIFO_RELAXED).present_mode(&only_fifo), Mode::FIFO);
+
+        let premultiplied = config.with_composite_alpha(vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED);
+        assert_eq!(premultiplied.composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE), vk::CompositeAlphaFlagsKHR::OPAQUE);
+        assert_eq!(config.composite_alpha(vk::CompositeAlphaFlagsKHR::INHERIT), vk::CompositeAlphaFlagsKHR::INHERIT);
+        assert_eq!(config.image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT), vk::ImageUsageFlags::COLOR_ATTACHMENT);
+
+        let capabilities = |min_image_count, max_image_count| vk::SurfaceCapabilitiesKHR{ min_image_count, max_image_count, ..Default::default() };
+        assert_eq!(config.with_image_count(8).image_count(&capabilities(2, 0)), 8);
+        assert_eq!(config.with_image_count(8).image_count(&capabilities(2, 4)), 4);
+        assert_eq!(config.with_image_count(1).image_count(&capabilities(2, 4)), 2);
+
+        let srgb = vk::SurfaceFormatKHR{ format: vk::Format::B8G8R8A8_SRGB, color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR };
+        let unorm = vk::SurfaceFormatKHR{ format: vk::Format::B8G8R8A8_UNORM, ..srgb };
+        let scrgb = vk::SurfaceFormatKHR{ format: vk::Format::R16G16B16A16_SFLOAT, color_space: vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT };
+        let hdr10 = vk::SurfaceFormatKHR{ format: vk::Format::A2B10G10R10_UNORM_PACK32, color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT };
+        let p3 = vk::SurfaceFormatKHR{ color_space: vk::ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT, ..srgb };
+        let linear = config.with_color_space(vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT);
+        assert_eq!(linear.surface_format(&[srgb, hdr10, scrgb]), Some(scrgb));
+        assert_eq!(linear.surface_format(&[srgb, hdr10]), Some(srgb));
+        // 8 bit is not enough for PQ
+        let pq_8bit = vk::SurfaceFormatKHR{ color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT, ..srgb };
+        let hdr = config.with_color_space(vk::ColorSpaceKHR::HDR10_ST2084_EXT);
+        assert_eq!(hdr.surface_format(&[pq_8bit, srgb, hdr10]), Some(hdr10));
+        assert_eq!(config.with_color_space(vk::ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT).surface_format(&[p3, srgb]), Some(srgb));
+        assert_eq!(config.surface_format(&[unorm]), None);
+    }
+
+    #[test]
+    fn hdr_encoding() {
+        let close = |a: f32, b: f32| (a-b).abs() < 1e-3;
+        assert_eq!(pq(0.0), pq(-1.0));
+        assert!(close(pq(1.0), 1.0));
+        // SDR white at 203 nits is 58% of the PQ range
+        let [r, g, b] = OutputSpace::Hdr10.encode([1.0, 1.0, 1.0]);
+        assert!(close(r, 0.5806) && close(g, 0.5806) && close(b, 0.5806));
+        assert!(close(OutputSpace::ExtendedSrgbLinear.encode([1.0, 0.0, 0.0])[0], 2.5375));
+        assert_eq!(OutputSpace::Srgb.encode([0.5, 0.25, 1.0]), [0.5, 0.25, 1.0]);
+    }
+}
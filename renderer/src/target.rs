@@ -0,0 +1,127 @@
+use ash::vk::{self, Image, ImageView};
+use crate::AllocatedImage;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttachmentLoad<T> {
+    Load,
+    Clear(T),
+    DontCare,
+}
+pub type LoadOp = AttachmentLoad<[f32;4]>;
+
+impl<T:Copy> AttachmentLoad<T> {
+    pub(crate) fn vk(&self) -> vk::AttachmentLoadOp {
+        match self {
+            Self::Load     => vk::AttachmentLoadOp::LOAD,
+            Self::Clear(_) => vk::AttachmentLoadOp::CLEAR,
+            Self::DontCare => vk::AttachmentLoadOp::DONT_CARE,
+        }
+    }
+    pub(crate) fn clear_value(&self) -> Option<T> {
+        match self { Self::Clear(value) => Some(*value), _ => None }
+    }
+    /// whether the previous contents of the attachment are irrelevant
+    pub(crate) fn discards(&self) -> bool { !matches!(self, Self::Load) }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Resolve {
+    pub image: Image,
+    pub view:  ImageView,
+    pub mode:  vk::ResolveModeFlags,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ColorAttachment {
+    pub image:   Image,
+    pub view:    ImageView,
+    pub load:    LoadOp,
+    pub store:   bool,
+    pub resolve: Option<Resolve>,
+}
+impl ColorAttachment {
+    pub const fn new(image: Image, view: ImageView, load: LoadOp) -> Self {
+        Self{ image, view, load, store: true, resolve: None }
+    }
+    pub const fn from_image(image: &AllocatedImage, load: LoadOp) -> Self {
+        Self::new(image.image, image.view, load)
+    }
+    /// Resolve the multisampled attachment into `target` at the end of rendering.
+    /// The multisampled contents are not stored.
+    pub const fn resolve_into(self, image: Image, view: ImageView) -> Self {
+        Self{ store: false, resolve: Some(Resolve{ image, view, mode: vk::ResolveModeFlags::AVERAGE }), ..self }
+    }
+}
+
+/// `None` leaves the depth or stencil aspect unattached.
+#[derive(Debug, Clone, Copy)]
+pub struct DepthStencilAttachment {
+    pub image:   Image,
+    pub view:    ImageView,
+    pub depth:   Option<AttachmentLoad<f32>>,
+    pub stencil: Option<AttachmentLoad<u32>>,
+    pub store:   bool,
+}
+
+/// Everything `Frame::begin_rendering_with` needs to start a dynamic rendering pass.
+/// All attachments must have `samples` samples, resolve targets must be single-sampled.
+#[derive(Debug, Clone, Copy)]
+pub struct RenderTarget<'a> {
+    pub area:          vk::Rect2D,
+    pub samples:       vk::SampleCountFlags,
+    pub colors:        &'a [ColorAttachment],
+    pub depth_stencil: Option<DepthStencilAttachment>,
+}
+impl<'a> RenderTarget<'a> {
+    pub fn new(extent: vk::Extent2D, colors: &'a [ColorAttachment]) -> Self {
+        Self{ area: extent.into(), samples: vk::SampleCountFlags::TYPE_1, colors, depth_stencil: None }
+    }
+}
+
+pub(crate) fn color_info(attachment: &ColorAttachment) -> vk::RenderingAttachmentInfo<'static> {
+    let mut clear_value = vk::ClearValue::default();
+    if let Some(color) = attachment.load.clear_value() {
+        clear_value.color = vk::ClearColorValue{ float32: color };
+    }
+    let info = vk::RenderingAttachmentInfo::default()
+        .image_view(attachment.view)
+        .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .load_op(attachment.load.vk())
+        .store_op(store_op(attachment.store))
+        .clear_value(clear_value);
+    match attachment.resolve {
+        None => info,
+        Some(resolve) => info
+            .resolve_mode(resolve.mode)
+            .resolve_image_view(resolve.view)
+            .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+    }
+}
+
+pub(crate) fn depth_info(attachment: &DepthStencilAttachment) -> Option<vk::RenderingAttachmentInfo<'static>> {
+    let load = attachment.depth?;
+    let mut clear_value = vk::ClearValue::default();
+    clear_value.depth_stencil = vk::ClearDepthStencilValue{ depth: load.clear_value().unwrap_or(1.0), stencil: 0 };
+    Some(vk::RenderingAttachmentInfo::default()
+        .image_view(attachment.view)
+        .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+        .load_op(load.vk())
+        .store_op(store_op(attachment.store))
+        .clear_value(clear_value))
+}
+
+pub(crate) fn stencil_info(attachment: &DepthStencilAttachment) -> Option<vk::RenderingAttachmentInfo<'static>> {
+    let load = attachment.stencil?;
+    let mut clear_value = vk::ClearValue::default();
+    clear_value.depth_stencil = vk::ClearDepthStencilValue{ depth: 1.0, stencil: load.clear_value().unwrap_or(0) };
+    Some(vk::RenderingAttachmentInfo::default()
+        .image_view(attachment.view)
+        .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+        .load_op(load.vk())
+        .store_op(store_op(attachment.store))
+        .clear_value(clear_value))
+}
+
+const fn store_op(store: bool) -> vk::AttachmentStoreOp {
+    if store { vk::AttachmentStoreOp::STORE } else { vk::AttachmentStoreOp::DONT_CARE }
+}
@@ -0,0 +1,258 @@
+use ash::vk;
+
+/// Everything needed to create a sampled 2D texture, possibly with mips and array layers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TextureDesc {
+    pub width:  u32,
+    pub height: u32,
+    pub format: vk::Format,
+    pub usage:  vk::ImageUsageFlags,
+    pub mip_levels:   u32,
+    pub array_layers: u32,
+}
+
+impl TextureDesc {
+    /// A single mip and layer, usable for sampling and as a copy destination.
+    pub const fn new(width: u32, height: u32, format: vk::Format) -> Self {
+        Self{ width, height, format,
+            usage: vk::ImageUsageFlags::from_raw(vk::ImageUsageFlags::TRANSFER_DST.as_raw() | vk::ImageUsageFlags::SAMPLED.as_raw()),
+            mip_levels: 1, array_layers: 1 }
+    }
+    pub const fn rgba8_srgb(width: u32, height: u32) -> Self { Self::new(width, height, vk::Format::R8G8B8A8_SRGB) }
+    pub const fn r8(width: u32, height: u32) -> Self { Self::new(width, height, vk::Format::R8_UNORM) }
+
+    /// The full mip chain down to 1x1, generated by blits when uploading.
+    pub const fn with_mips(mut self) -> Self {
+        self.mip_levels = mip_count(self.width, self.height);
+        self.usage = vk::ImageUsageFlags::from_raw(self.usage.as_raw() | vk::ImageUsageFlags::TRANSFER_SRC.as_raw());
+        self
+    }
+    pub const fn with_usage(mut self, usage: vk::ImageUsageFlags) -> Self {
+        self.usage = vk::ImageUsageFlags::from_raw(self.usage.as_raw() | usage.as_raw());
+        self
+    }
+    pub const fn with_layers(mut self, array_layers: u32) -> Self {
+        self.array_layers = array_layers;
+        self
+    }
+
+    pub(crate) const fn subresource_range(&self) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange{
+            aspect_mask:      vk::ImageAspectFlags::COLOR,
+            base_mip_level:   0,
+            level_count:      self.mip_levels,
+            base_array_layer: 0,
+            layer_count:      self.array_layers,
+        }
+    }
+    pub(crate) const fn view_type(&self) -> vk::ImageViewType {
+        if self.array_layers > 1 { vk::ImageViewType::TYPE_2D_ARRAY } else { vk::ImageViewType::TYPE_2D }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Texture {
+    pub image:  vk::Image,
+    pub view:   vk::ImageView,
+    pub memory: vk::DeviceMemory,
+    pub desc:   TextureDesc,
+}
+
+/// Number of mips down to 1x1.
+pub const fn mip_count(width: u32, height: u32) -> u32 {
+    let size = if width > height { width } else { height };
+    if size == 0 { 1 } else { u32::BITS - size.leading_zeros() }
+}
+
+/// Bytes per texel of the formats textures can be uploaded in.
+pub const fn texel_size(format: vk::Format) -> Option<u32> {
+    Some(match format {
+        vk::Format::R8_UNORM | vk::Format::R8_SRGB => 1,
+        vk::Format::R8G8_UNORM => 2,
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB
+      | vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => 4,
+        vk::Format::R16G16B16A16_SFLOAT => 8,
+        vk::Format::R32G32B32A32_SFLOAT => 16,
+        _ => return None,
+    })
+}
+
+fn mip_barrier(device: &ash::Device, cmd: vk::CommandBuffer, image: vk::Image, desc: &TextureDesc, level: u32, levels: u32,
+        (old_layout, src_access): (vk::ImageLayout, vk::AccessFlags), (new_layout, dst_access): (vk::ImageLayout, vk::AccessFlags)) {
+    let barrier = [vk::ImageMemoryBarrier::default()
+        .image(image)
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_access_mask(src_access)
+        .dst_access_mask(dst_access)
+        .subresource_range(vk::ImageSubresourceRange{ base_mip_level: level, level_count: levels, ..desc.subresource_range() })
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)];
+    unsafe{device.cmd_pipeline_barrier(cmd, vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(), &[], &[], &barrier)};
+}
+
+/// Fills mips 1.. by repeatedly blitting from the previous level.
+/// Expects every level in TRANSFER_DST_OPTIMAL with level 0 written, and leaves them that way,
+/// so the tracked state of the image stays valid.
+pub(crate) fn cmd_generate_mips(device: &ash::Device, cmd: vk::CommandBuffer, image: vk::Image, desc: &TextureDesc, filter: vk::Filter) {
+    let dst = (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::AccessFlags::TRANSFER_WRITE);
+    let src = (vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::AccessFlags::TRANSFER_READ);
+    let extent = |level: u32| vk::Offset3D{ x: (desc.width>>level).max(1) as i32, y: (desc.height>>level).max(1) as i32, z: 1 };
+    let layers = |level: u32| vk::ImageSubresourceLayers{
+        aspect_mask: vk::ImageAspectFlags::COLOR, mip_level: level, base_array_layer: 0, layer_count: desc.array_layers };
+    for level in 1..desc.mip_levels {
+        mip_barrier(device, cmd, image, desc, level-1, 1, dst, src);
+        let blit = [vk::ImageBlit{
+            src_subresource: layers(level-1),
+            src_offsets:     [vk::Offset3D::default(), extent(level-1)],
+            dst_subresource: layers(level),
+            dst_offsets:     [vk::Offset3D::default(), extent(level)],
+        }];
+        unsafe{device.cmd_blit_image(cmd,
+            image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            image, vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &blit, filter)};
+    }
+    if desc.mip_levels > 1 {
+        mip_barrier(device, cmd, image, desc, 0, desc.mip_levels-1, src, dst);
+    }
+}
+
+/// Sampler configurations that cover the common cases, see `Renderer::create_sampler`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SamplerPreset {
+    /// pixel coordinates, no filtering, as used for the glyph atlas
+    NearestUnnormalized,
+    Nearest,
+    Linear,
+    /// trilinear filtering across all mips
+    LinearMipmapped,
+    /// like `LinearMipmapped`, but the texture repeats instead of clamping to the edge
+    LinearRepeat,
+}
+
+impl SamplerPreset {
+    pub fn info(self) -> vk::SamplerCreateInfo<'static> {
+        let (filter, mipmap_mode, address_mode, max_lod) = match self {
+            SamplerPreset::NearestUnnormalized => (vk::Filter::NEAREST, vk::SamplerMipmapMode::NEAREST, vk::SamplerAddressMode::CLAMP_TO_BORDER, 0.0),
+            SamplerPreset::Nearest         => (vk::Filter::NEAREST, vk::SamplerMipmapMode::NEAREST, vk::SamplerAddressMode::CLAMP_TO_EDGE, 0.0),
+            SamplerPreset::Linear          => (vk::Filter::LINEAR,  vk::SamplerMipmapMode::NEAREST, vk::SamplerAddressMode::CLAMP_TO_EDGE, 0.0),
+            SamplerPreset::LinearMipmapped => (vk::Filter::LINEAR,  vk::SamplerMipmapMode::LINEAR,  vk::SamplerAddressMode::CLAMP_TO_EDGE, vk::LOD_CLAMP_NONE),
+            SamplerPreset::LinearRepeat    => (vk::Filter::LINEAR,  vk::SamplerMipmapMode::LINEAR,  vk::SamplerAddressMode::REPEAT,        vk::LOD_CLAMP_NONE),
+        };
+        vk::SamplerCreateInfo::default()
+            .mag_filter(filter)
+            .min_filter(filter)
+            .mipmap_mode(mipmap_mode)
+            .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+            .address_mode_u(address_mode)
+            .address_mode_v(address_mode)
+            .address_mode_w(address_mode)
+            .anisotropy_enable(false)
+            .unnormalized_coordinates(self == SamplerPreset::NearestUnnormalized)
+            .compare_enable(false)
+            .compare_op(vk::CompareOp::ALWAYS)
+            .mip_lod_bias(0.0)
+            .min_lod(0.0)
+            .max_lod(max_lod)
+    }
+}
+
+/// Decoded 8 bit RGBA pixels, rows tightly packed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaImage {
+    pub width:  u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+#[cfg(feature="png")]
+impl RgbaImage {
+    pub fn decode_png(bytes: &[u8]) -> Result<Self, String> {
+        let mut decoder = png::Decoder::new(std::io::Cursor::new(bytes));
+        // palettes and 16 bit channels are converted to 8 bit color
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|err| format!("invalid png: {err}"))?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(|err| format!("invalid png: {err}"))?;
+        buffer.truncate(info.buffer_size());
+        let pixels = match info.color_type {
+            png::ColorType::Rgba           => buffer,
+            png::ColorType::Rgb            => buffer.chunks_exact(3).flat_map(|c| [c[0], c[1], c[2], 0xFF]).collect(),
+            png::ColorType::GrayscaleAlpha => buffer.chunks_exact(2).flat_map(|c| [c[0], c[0], c[0], c[1]]).collect(),
+            png::ColorType::Grayscale      => buffer.iter().flat_map(|&c| [c, c, c, 0xFF]).collect(),
+            png::ColorType::Indexed        => return Err("png palette was not expanded".into()),
+        };
+        Ok(Self{ width: info.width, height: info.height, pixels })
+    }
+}
+
+#[cfg(feature="qoi")]
+impl RgbaImage {
+    pub fn decode_qoi(bytes: &[u8]) -> Result<Self, String> {
+        let mut decoder = qoi::Decoder::new(bytes).map_err(|err| format!("invalid qoi: {err}"))?
+            .with_channels(qoi::Channels::Rgba);
+        let (width, height) = (decoder.header().width, decoder.header().height);
+        let pixels = decoder.decode_to_vec().map_err(|err| format!("invalid qoi: {err}"))?;
+        Ok(Self{ width, height, pixels })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mip_chain_length() {
+        assert_eq!(mip_count(1, 1), 1);
+        assert_eq!(mip_count(256, 256), 9);
+        assert_eq!(mip_count(1024, 3), 11);
+        assert_eq!(mip_count(300, 17), 9);
+        assert_eq!(TextureDesc::r8(64, 32).with_mips().mip_levels, 7);
+    }
+
+    #[test]
+    fn only_mipmapped_presets_sample_lower_mips() {
+        for preset in [SamplerPreset::NearestUnnormalized, SamplerPreset::Nearest, SamplerPreset::Linear] {
+            assert_eq!(preset.info().max_lod, 0.0);
+        }
+        for preset in [SamplerPreset::LinearMipmapped, SamplerPreset::LinearRepeat] {
+            assert_eq!(preset.info().max_lod, vk::LOD_CLAMP_NONE);
+        }
+        // unnormalized coordinates are only valid without mips and with clamping
+        assert_eq!(SamplerPreset::NearestUnnormalized.info().unnormalized_coordinates, vk::TRUE);
+        assert_eq!(SamplerPreset::Linear.info().unnormalized_coordinates, vk::FALSE);
+    }
+
+    #[cfg(feature="png")]
+    #[test]
+    fn png_round_trip() {
+        let image = RgbaImage{ width: 3, height: 2, pixels: (0..24).map(|i| i*10).collect() };
+        let png = image.encode_png().unwrap();
+        assert_eq!(RgbaImage::decode_png(&png).unwrap(), image);
+        assert!(RgbaImage::decode_png(&png[..png.len()/2]).is_err());
+    }
+
+    #[cfg(feature="png")]
+    #[test]
+    fn png_without_alpha_is_opaque() {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, 2, 1);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header().unwrap().write_image_data(&[1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(RgbaImage::decode_png(&png).unwrap().pixels, [1, 2, 3, 0xFF, 4, 5, 6, 0xFF]);
+    }
+
+    #[cfg(feature="qoi")]
+    #[test]
+    fn qoi_round_trip() {
+        let image = RgbaImage{ width: 3, height: 2, pixels: (0..24).map(|i| i*10).collect() };
+        let qoi = qoi::encode_to_vec(&image.pixels, image.width, image.height).unwrap();
+        assert_eq!(RgbaImage::decode_qoi(&qoi).unwrap(), image);
+        let rgb = qoi::encode_to_vec([1u8, 2, 3, 4, 5, 6], 2, 1).unwrap();
+        assert_eq!(RgbaImage::decode_qoi(&rgb).unwrap().pixels, [1, 2, 3, 0xFF, 4, 5, 6, 0xFF]);
+        assert!(RgbaImage::decode_qoi(b"qoif").is_err());
+    }
+}
@@ -0,0 +1,44 @@
+use core::{
+    ffi,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use ash::{ext, vk};
+
+/// Counts the warnings and errors reported by the validation layers, and prints them.
+pub(crate) struct ValidationMessenger {
+    debug_utils: ext::debug_utils::Instance,
+    messenger:   vk::DebugUtilsMessengerEXT,
+    count:       Box<AtomicU32>, // the callback holds a pointer to it, so it must not move
+}
+
+impl ValidationMessenger {
+    /// `instance` must have been created with VK_EXT_debug_utils enabled.
+    pub fn new(entry: &ash::Entry, instance: &ash::Instance) -> Self {
+        let debug_utils = ext::debug_utils::Instance::new(entry, instance);
+        let count = Box::new(AtomicU32::new(0));
+        let info = vk::DebugUtilsMessengerCreateInfoEXT::default()
+            .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)
+            .message_type(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION)
+            .pfn_user_callback(Some(count_message))
+            .user_data(core::ptr::from_ref::<AtomicU32>(&count).cast_mut().cast());
+        let messenger = unsafe{debug_utils.create_debug_utils_messenger(&info, None)}.unwrap();
+        Self{ debug_utils, messenger, count }
+    }
+    pub fn count(&self) -> u32 { self.count.load(Ordering::Relaxed) }
+    /// Must be called before the instance is destroyed.
+    pub fn destroy(&self){
+        unsafe{self.debug_utils.destroy_debug_utils_messenger(self.messenger, None)};
+    }
+}
+
+unsafe extern "system" fn count_message(
+        severity:  vk::DebugUtilsMessageSeverityFlagsEXT,
+        _types:    vk::DebugUtilsMessageTypeFlagsEXT,
+        data:      *const vk::DebugUtilsMessengerCallbackDataEXT<'_>,
+        user_data: *mut ffi::c_void) -> vk::Bool32 {
+    let message = unsafe{data.as_ref().and_then(|data| data.message_as_c_str())}.unwrap_or_default();
+    eprintln!("validation {severity:?}: {}", message.to_string_lossy());
+    let count = unsafe{&*user_data.cast::<AtomicU32>()};
+    count.fetch_add(1, Ordering::Relaxed);
+    vk::FALSE
+}
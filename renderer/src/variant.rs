@@ -0,0 +1,161 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use ash::vk;
+
+/// A GLSL source file compiled with a set of preprocessor defines,
+/// so several shaders can share one source.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ShaderVariant {
+    pub path:    PathBuf,
+    /// sorted by name, so the order they were added in does not matter
+    pub defines: Vec<(String, Option<String>)>,
+}
+
+impl ShaderVariant {
+    pub fn new<P:AsRef<Path>>(path: P) -> Self {
+        Self{ path: path.as_ref().to_path_buf(), defines: Vec::new() }
+    }
+
+    /// Like `#define name value`, replaces an earlier define of `name`.
+    pub fn define(mut self, name: &str, value: Option<&str>) -> Self {
+        let define = (name.to_string(), value.map(str::to_string));
+        match self.defines.binary_search_by(|(other, _)| other.as_str().cmp(name)) {
+            Ok(idx)  => self.defines[idx] = define,
+            Err(idx) => self.defines.insert(idx, define),
+        }
+        self
+    }
+}
+
+impl From<&str> for ShaderVariant {
+    fn from(path: &str) -> Self { Self::new(path) }
+}
+impl From<&Path> for ShaderVariant {
+    fn from(path: &Path) -> Self { Self::new(path) }
+}
+impl From<&PathBuf> for ShaderVariant {
+    fn from(path: &PathBuf) -> Self { Self::new(path) }
+}
+impl From<PathBuf> for ShaderVariant {
+    fn from(path: PathBuf) -> Self { Self{ path, defines: Vec::new() } }
+}
+
+pub(crate) fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// The files a variant was compiled from, its source and then every file it includes,
+/// with their modification times when it was compiled.
+pub(crate) type Sources = Vec<(PathBuf, Option<SystemTime>)>;
+
+struct CachedSpirv {
+    modified: Sources,
+    spirv:    Vec<u8>,
+}
+
+/// Compiled SPIR-V per variant and stage, recompiled when the source or an included file changes.
+#[derive(Default)]
+pub(crate) struct VariantCache {
+    spirv: HashMap<(ShaderVariant, vk::ShaderStageFlags), CachedSpirv>,
+}
+
+impl VariantCache {
+    pub(crate) fn compile(&mut self, variant: &ShaderVariant, stage: vk::ShaderStageFlags) -> Result<&[u8], String> {
+        let key = (variant.clone(), stage);
+        let stale = match self.spirv.get(&key) {
+            Some(cached) => cached.modified.iter().any(|(path, time)| time.is_none() || modified(path) != *time),
+            None         => true,
+        };
+        if stale {
+            let (spirv, modified) = compile_glsl(variant, stage)?;
+            self.spirv.insert(key.clone(), CachedSpirv{ modified, spirv });
+        }
+        Ok(&self.spirv[&key].spirv)
+    }
+
+    /// The files the cached SPIR-V of `variant` was compiled from, `None` if it was not compiled yet.
+    pub(crate) fn sources(&self, variant: &ShaderVariant, stage: vk::ShaderStageFlags) -> Option<&Sources> {
+        self.spirv.get(&(variant.clone(), stage)).map(|cached| &cached.modified)
+    }
+}
+
+fn compile_glsl(variant: &ShaderVariant, stage: vk::ShaderStageFlags) -> Result<(Vec<u8>, Sources), String> {
+    let kind = match stage {
+        vk::ShaderStageFlags::VERTEX                  => shaderc::ShaderKind::Vertex,
+        vk::ShaderStageFlags::FRAGMENT                => shaderc::ShaderKind::Fragment,
+        vk::ShaderStageFlags::COMPUTE                 => shaderc::ShaderKind::Compute,
+        vk::ShaderStageFlags::GEOMETRY                => shaderc::ShaderKind::Geometry,
+        vk::ShaderStageFlags::TESSELLATION_CONTROL    => shaderc::ShaderKind::TessControl,
+        vk::ShaderStageFlags::TESSELLATION_EVALUATION => shaderc::ShaderKind::TessEvaluation,
+        _ => return Err(format!("can not compile {} for {stage:?}", variant.path.display())),
+    };
+    let path = variant.path.as_path();
+    // outlives `options`, which borrows it in the include callback
+    let sources = RefCell::new(vec![(path.to_path_buf(), modified(path))]);
+    let compiler = shaderc::Compiler::new().unwrap();
+    let mut options = shaderc::CompileOptions::new().unwrap();
+    for (name, value) in &variant.defines {
+        options.add_macro_definition(name, value.as_deref());
+    }
+    let dir = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+    let recorded = &sources;
+    options.set_include_callback(move |requested, ty, requesting, _depth| {
+        let include = shader_build::resolve_include(&dir, requested, ty, requesting)?;
+        let included = PathBuf::from(&include.resolved_name);
+        let mut sources = recorded.borrow_mut();
+        if !sources.iter().any(|(path, _)| *path == included) {
+            let time = modified(&included);
+            sources.push((included, time));
+        }
+        Ok(include)
+    });
+    let src = std::fs::read_to_string(path).map_err(|err| format!("could not read {}: {err}", path.display()))?;
+    let spirv = compiler.compile_into_spirv(
+        &src,
+        kind,
+        &path.to_string_lossy(),
+        "main",
+        Some(&options)
+    ).map_err(|err| format!("{} failed to compile\n{err}", path.display()))?;
+    drop(options);
+    Ok((spirv.as_binary_u8().to_vec(), sources.into_inner()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn define_order_does_not_matter() {
+        let a = ShaderVariant::new("a.frag.glsl").define("B", None).define("A", Some("1"));
+        let b = ShaderVariant::new("a.frag.glsl").define("A", Some("0")).define("B", None).define("A", Some("1"));
+        assert_eq!(a, b);
+        assert_eq!(a.defines, [("A".to_string(), Some("1".to_string())), ("B".to_string(), None)]);
+    }
+
+    #[test]
+    fn edits_to_included_files_recompile() {
+        let dir = std::env::temp_dir().join(format!("variant-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let include = dir.join("color.glsl");
+        std::fs::write(&include, "vec4 color() { return vec4(1.0); }\n").unwrap();
+        std::fs::write(dir.join("a.frag.glsl"), "#version 450\n#include \"color.glsl\"\nlayout(location = 0) out vec4 out_color;\nvoid main() { out_color = color(); }\n").unwrap();
+        let variant = ShaderVariant::new(dir.join("a.frag.glsl"));
+        let stage = vk::ShaderStageFlags::FRAGMENT;
+
+        let mut cache = VariantCache::default();
+        let before = cache.compile(&variant, stage).unwrap().to_vec();
+        let sources : Vec<_> = cache.sources(&variant, stage).unwrap().iter().map(|(path, _)| path.file_name().unwrap().to_owned()).collect();
+        assert_eq!(sources, ["a.frag.glsl", "color.glsl"]);
+
+        // only the include changes, a second later so coarse timestamps differ too
+        std::fs::write(&include, "vec4 color() { return vec4(0.5); }\n").unwrap();
+        let file = std::fs::File::options().write(true).open(&include).unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(1)).unwrap();
+        let after = cache.compile(&variant, stage).unwrap().to_vec();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_ne!(before, after);
+    }
+}
@@ -0,0 +1,214 @@
+//! Records real command buffers on a headless device with VK_LAYER_KHRONOS_validation enabled,
+//! e.g. lavapipe. Tests are skipped without a Vulkan loader, the validation layer or a device,
+//! and fail on any validation message.
+use ash::vk::{self, ShaderEXT};
+use std::collections::HashSet;
+use renderer::{ColorAttachment, CommandStats, DescriptorWriter, LoadOp, RenderTarget, Renderer, RgbaImage, SamplerPreset, SwapchainConfig, TextureDesc, Usage};
+
+fn headless_renderer(width: u32, height: u32) -> Option<Renderer> {
+    let Ok(entry) = (unsafe{ash::Entry::load()}) else {
+        eprintln!("no Vulkan loader found, skipping");
+        return None;
+    };
+    // the renderer always enables it, and panics without it
+    let layers = unsafe{entry.enumerate_instance_layer_properties()}.unwrap_or_default();
+    if !layers.iter().any(|layer| layer.layer_name_as_c_str() == Ok(c"VK_LAYER_KHRONOS_validation")) {
+        eprintln!("VK_LAYER_KHRONOS_validation is not installed, skipping");
+        return None;
+    }
+    // a bare instance, only to ask for devices
+    let device_count = unsafe{entry.create_instance(&vk::InstanceCreateInfo::default(), None)}.map(|instance| {
+        let count = unsafe{instance.enumerate_physical_devices()}.map_or(0, |devices| devices.len());
+        unsafe{instance.destroy_instance(None)};
+        count
+    }).unwrap_or(0);
+    if device_count == 0 {
+        eprintln!("no Vulkan device found, skipping");
+        return None;
+    }
+    Some(Renderer::new_headless(vk::Extent2D{ width, height }, SwapchainConfig::default()))
+}
+
+fn assert_no_validation_messages(renderer: &Renderer) {
+    assert_eq!(renderer.validation_messages(), Some(0), "the validation layers reported problems, see stderr");
+}
+
+#[test]
+fn barriers_pass_validation() {
+    let Some(mut renderer) = headless_renderer(64, 64) else { return };
+    renderer.enable_depth_stencil();
+    // upload, mip generation and the transition to sampling happen in a oneshot command buffer
+    let desc = TextureDesc::rgba8_srgb(16, 16).with_mips();
+    let texture = renderer.create_texture_with_data(&desc, &[0x80; 16*16*4]);
+    let (staging, staging_memory) = renderer.alloc_buffer(16*16*4, vk::BufferUsageFlags::TRANSFER_SRC, renderer.gpu_memory_idx);
+    let region = vk::BufferImageCopy{
+        image_subresource: vk::ImageSubresourceLayers{ aspect_mask: vk::ImageAspectFlags::COLOR, mip_level: 0, base_array_layer: 0, layer_count: 1 },
+        image_extent: vk::Extent3D{ width: 16, height: 16, depth: 1 },
+        ..Default::default()
+    };
+
+    // two frames, so swapchain images and the depth/stencil image go around the present cycle
+    for _ in 0..2 {
+        let mut frame = renderer.wait_and_begin_frame();
+        // write after read, then read after write
+        frame.buffer_to_image(staging, texture.image, &[region]);
+        frame.use_image(texture.image, Usage::SampledFragment);
+        frame.use_image(texture.image, Usage::SampledVertex);
+        frame.begin_rendering([0.0, 0.0, 0.0, 1.0]);
+        frame.end_rendering();
+        let _ = frame.capture();
+        frame.end_frame();
+    }
+    renderer.finish_captures();
+    assert_no_validation_messages(&renderer);
+
+    unsafe{renderer.device.device_wait_idle()}.unwrap();
+    unsafe{renderer.device.destroy_buffer(staging, None)};
+    unsafe{renderer.device.free_memory(staging_memory, None)};
+    renderer.free_texture(texture);
+}
+
+#[test]
+fn redundant_commands_are_skipped() {
+    let Some(mut renderer) = headless_renderer(64, 64) else { return };
+    let set_layout = unsafe{renderer.device.create_descriptor_set_layout(&vk::DescriptorSetLayoutCreateInfo::default(), None)}.unwrap();
+    let set_layouts = [set_layout];
+    let pipeline_layout = unsafe{renderer.device.create_pipeline_layout(&vk::PipelineLayoutCreateInfo::default().set_layouts(&set_layouts), None)}.unwrap();
+
+    let mut frame = renderer.wait_and_begin_frame();
+    frame.begin_rendering([0.0; 4]);
+    let since = |frame: &renderer::Frame, before: CommandStats| {
+        let stats = frame.command_stats();
+        (stats.emitted-before.emitted, stats.skipped-before.skipped)
+    };
+
+    let before = frame.command_stats();
+    let scissor = vk::Rect2D{ offset: vk::Offset2D{ x: 8, y: 8 }, extent: vk::Extent2D{ width: 16, height: 16 } };
+    for _ in 0..3 {
+        frame.set_cull_mode(vk::CullModeFlags::BACK);
+        frame.set_front_face(vk::FrontFace::CLOCKWISE);
+        frame.set_scissors(&[scissor]);
+    }
+    assert_eq!(since(&frame, before), (3, 6));
+    frame.set_cull_mode(vk::CullModeFlags::NONE);
+    frame.set_cull_mode(vk::CullModeFlags::BACK);
+    assert_eq!(since(&frame, before), (5, 6));
+
+    // the faces are cached separately, the back face was never set
+    let before = frame.command_stats();
+    frame.set_stencil_reference(vk::StencilFaceFlags::FRONT, 1);
+    frame.set_stencil_reference(vk::StencilFaceFlags::FRONT_AND_BACK, 1);
+    frame.set_stencil_reference(vk::StencilFaceFlags::BACK, 1);
+    frame.set_stencil_reference(vk::StencilFaceFlags::FRONT, 1);
+    assert_eq!(since(&frame, before), (2, 2));
+
+    let before = frame.command_stats();
+    let set = frame.allocate_descriptor_set(set_layout);
+    for _ in 0..3 {
+        frame.bind_descriptor_set(set, pipeline_layout);
+        frame.bind_vs_fs(ShaderEXT::null(), ShaderEXT::null());
+    }
+    assert_eq!(since(&frame, before), (2, 4));
+
+    frame.end_rendering();
+    frame.end_frame();
+    assert_no_validation_messages(&renderer);
+
+    unsafe{renderer.device.device_wait_idle()}.unwrap();
+    unsafe{renderer.device.destroy_pipeline_layout(pipeline_layout, None)};
+    unsafe{renderer.device.destroy_descriptor_set_layout(set_layout, None)};
+}
+
+#[test]
+fn frame_descriptor_pools_grow_and_are_reused() {
+    let Some(mut renderer) = headless_renderer(64, 64) else { return };
+    let bindings = [
+        vk::DescriptorSetLayoutBinding::default().binding(0).descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER).descriptor_count(1).stage_flags(vk::ShaderStageFlags::FRAGMENT),
+        vk::DescriptorSetLayoutBinding::default().binding(1).descriptor_type(vk::DescriptorType::STORAGE_BUFFER).descriptor_count(1).stage_flags(vk::ShaderStageFlags::FRAGMENT),
+    ];
+    let set_layout = unsafe{renderer.device.create_descriptor_set_layout(&vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings), None)}.unwrap();
+    let texture = renderer.create_texture_with_data(&TextureDesc::rgba8_srgb(4, 4), &[0xFF; 4*4*4]);
+    let sampler = renderer.create_sampler(SamplerPreset::Linear);
+    let (buffer, memory) = renderer.alloc_buffer(256, vk::BufferUsageFlags::STORAGE_BUFFER, renderer.gpu_memory_idx);
+    let device = renderer.device.clone();
+
+    // more sets than the first pool holds, so it has to grow
+    let mut pools = Vec::new();
+    for _ in 0..2 {
+        let mut frame = renderer.wait_and_begin_frame();
+        let sets : HashSet<_> = (0..100).map(|_| frame.allocate_descriptor_set(set_layout)).collect();
+        assert_eq!(sets.len(), 100);
+        for &set in &sets {
+            DescriptorWriter::new(set)
+                .combined_image_sampler(0, texture.view, sampler)
+                .storage_buffer(1, buffer, 0, vk::WHOLE_SIZE)
+                .update(&device);
+        }
+        pools.push(frame.descriptor_pools().into_iter().collect::<HashSet<_>>());
+        frame.end_frame();
+    }
+    assert!(pools[0].len() > 1);
+    // the second frame reset the pools of the first, and needed no new ones
+    assert_eq!(pools[0], pools[1]);
+    assert_no_validation_messages(&renderer);
+
+    unsafe{renderer.device.device_wait_idle()}.unwrap();
+    unsafe{renderer.device.destroy_buffer(buffer, None)};
+    unsafe{renderer.device.free_memory(memory, None)};
+    unsafe{renderer.device.destroy_sampler(sampler, None)};
+    unsafe{renderer.device.destroy_descriptor_set_layout(set_layout, None)};
+    renderer.free_texture(texture);
+}
+
+#[test]
+fn bindless_slots_are_reused() {
+    let Some(mut renderer) = headless_renderer(64, 64) else { return };
+    if !renderer.bindless {
+        eprintln!("no descriptor indexing, skipping");
+        return;
+    }
+    let texture = renderer.create_texture_with_data(&TextureDesc::rgba8_srgb(4, 4), &[0xFF; 4*4*4]);
+    let sampler = renderer.create_sampler(SamplerPreset::Nearest);
+    let mut textures = renderer.create_bindless_textures(4, vk::ShaderStageFlags::FRAGMENT);
+    let slots : Vec<_> = (0..3).map(|_| textures.add(&renderer.device, texture.view, sampler)).collect();
+    assert_eq!(slots, [0, 1, 2]);
+    textures.remove(1);
+    assert_eq!(textures.add(&renderer.device, texture.view, sampler), 1);
+    assert_eq!(textures.add(&renderer.device, texture.view, sampler), 3);
+    assert_no_validation_messages(&renderer);
+
+    renderer.destroy_bindless_textures(textures);
+    unsafe{renderer.device.destroy_sampler(sampler, None)};
+    renderer.free_texture(texture);
+}
+
+#[test]
+fn capture_matches_reference() {
+    let Some(mut renderer) = headless_renderer(32, 24) else { return };
+    let mut frame = renderer.wait_and_begin_frame();
+    let (image, view, extent) = (frame.swapchain_image(), frame.swapchain_view(), frame.swapchain_extent());
+    frame.begin_rendering([0.0, 0.0, 0.0, 1.0]);
+    frame.end_rendering();
+    // a red square, cleared through the render area
+    let square = vk::Rect2D{ offset: vk::Offset2D{ x: 8, y: 4 }, extent: vk::Extent2D{ width: 12, height: 10 } };
+    let red = [ColorAttachment::new(image, view, LoadOp::Clear([1.0, 0.0, 0.0, 1.0]))];
+    frame.begin_rendering_with(&RenderTarget{ area: square, ..RenderTarget::new(extent, &red) });
+    frame.end_rendering();
+    // capture reads the area of the last pass
+    let whole = [ColorAttachment::new(image, view, LoadOp::Load)];
+    frame.begin_rendering_with(&RenderTarget::new(extent, &whole));
+    frame.end_rendering();
+    let capture = frame.capture();
+    frame.end_frame();
+    renderer.finish_captures();
+    assert_no_validation_messages(&renderer);
+
+    let captured = capture.try_take().expect("the capture is delivered once the frame finished");
+    let inside = |x: u32, y: u32| (8..20).contains(&x) && (4..14).contains(&y);
+    let reference = RgbaImage{ width: 32, height: 24, pixels: (0..24).flat_map(|y| (0..32).flat_map(move |x|
+        if inside(x, y) { [0xFF, 0, 0, 0xFF] } else { [0, 0, 0, 0xFF] })).collect() };
+    assert_eq!((captured.width, captured.height), (reference.width, reference.height));
+    let mismatches = captured.pixels.chunks_exact(4).zip(reference.pixels.chunks_exact(4))
+        .filter(|(a, b)| a.iter().zip(b.iter()).any(|(a, b)| a.abs_diff(*b) > 1)).count();
+    assert_eq!(mismatches, 0, "{mismatches} pixels differ from the reference");
+}
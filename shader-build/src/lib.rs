@@ -0,0 +1,135 @@
+//! Compiles GLSL shaders from a build script, so the binary embeds SPIR-V
+//! and needs neither shaderc nor the shader sources at runtime.
+//!
+//! ```ignore
+//! // build.rs
+//! fn main() {
+//!     shader_build::ShaderBuild::new("shaders").compile();
+//! }
+//! // main.rs
+//! mod shaders { include!(concat!(env!("OUT_DIR"), "/shaders.rs")); }
+//! renderer.load_spirv_vs_fs(shaders::TEXT_RENDERER_VERT, shaders::SUBPIXEL_FRAG, ..);
+//! ```
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+struct Variant {
+    name:    String,
+    source:  PathBuf,
+    defines: Vec<(String, Option<String>)>,
+}
+
+pub struct ShaderBuild {
+    dir:      PathBuf,
+    defines:  Vec<(String, Option<String>)>,
+    variants: Vec<Variant>,
+}
+
+/// Only files named `<name>.<stage>.glsl` are compiled, other `.glsl` files are include-only.
+fn shader_kind(path: &Path) -> Option<(shaderc::ShaderKind, &'static str)> {
+    let name = path.file_name()?.to_str()?.strip_suffix(".glsl")?;
+    let (_, stage) = name.rsplit_once('.')?;
+    Some(match stage {
+        "vert" => (shaderc::ShaderKind::Vertex,         "VERT"),
+        "frag" => (shaderc::ShaderKind::Fragment,       "FRAG"),
+        "comp" => (shaderc::ShaderKind::Compute,        "COMP"),
+        "geom" => (shaderc::ShaderKind::Geometry,       "GEOM"),
+        "tesc" => (shaderc::ShaderKind::TessControl,    "TESC"),
+        "tese" => (shaderc::ShaderKind::TessEvaluation, "TESE"),
+        _ => return None,
+    })
+}
+
+/// `text-renderer` -> `TEXT_RENDERER`
+fn const_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }).collect()
+}
+
+impl ShaderBuild {
+    pub fn new<P:AsRef<Path>>(dir: P) -> Self {
+        Self{ dir: dir.as_ref().to_path_buf(), defines: Vec::new(), variants: Vec::new() }
+    }
+
+    /// Defines `name` in every shader, like `#define name value`.
+    pub fn define(mut self, name: &str, value: Option<&str>) -> Self {
+        self.defines.push((name.to_string(), value.map(str::to_string)));
+        self
+    }
+
+    /// Compiles `source` (relative to the shader directory) a second time with extra defines,
+    /// embedded as `<NAME>_<STAGE>`.
+    pub fn variant(mut self, name: &str, source: &str, defines: &[(&str, &str)]) -> Self {
+        self.variants.push(Variant{
+            name:    name.to_string(),
+            source:  self.dir.join(source),
+            defines: defines.iter().map(|(name, value)| (name.to_string(), Some(value.to_string()))).collect(),
+        });
+        self
+    }
+
+    /// Compiles all shaders into `$OUT_DIR`, and writes `$OUT_DIR/shaders.rs` with a
+    /// 4-byte aligned `&[u8]` constant for each one. Panics on compile errors, failing the build.
+    pub fn compile(self) {
+        let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").expect("ShaderBuild has to run from a build script"));
+        println!("cargo:rerun-if-changed={}", self.dir.display());
+
+        let mut sources : Vec<_> = std::fs::read_dir(&self.dir)
+            .unwrap_or_else(|err| panic!("could not read {}: {err}", self.dir.display()))
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| shader_kind(path).is_some())
+            .collect();
+        sources.sort();
+        let mut jobs : Vec<_> = sources.into_iter().map(|path| {
+            let name = path.file_name().unwrap().to_str().unwrap().split('.').next().unwrap().to_string();
+            Variant{ name, source: path, defines: Vec::new() }
+        }).collect();
+        jobs.extend(self.variants.iter().map(|variant| Variant{ name: variant.name.clone(), source: variant.source.clone(), defines: variant.defines.clone() }));
+
+        let compiler = shaderc::Compiler::new().expect("could not create shaderc compiler");
+        let mut generated = String::from("// generated by shader-build, do not edit\n\n#[repr(C, align(4))]\nstruct Spirv<const N: usize>([u8; N]);\n");
+        for job in jobs {
+            let (kind, stage) = shader_kind(&job.source).unwrap_or_else(|| panic!("{} is not a <name>.<stage>.glsl file", job.source.display()));
+            let spirv = self.compile_one(&compiler, &job, kind);
+            let ident = format!("{}_{stage}", const_name(&job.name));
+            let file = out_dir.join(format!("{}.{}.spv", job.name, stage.to_ascii_lowercase()));
+            std::fs::write(&file, &spirv).unwrap();
+            writeln!(generated, "\nstatic {ident}_SPIRV: Spirv<{}> = Spirv(*include_bytes!({:?}));", spirv.len(), file.display().to_string()).unwrap();
+            writeln!(generated, "#[allow(dead_code)]\npub static {ident}: &[u8] = &{ident}_SPIRV.0;").unwrap();
+        }
+        std::fs::write(out_dir.join("shaders.rs"), generated).unwrap();
+    }
+
+    fn compile_one(&self, compiler: &shaderc::Compiler, job: &Variant, kind: shaderc::ShaderKind) -> Vec<u8> {
+        println!("cargo:rerun-if-changed={}", job.source.display());
+        let source = std::fs::read_to_string(&job.source)
+            .unwrap_or_else(|err| panic!("could not read {}: {err}", job.source.display()));
+        let mut options = shaderc::CompileOptions::new().unwrap();
+        for (name, value) in self.defines.iter().chain(job.defines.iter()) {
+            options.add_macro_definition(name, value.as_deref());
+        }
+        let dir = self.dir.clone();
+        options.set_include_callback(move |requested, ty, requesting, _depth| {
+            let include = resolve_include(&dir, requested, ty, requesting)?;
+            println!("cargo:rerun-if-changed={}", include.resolved_name);
+            Ok(include)
+        });
+        let artifact = compiler.compile_into_spirv(&source, kind, &job.source.to_string_lossy(), "main", Some(&options))
+            .unwrap_or_else(|err| panic!("{} failed to compile\n{err}", job.source.display()));
+        artifact.as_binary_u8().to_vec()
+    }
+}
+
+/// `#include "file"` is looked up next to the including file first, `#include <file>` only in `dir`.
+/// Meant for `shaderc::CompileOptions::set_include_callback`.
+pub fn resolve_include(dir: &Path, requested: &str, ty: shaderc::IncludeType, requesting: &str) -> shaderc::IncludeCallbackResult {
+    let relative = Path::new(requesting).parent().map(|parent| parent.join(requested));
+    let candidates = match ty {
+        shaderc::IncludeType::Relative => vec![relative, Some(dir.join(requested))],
+        shaderc::IncludeType::Standard => vec![Some(dir.join(requested))],
+    };
+    let path = candidates.into_iter().flatten().find(|path| path.is_file())
+        .ok_or_else(|| format!("could not find include {requested:?} from {requesting}"))?;
+    let content = std::fs::read_to_string(&path).map_err(|err| format!("{}: {err}", path.display()))?;
+    Ok(shaderc::ResolvedInclude{ resolved_name: path.display().to_string(), content })
+}
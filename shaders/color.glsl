@@ -0,0 +1,37 @@
+// encodes linear sRGB colors (common::LinearRgba) for the swapchain, select with OUTPUT,
+// mirrors renderer::OutputSpace::encode
+#define OUTPUT_SRGB  0
+#define OUTPUT_SCRGB 1
+#define OUTPUT_HDR10 2
+#ifndef OUTPUT
+#define OUTPUT OUTPUT_SRGB
+#endif
+#ifndef SDR_WHITE_NITS
+#define SDR_WHITE_NITS 203.0
+#endif
+
+// SMPTE ST 2084 inverse EOTF, 1.0 is 10000 nits
+vec3 pq(vec3 y) {
+    const float m1 = 0.1593017578125, m2 = 78.84375;
+    const float c1 = 0.8359375, c2 = 18.8515625, c3 = 18.6875;
+    y = pow(clamp(y, 0.0, 1.0), vec3(m1));
+    return pow((c1 + c2*y)/(1.0 + c3*y), vec3(m2));
+}
+
+vec3 encode_output(vec3 linear) {
+#if OUTPUT == OUTPUT_SRGB
+    // sRGB formats encode in hardware
+    return linear;
+#elif OUTPUT == OUTPUT_SCRGB
+    return linear*(SDR_WHITE_NITS/80.0);
+#elif OUTPUT == OUTPUT_HDR10
+    // columns of the BT.709 to BT.2020 matrix
+    const mat3 to_rec2020 = mat3(
+        0.627404, 0.069097, 0.016391,
+        0.329283, 0.919541, 0.088013,
+        0.043313, 0.011362, 0.895595);
+    return pq(to_rec2020*linear*(SDR_WHITE_NITS/10000.0));
+#else
+#error unknown OUTPUT
+#endif
+}
@@ -0,0 +1,92 @@
+// coverage of the shapes from common::shapes, distances are in pixels
+
+// common::shapes::ShapeKind
+#define SHAPE_GLYPH       0u
+#define SHAPE_ROUNDED_BOX 1u
+#define SHAPE_ELLIPSE     2u
+#define SHAPE_PATH        3u
+#define SHAPE_WAVE        4u
+
+// edges of common::path::Outline, (from, to) relative to the center of their quad
+layout(std430, binding = 1) readonly buffer PathEdges {
+    vec4 edges[];
+} path;
+
+// common::shapes::encode_shape
+uint shape_kind(uvec4 shape) { return shape.w >> 13; }
+
+// iq's rounded box distance
+float sd_rounded_box(vec2 p, vec2 half_size, float radius) {
+    vec2 q = abs(p) - half_size + radius;
+    return length(max(q, 0.0)) + min(max(q.x, q.y), 0.0) - radius;
+}
+
+// first order approximation, exact on the axes
+float sd_ellipse(vec2 p, vec2 radii) {
+    float k1 = length(p/radii);
+    float k2 = length(p/(radii*radii));
+    return k1*(k1 - 1.0)/max(k2, 1e-6);
+}
+
+// a horizontal sine with butt ends, first order approximation like sd_ellipse
+float sd_wave(vec2 p, float half_length, float amplitude, float wavelength, float phase, float thickness) {
+    float k = 6.2831853/wavelength;
+    float x = k*(p.x + phase);
+    float slope = amplitude*k*cos(x);
+    float d = abs(p.y - amplitude*sin(x))/sqrt(1.0 + slope*slope) - 0.5*thickness;
+    return max(d, abs(p.x) - half_length);
+}
+
+// antiderivative of clamp(t, 0, 1)
+float clamped_integral(float t) {
+    return t <= 0.0 ? 0.0 : t >= 1.0 ? t - 0.5 : 0.5*t*t;
+}
+
+// the average winding number over the pixel with its top left at `corner`, for one edge,
+// summed over all edges it is the accumulation of common::path's rasterizer
+float edge_winding(vec4 edge, vec2 corner) {
+    bool  down      = edge.y < edge.w;
+    vec2  top       = down ? edge.xy : edge.zw;
+    vec2  bottom    = down ? edge.zw : edge.xy;
+    float y0 = max(top.y, corner.y);
+    float y1 = min(bottom.y, corner.y + 1.0);
+    if (y0 >= y1) return 0.0;
+    float dxdy = (bottom.x - top.x)/(bottom.y - top.y);
+    // how much of the pixel is right of the edge, averaged over the part of the row it crosses
+    float f0 = corner.x + 1.0 - (top.x + (y0 - top.y)*dxdy);
+    float f1 = corner.x + 1.0 - (top.x + (y1 - top.y)*dxdy);
+    float right = abs(f1 - f0) < 1e-4 ? clamp(0.5*(f0 + f1), 0.0, 1.0)
+                                      : (clamped_integral(f1) - clamped_integral(f0))/(f1 - f0);
+    return (down ? 1.0 : -1.0)*(y1 - y0)*right;
+}
+
+// shape = (first edge low and high bits, edge count, even-odd in the lowest bit)
+float path_coverage(vec2 p, uvec4 shape) {
+    uint first = shape.x | (shape.y << 16);
+    float winding = 0.0;
+    for (uint i = first; i < first + shape.z; i++) {
+        winding += edge_winding(path.edges[i], p - 0.5);
+    }
+    winding = abs(winding);
+    if ((shape.w & 1u) != 0u) {
+        // even-odd, a triangle wave
+        float folded = mod(winding, 2.0);
+        return folded > 1.0 ? 2.0 - folded : folded;
+    }
+    return min(winding, 1.0);
+}
+
+// sizes in 1/8 pixels, (half width, half height, corner radius, border width), (radii, unused, border width)
+// or (half length, amplitude, wavelength, thickness) with the phase of waves in uv.x
+float shape_coverage(vec2 p, uvec4 encoded, vec2 uv) {
+    uint kind = shape_kind(encoded);
+    if (kind == SHAPE_PATH) return path_coverage(p, encoded);
+    vec4 shape = vec4(vec3(encoded.xyz), float(encoded.w & 0x1FFFu))/8.0;
+    if (kind == SHAPE_WAVE) return clamp(0.5 - sd_wave(p, shape.x, shape.y, shape.z, uv.x/8.0, shape.w), 0.0, 1.0);
+    float d = kind == SHAPE_ELLIPSE ? sd_ellipse(p, shape.xy) : sd_rounded_box(p, shape.xy, shape.z);
+    if (shape.w > 0.0) {
+        // only the band just inside the edge
+        d = abs(d + 0.5*shape.w) - 0.5*shape.w;
+    }
+    return clamp(0.5 - d, 0.0, 1.0);
+}
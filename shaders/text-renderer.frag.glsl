@@ -1,12 +1,66 @@
 #version 450
-layout(location = 0) in  vec3 in_color;
+// shared by all text modes, select one with MODE (defaults to MODE_TEXT)
+#define MODE_TEXT        0
+#define MODE_SOLID_COLOR 1
+#define MODE_SUBPIXEL    2
+#define MODE_SDF         3
+#ifndef MODE
+#define MODE MODE_TEXT
+#endif
+// with PREMULTIPLIED defined the output is premultiplied RGBA with coverage in alpha,
+// for renderer::BlendMode::Premultiplied or ComponentAlphaPremultiplied on transparent targets
+#include "color.glsl"
+#include "shapes.glsl"
+
+layout(location = 0) in  vec4 in_color;
 layout(location = 1) in  vec2 in_uv;
+// shapes are drawn in the same batch as glyphs, with another kind than SHAPE_GLYPH
+layout(location = 2) in  vec2 in_local;
+layout(location = 3) flat in uvec4 in_shape;
 
 layout(location = 0) out vec4 out_color;
+#if MODE == MODE_SUBPIXEL
+layout(location = 1) out vec4 out_alpha;
+#endif
 
 layout(binding = 0) uniform sampler2D font_texture;
 
 void main(){
-    float alpha = texture(font_texture, in_uv).y;
-    out_color = vec4(in_color, alpha);
+    vec3 color = encode_output(in_color.rgb);
+    bool  is_shape = shape_kind(in_shape) != SHAPE_GLYPH;
+    float shape    = is_shape ? shape_coverage(in_local, in_shape, in_uv) : 0.0;
+    // glyphs are sampled outside of the branch, implicit derivatives need uniform control flow
+#if MODE == MODE_TEXT
+    float glyph = texture(font_texture, in_uv).y;
+    float alpha = in_color.a*(is_shape ? shape : glyph);
+#elif MODE == MODE_SOLID_COLOR
+    float glyph = texture(font_texture, in_uv).x;
+    float alpha = in_color.a*(is_shape ? shape : glyph);
+#elif MODE == MODE_SUBPIXEL
+    // unnormalized coordinates require explicit lod
+    vec3 glyph = textureLod(font_texture, in_uv, 0).xyz;
+    vec3 coverage = in_color.a*(is_shape ? vec3(shape) : glyph);
+#elif MODE == MODE_SDF
+    // signed distance in x, 0.5 on the edge
+    float dist  = texture(font_texture, in_uv).x;
+    float width = fwidth(dist);
+    float glyph = smoothstep(0.5-width, 0.5+width, dist);
+    float alpha = in_color.a*(is_shape ? shape : glyph);
+#else
+#error unknown MODE
+#endif
+
+#if MODE == MODE_SUBPIXEL && defined(PREMULTIPLIED)
+    // the layer's alpha has to cover the strongest channel, or composing it would drop coverage
+    float alpha = max(coverage.r, max(coverage.g, coverage.b));
+    out_color = vec4(color*coverage, alpha);
+    out_alpha = vec4(coverage, alpha);
+#elif MODE == MODE_SUBPIXEL
+    out_color = vec4(color, 1.0);
+    out_alpha = vec4(coverage, 1.0);
+#elif defined(PREMULTIPLIED)
+    out_color = vec4(color*alpha, alpha);
+#else
+    out_color = vec4(color, alpha);
+#endif
 }
@@ -2,10 +2,14 @@
 
 layout(location = 0) in  ivec2 in_pos;
 layout(location = 1) in  ivec2 in_uv;
-layout(location = 2) in   vec4 in_color;
+layout(location = 2) in   vec4 in_color; // linear sRGB, half floats
+layout(location = 3) in  ivec2 in_local; // 1/8 pixels
+layout(location = 4) in  uvec4 in_shape; // see common::shapes::encode_shape
 
-layout(location = 0) out  vec3 out_color;
+layout(location = 0) out  vec4 out_color;
 layout(location = 1) out  vec2 out_uv;
+layout(location = 2) out  vec2 out_local;
+layout(location = 3) flat out uvec4 out_shape;
 
 layout(push_constant) uniform _ {
     vec2 scale; vec2 offset;
@@ -13,6 +17,8 @@ layout(push_constant) uniform _ {
 
 void main() {
     gl_Position = vec4(push.scale.xy*(vec2(in_pos)-push.offset), 0.0, 1.0);
-    out_color   = in_color.xyz;
+    out_color   = in_color;
     out_uv      = in_uv;
+    out_local   = vec2(in_local)/8.0;
+    out_shape   = in_shape;
 }
@@ -11,6 +11,32 @@ use winit::{
     window::{Window,WindowId}
 };
 
+// SPIR-V compiled by build.rs
+mod shaders { include!(concat!(env!("OUT_DIR"), "/shaders.rs")); }
+
+/// Without hot reloading the shaders only change with the output space.
+#[cfg(not(feature="hot-reload"))]
+struct Shaders {
+    vs: vk::ShaderEXT,
+    fs: vk::ShaderEXT,
+}
+
+/// The text fragment shader encoding for `output`.
+#[cfg(feature="hot-reload")]
+fn text_fs_variant(output: renderer::OutputSpace) -> renderer::ShaderVariant {
+    // one fragment shader for all text modes, MODE_TEXT, MODE_SOLID_COLOR, MODE_SUBPIXEL or MODE_SDF
+    renderer::ShaderVariant::new("shaders/text-renderer.frag.glsl").define("MODE", Some("MODE_SUBPIXEL"))
+        .define("OUTPUT", Some(output.define()))
+}
+#[cfg(not(feature="hot-reload"))]
+fn text_fs_spirv(output: renderer::OutputSpace) -> &'static [u8] {
+    match output {
+        renderer::OutputSpace::Srgb               => shaders::SUBPIXEL_FRAG,
+        renderer::OutputSpace::ExtendedSrgbLinear => shaders::SUBPIXEL_SCRGB_FRAG,
+        renderer::OutputSpace::Hdr10              => shaders::SUBPIXEL_HDR10_FRAG,
+    }
+}
+
 fn gen_buffer_image_copy(ptr_offset:u64, buffer_image_copy: BufferImageCopy) -> vk::BufferImageCopy {
     let BufferImageCopy { buffer_offset, width, height, u, v } = buffer_image_copy;
     vk::BufferImageCopy{
@@ -40,20 +66,28 @@ fn push_quad_indices(ptr:*mut c_void, i:u16) -> *mut c_void {
     unsafe{push_type::<[u16;6]>(ptr, indices)}
 }
 
+// where the path edges go in the bar buffer, the descriptor set points at this range
+const PATH_EDGES_OFFSET : u64 = 48<<20;
+const PATH_EDGES_SIZE   : u64 = 16<<20;
+
 #[derive(Default)]
 enum App{
     #[default] Uninitialized,
     Resumed{
         window: Window,
         renderer: renderer::Renderer,
-        vs : vk::ShaderEXT,
-        fs : vk::ShaderEXT,
+        #[cfg(feature="hot-reload")]
+        shaders : renderer::ShaderWatcher,
+        #[cfg(not(feature="hot-reload"))]
+        shaders : Shaders,
         bar_buffer : vk::Buffer,
         bar_memory : *mut c_void,
-        pipeline_layout : vk::PipelineLayout,
+        layout : renderer::ShaderLayout,
+        output_space : renderer::OutputSpace, // the shaders encode for it
         descriptor_set : vk::DescriptorSet,
         image : vk::Image,
         text_engine : TextEngine,
+        text_scale : f32,
     },
 }
 impl ApplicationHandler for App {
@@ -67,87 +101,75 @@ impl ApplicationHandler for App {
                 let glyph_cache_size = 1<<10;
                 let glyph_cache_format = vk::Format::R8G8B8A8_UNORM;
 
-                let text_engine = TextEngine::new(glyph_cache_size, &[
+                let mut text_engine = TextEngine::new(glyph_cache_size, &[
                     "./fonts/source-sans/upright.ttf",
                     "./fonts/source-sans/italic.ttf",
                     "./fonts/crimson-pro/upright.ttf",
                     "./fonts/crimson-pro/italic.ttf",
                 ]);
+                // the light on dark text would look thin with linearly blended coverage
+                text_engine.set_gamma(TextGamma::DIRECTWRITE);
                 let init_text_engine = Instant::now();
 
                 let window = event_loop.create_window(Window::default_attributes()).expect("could not create window");
                 let raw_window  = window.window_handle().unwrap().as_raw();
                 let raw_display = window.display_handle().unwrap().as_raw();
-                let renderer = renderer::Renderer::new(raw_window, raw_display);
+                let size = window.inner_size();
+                // HDR=1 asks for an scRGB swapchain, falling back to sRGB
+                let mut swapchain_config = renderer::SwapchainConfig::default();
+                if std::env::var_os("HDR").is_some() {
+                    swapchain_config = swapchain_config.with_color_space(vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT);
+                }
+                let mut renderer = renderer::Renderer::new(raw_window, raw_display, vk::Extent2D{ width: size.width, height: size.height }, swapchain_config);
                 let init_render = Instant::now();
 
                 renderer.debug_print();
-                let push_constant_ranges = [
-                    vk::PushConstantRange::default()
-                        .stage_flags(vk::ShaderStageFlags::VERTEX)
-                        .size(size_of::<[f32;4]>() as u32) ];
-                //let binding_flag_bits = [vk::DescriptorBindingFlagsEXT::UPDATE_AFTER_BIND];
-                //let mut binding_flags = vk::DescriptorSetLayoutBindingFlagsCreateInfoEXT::default()
-                //    .binding_flags(&binding_flag_bits);
-
+                // the scale factor of the window's monitor, ScaleFactorChanged reports changes to it.
+                // winit reports 1.0 where the platform has none, e.g. X11 without Xft.dpi,
+                // then the physical size of the display is used until ScaleFactorChanged
+                let text_scale = match window.scale_factor() as f32 {
+                    1.0    => renderer.display_scale().unwrap_or(1.0),
+                    factor => factor,
+                };
+                println!("text scale: {text_scale}");
+
+                // push constants and the descriptor set layout come from the shaders
+                #[cfg(feature="hot-reload")]
+                let (shaders, layout) = {
+                    let vs_variant = renderer::ShaderVariant::new("shaders/text-renderer.vert.glsl");
+                    let fs_variant = text_fs_variant(renderer.output_space());
+                    let (vs,fs,layout) = renderer.load_glsl_vs_fs_reflected(vs_variant.clone(), fs_variant.clone());
+                    // edits to the shaders are picked up in about_to_wait
+                    (renderer::ShaderWatcher::new(&renderer, vs_variant, fs_variant, vs, fs, &layout.push_constant_ranges, &layout.set_layouts), layout)
+                };
+                #[cfg(not(feature="hot-reload"))]
+                let (shaders, layout) = {
+                    let (vs,fs,layout) = renderer.load_spirv_vs_fs_reflected(shaders::TEXT_RENDERER_VERT, text_fs_spirv(renderer.output_space()));
+                    (Shaders{vs, fs}, layout)
+                };
+                if let Err(err) = layout.validate_vertex_input::<Vertex>() {
+                    panic!("vertex layout does not match the vertex shader:\n{err}");
+                }
                 // create texture image
                 let (image,view) = renderer.alloc_image_and_view(glyph_cache_size as u32, glyph_cache_size as u32, glyph_cache_format);
-                {
-                    let cmd = renderer.begin_oneshot_cmd();
-                    renderer.transition_image(cmd, image, vk::ImageLayout::UNDEFINED, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
-                    renderer.end_oneshot_cmd(cmd);
-                }
                 let sampler = renderer.new_sampler_nearest();
 
-                // create descriptor set layout
-                let set_layout_bindings = [
-                    vk::DescriptorSetLayoutBinding::default()
-                        .binding(0)
-                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                        .descriptor_count(1)
-                        .stage_flags(vk::ShaderStageFlags::FRAGMENT) ];
-                let set_layout_info = vk::DescriptorSetLayoutCreateInfo::default()
-                    .bindings(&set_layout_bindings);
-                    //.flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL_EXT)
-                    //.push_next(&mut binding_flags);
-                let set_layouts = [unsafe{renderer.device.create_descriptor_set_layout(&set_layout_info, None)}.unwrap()];
-                let descriptor_alloc_info = vk::DescriptorSetAllocateInfo::default()
-                    .descriptor_pool(renderer.descriptor_pool)
-                    .set_layouts(&set_layouts);
-                let descriptor_set = unsafe{renderer.device.allocate_descriptor_sets(&descriptor_alloc_info)}.unwrap()[0];
-
-                let desc_img_info = [
-                    vk::DescriptorImageInfo::default()
-                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                    .image_view(view)
-                    .sampler(sampler)
-                ];
-                let descriptor_writes = [
-                    vk::WriteDescriptorSet::default()
-                        .dst_set(descriptor_set)
-                        .dst_binding(0)
-                        .dst_array_element(0)
-                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                        .descriptor_count(1)
-                        .image_info(&desc_img_info)
-                ];
-                unsafe{renderer.device.update_descriptor_sets(&descriptor_writes, &[])};
-
-                // create pipeline layout
-                let pipeline_layout_info = vk::PipelineLayoutCreateInfo::default()
-                    .set_layouts(&set_layouts)
-                    .push_constant_ranges(&push_constant_ranges);
-                let pipeline_layout = unsafe{ renderer.device.create_pipeline_layout(&pipeline_layout_info, None) }.unwrap();
-                println!("pipeline layout: {pipeline_layout:?}");
-
-                //let (vs,fs) = renderer.load_glsl_vs_fs("shaders/text-renderer.vert.glsl", "shaders/text-renderer.frag.glsl", &push_constant_ranges, &set_layouts);
-                let (vs,fs) = renderer.load_glsl_vs_fs("shaders/text-renderer.vert.glsl", "shaders/subpixel.frag.glsl", &push_constant_ranges, &set_layouts);
-                let Some((bar_buffer, bar_memory)) = renderer.map_bar_buffer(64<<20,
+                println!("pipeline layout: {:?}", layout.pipeline_layout);
+                let output_space = renderer.output_space();
+
+                let Some((bar_buffer, bar_memory)) = renderer.map_bar_buffer(PATH_EDGES_OFFSET+PATH_EDGES_SIZE,
                     vk::BufferUsageFlags::VERTEX_BUFFER
                   | vk::BufferUsageFlags::INDEX_BUFFER
+                  | vk::BufferUsageFlags::STORAGE_BUFFER
                   | vk::BufferUsageFlags::TRANSFER_SRC) else {panic!(":(")};
                 println!("mem ptr {bar_memory:?}");
 
+                let descriptor_set = renderer.allocate_descriptor_set(layout.set_layouts[0]);
+                renderer::DescriptorWriter::new(descriptor_set)
+                    .combined_image_sampler(0, view, sampler)
+                    .storage_buffer(1, bar_buffer, PATH_EDGES_OFFSET, PATH_EDGES_SIZE)
+                    .update(&renderer.device);
+
                 println!("initialized!!");
 
                 let init_end = Instant::now();
@@ -155,7 +177,7 @@ impl ApplicationHandler for App {
                 println!("{:>13?} renderer new",  init_render-init_text_engine);
                 println!("{:>13?} post renderer", init_end-init_render);
                 println!("{:>13?} total init",    init_end-init_start);
-                *self = App::Resumed{ window, renderer, vs, fs, bar_buffer, bar_memory, pipeline_layout, descriptor_set, image, text_engine};
+                *self = App::Resumed{ window, renderer, shaders, bar_buffer, bar_memory, layout, output_space, descriptor_set, image, text_engine, text_scale};
             },
         }
     }
@@ -166,8 +188,32 @@ impl ApplicationHandler for App {
                 println!("Window closed");
                 event_loop.exit()
             },
+            WindowEvent::Resized(size) => {
+                let App::Resumed{window, renderer, ..} = self else { return };
+                renderer.resize(vk::Extent2D{ width: size.width, height: size.height });
+                window.request_redraw();
+            },
+            WindowEvent::ScaleFactorChanged{scale_factor, ..} => {
+                let App::Resumed{window, text_scale, ..} = self else { return };
+                *text_scale = scale_factor as f32;
+                window.request_redraw();
+            },
             WindowEvent::RedrawRequested => {
-                let App::Resumed{window, renderer,vs,fs,bar_buffer,bar_memory, pipeline_layout, descriptor_set, image, text_engine} = self else { panic!("not active!") };
+                let App::Resumed{window, renderer,shaders,bar_buffer,bar_memory, layout, output_space, descriptor_set, image, text_engine, text_scale} = self else { panic!("not active!") };
+                // a new swapchain config can change the color space, the fragment shader encodes for the previous one
+                if renderer.output_space() != *output_space {
+                    *output_space = renderer.output_space();
+                    #[cfg(feature="hot-reload")]
+                    shaders.set_fs_variant(renderer, text_fs_variant(*output_space));
+                    #[cfg(not(feature="hot-reload"))]
+                    {
+                        let (vs,fs) = renderer.load_spirv_vs_fs(shaders::TEXT_RENDERER_VERT, text_fs_spirv(*output_space), &layout.push_constant_ranges, &layout.set_layouts);
+                        unsafe{renderer.device.device_wait_idle()}.unwrap();
+                        renderer.destroy_shader(shaders.vs);
+                        renderer.destroy_shader(shaders.fs);
+                        *shaders = Shaders{vs, fs};
+                    }
+                }
                 println!("================================================================================");
                 let winsize = window.inner_size();
                 let win_w = winsize.width as f32;
@@ -175,29 +221,34 @@ impl ApplicationHandler for App {
 
                 let english = Locale::new("en", Script::LATIN, Direction::LeftToRight);
 
-                let gb_light = Color::srgb8(0xF2, 0xe5, 0xbc, 0xFF);
-                let gb_aqua  = Color::srgb8(0x8e, 0xc0, 0x7c, 0xFF);
-                let gb_red   = Color::srgb8(0xfb, 0x49, 0x34, 0xFF);
-                let gb_yellow= Color::srgb8(0xfa, 0xbd, 0x2f, 0xFF);
+                let gb_light = Color::srgb8(0xF2, 0xe5, 0xbc, 0xFF).to_linear();
+                let gb_aqua  = Color::srgb8(0x8e, 0xc0, 0x7c, 0xFF).to_linear();
+                let gb_red   = Color::srgb8(0xfb, 0x49, 0x34, 0xFF).to_linear();
+                let gb_yellow= Color::srgb8(0xfa, 0xbd, 0x2f, 0xFF).to_linear();
                 let color = gb_aqua;
                 let features = &[];
                 let subpixel = 4;
 
-                let style_h1  = Style{ features, color:gb_light,  subpixel,   autohint: false, font_idx: 0, size: 32, weight: 600 };
-                //let style_h2  = Style{ features, color:gb_light,  subpixel,   autohint: false, font_idx: 0, size: 18, weight: 300 };
-
-                let style_s0  = Style{ features, color,           subpixel,   autohint: false, font_idx: 0, size: 21, weight: 400 };
-                let style_s1  = Style{ features, color,           subpixel,   autohint: false, font_idx: 1, size: 21, weight: 400 };
-                let style_s2  = Style{ features, color:gb_red,    subpixel,   autohint: false, font_idx: 0, size: 12, weight: 400 };
-                let style_s3  = Style{ features, color:gb_yellow, subpixel,   autohint: false, font_idx: 2, size: 21, weight: 300 };
-                let style_s3b = Style{ features, color:gb_yellow, subpixel,   autohint: false, font_idx: 2, size: 21, weight: 700 };
-                let style_s4  = Style{ features, color:gb_light,  subpixel,   autohint: false, font_idx: 3, size: 18, weight: 250 };
-                let style_s5  = Style{ features, color:gb_light,  subpixel,   autohint: false, font_idx: 0, size: 18, weight: 400 };
-
-                let line_width = 600.0;
+                let underline = [Decoration::new(DecorationLine::Underline)];
+                let double    = [Decoration::new(DecorationLine::Underline).with_style(DecorationStyle::Double)];
+                let wavy      = [Decoration::new(DecorationLine::Underline).with_style(DecorationStyle::Wavy).with_color(gb_red)];
+                let struck    = [Decoration::new(DecorationLine::Strikethrough), Decoration::new(DecorationLine::Overline).with_color(gb_red)];
+                let style_h1  = Style{ features, color:gb_light,  subpixel,   autohint: false, font_idx: 0, size: 32, weight: 600, gamma: None, decorations: &underline }.scaled(*text_scale);
+                //let style_h2  = Style{ features, color:gb_light,  subpixel,   autohint: false, font_idx: 0, size: 18, weight: 300, gamma: None, decorations: &[] };
+
+                let style_s0  = Style{ features, color,           subpixel,   autohint: false, font_idx: 0, size: 21, weight: 400, gamma: None, decorations: &struck }.scaled(*text_scale);
+                let style_s1  = Style{ features, color,           subpixel,   autohint: false, font_idx: 1, size: 21, weight: 400, gamma: None, decorations: &[] }.scaled(*text_scale);
+                let style_s2  = Style{ features, color:gb_red,    subpixel,   autohint: false, font_idx: 0, size: 12, weight: 400, gamma: None, decorations: &[] }.scaled(*text_scale);
+                let style_s3  = Style{ features, color:gb_yellow, subpixel,   autohint: false, font_idx: 2, size: 21, weight: 300, gamma: None, decorations: &[] }.scaled(*text_scale);
+                let style_s3b = Style{ features, color:gb_yellow, subpixel,   autohint: false, font_idx: 2, size: 21, weight: 700, gamma: None, decorations: &double }.scaled(*text_scale);
+                let style_s4  = Style{ features, color:gb_light,  subpixel,   autohint: false, font_idx: 3, size: 18, weight: 250, gamma: None, decorations: &wavy }.scaled(*text_scale);
+                let style_s5  = Style{ features, color:gb_light,  subpixel,   autohint: false, font_idx: 0, size: 18, weight: 400, gamma: None, decorations: &[] }.scaled(*text_scale);
+
+                let line_width = 600.0*(*text_scale);
                 let mut cursor = vec2(50,100);
                 let cursor_s = cursor;
 
+                text_engine.begin_frame();
                 let mut h1 = text_engine::StyledParagraph::default();
                 h1.add(&english, &style_h1, "How do you spell Пётр Кропоткин?");
                 let mut text  = text_engine.render_paragraph(&mut cursor, line_width, 0.5, &h1);
@@ -222,14 +273,32 @@ impl ApplicationHandler for App {
 
 
                 println!("{cursor_s} -> {cursor}");
-                //text.quads.insert(0, gen_rect(cursor_s, vec2(line_width, cursor.y-cursor_s.y), Color::srgb8(16, 16, 16, 0xFF)) );
+                //text.quads.insert(0, gen_rect(cursor_s, vec2(line_width, cursor.y-cursor_s.y), Color::srgb8(16, 16, 16, 0xFF).into()) );
+
+                // shapes go into the same batch, in front of the text unless inserted before it
+                let frame_fill = shapes::Fill::LinearGradient{ from: cursor_s, to: cursor, start: gb_yellow, end: gb_red };
+                // icons in the margin, covered in the fragment shader
+                let star = (0..10).fold(path::Path::new(), |star, i| {
+                    let radius = if i%2 == 0 { 12.0 } else { 5.0 }*(*text_scale);
+                    let corner = cursor_s + vec2(-28, 8) + Rotor2::from_angle(std::f32::consts::PI*(i as f32/5.0 - 0.5)).rotate(vec2(radius, 0));
+                    if i == 0 { star.move_to(corner) } else { star.line_to(corner) }
+                }).close();
+                text.draw_path(&star, &path::Paint::Fill(path::FillRule::NonZero), gb_yellow);
+                let check = path::Path::new()
+                    .move_to(cursor_s + vec2(-36, 40)).line_to(cursor_s + vec2(-30, 46)).line_to(cursor_s + vec2(-20, 32));
+                let stroke = path::Stroke::new(3.0*(*text_scale)).with_join(path::LineJoin::Round).with_cap(shapes::LineCap::Round);
+                text.draw_path(&check, &path::Paint::Stroke(stroke), gb_light);
+
+                text.quads.push(shapes::border(cursor_s-vec2(20, 20), vec2(line_width+40.0, cursor.y-cursor_s.y+40.0), 12.0, 2.0, frame_fill));
 
                 // top left
-                text.draw_hook_top_left    (cursor_s,                    Color::srgb8(0xFF, 0xFF, 0, 0xFF));
-                text.draw_hook_top_right   (cursor_s+vec2(line_width,0), Color::srgb8(0xFF, 0xFF, 0, 0xFF));
-                text.draw_hook_bottom_left (cursor,                      Color::srgb8(0xFF, 0xFF, 0, 0xFF));
-                text.draw_hook_bottom_right(cursor  +vec2(line_width,0), Color::srgb8(0xFF, 0xFF, 0, 0xFF));
+                text.draw_hook_top_left    (cursor_s,                    Color::srgb8(0xFF, 0xFF, 0, 0xFF).into());
+                text.draw_hook_top_right   (cursor_s+vec2(line_width,0), Color::srgb8(0xFF, 0xFF, 0, 0xFF).into());
+                text.draw_hook_bottom_left (cursor,                      Color::srgb8(0xFF, 0xFF, 0, 0xFF).into());
+                text.draw_hook_bottom_right(cursor  +vec2(line_width,0), Color::srgb8(0xFF, 0xFF, 0, 0xFF).into());
 
+                let clear = Color::srgb8(0x32, 0x30, 0x2f, 0xFF).to_linear();
+                let [clear_r, clear_g, clear_b] = renderer.output_space().encode([clear.r, clear.g, clear.b]);
                 let mut frame = renderer.wait_and_begin_frame();
 
                 // copy text into bar memory
@@ -255,40 +324,29 @@ impl ApplicationHandler for App {
                     unsafe{transmute::<*mut c_void, *mut u8>(bar_ptr).write_volatile(*b);}
                     bar_ptr = unsafe{bar_ptr.byte_add(1)};
                 }
-                let _buffer_end = bar_ptr;
+                let buffer_end = unsafe{bar_ptr.byte_offset_from(*bar_memory)} as u64;
+                assert!(buffer_end <= PATH_EDGES_OFFSET, "text does not fit into the bar buffer");
+                assert!((text.edges.len()*size_of::<[f32;4]>()) as u64 <= PATH_EDGES_SIZE, "path edges do not fit into the bar buffer");
+                unsafe{core::ptr::copy_nonoverlapping(text.edges.as_ptr(), (*bar_memory).byte_add(PATH_EDGES_OFFSET as usize).cast::<[f32;4]>(), text.edges.len())};
 
                 // add pixel offset to the buffers
                 let buffer_updates :Vec<vk::BufferImageCopy> = text.buffer_updates.into_iter().map(move|buffer_image_copy|gen_buffer_image_copy(pixel_buffer_offset,buffer_image_copy)).collect();
 
                 frame.buffer_to_image(*bar_buffer, *image, &buffer_updates);
+                frame.use_image(*image, renderer::Usage::SampledFragment);
+                frame.use_buffer(*bar_buffer, renderer::Usage::VertexBuffer);
+                frame.use_buffer(*bar_buffer, renderer::Usage::IndexBuffer);
 
-                frame.begin_rendering([(0x32 as f32/0xFF as f32).powf(2.2),
-                                       (0x30 as f32/0xFF as f32).powf(2.2),
-                                       (0x2f as f32/0xFF as f32).powf(2.2),
-                                       1.0]);
-                frame.bind_vs_fs(*vs, *fs);
+                frame.begin_rendering([clear_r, clear_g, clear_b, 1.0]);
+                frame.bind_vs_fs(shaders.vs, shaders.fs);
                 frame.bind_vertex_buffer(*bar_buffer);
                 frame.bind_index_buffer(*bar_buffer, index_buffer_offset);
-                frame.set_vertex_input(size_of::<Vertex>() as u32, &[
-                    (0, vk::Format::R16G16_SINT),
-                    (4, vk::Format::R16G16_UINT),
-                    (8, vk::Format::R8G8B8A8_UNORM),
-                ]);
+                frame.set_vertex_layout::<Vertex>();
 
-                frame.set_color_blend_enable(&[1]);
-                // component-alpha blending
-                frame.set_color_blend_equation(&[
-                    vk::ColorBlendEquationEXT::default()
-                        .src_color_blend_factor(vk::BlendFactor::SRC1_COLOR)
-                        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC1_COLOR)
-                        .color_blend_op(vk::BlendOp::ADD)
-                        // ignore alpha component
-                        .src_alpha_blend_factor(vk::BlendFactor::ZERO)
-                        .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
-                        .alpha_blend_op(vk::BlendOp::ADD)
-                ]);
-                frame.bind_descriptor_set(*descriptor_set, *pipeline_layout);
-                frame.push_constant(*pipeline_layout, &[2.0/win_w, 2.0/win_h, win_w/2.0, win_h/2.0]);
+                // the swapchain is opaque, transparent targets would need ComponentAlphaPremultiplied
+                frame.set_blend_mode(renderer::BlendMode::ComponentAlpha);
+                frame.bind_descriptor_set(*descriptor_set, layout.pipeline_layout);
+                frame.push_constant(layout.pipeline_layout, &[2.0/win_w, 2.0/win_h, win_w/2.0, win_h/2.0]);
                 frame.draw_indexed((quad_count*6) as u32, 0, 0);
 
                 frame.end_rendering();
@@ -299,8 +357,20 @@ impl ApplicationHandler for App {
             _ => (),
         }
     }
+
+    #[cfg(feature="hot-reload")]
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop){
+        let App::Resumed{window, renderer, shaders, ..} = self else { return };
+        if shaders.poll(renderer) {
+            window.request_redraw();
+        }
+        event_loop.set_control_flow(ControlFlow::WaitUntil(std::time::Instant::now() + SHADER_POLL_INTERVAL));
+    }
 }
 
+#[cfg(feature="hot-reload")]
+const SHADER_POLL_INTERVAL : std::time::Duration = std::time::Duration::from_millis(250);
+
 fn main() {
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Wait);
@@ -29,12 +29,19 @@ pub struct Style<'a>{
     pub font_idx: u32,
     pub size:     u32,
     pub weight:   u32,
-    pub color:    Color,
+    pub color:    LinearRgba,
     pub autohint: bool,
     pub subpixel: i32,
     pub features: &'a[&'a str],
+    /// overrides `TextEngine::set_gamma` for this style
+    pub gamma:    Option<TextGamma>,
+    pub decorations: &'a[Decoration],
 }
 impl Style<'_> {
+    /// The same style with its size scaled by a DPI factor, e.g. the window's scale factor.
+    pub fn scaled(&self, factor: f32) -> Self {
+        Self{ size: (self.size as f32*factor).round().max(1.0) as u32, ..self.clone() }
+    }
     fn load_flags(&self) -> ft::face::LoadFlag {
         if self.autohint {
             ft::face::LoadFlag::FORCE_AUTOHINT
@@ -53,6 +60,83 @@ impl Style<'_> {
     }
 }
 
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum DecorationLine {
+    Underline,
+    Strikethrough,
+    Overline,
+}
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum DecorationStyle {
+    Single,
+    Double,
+    Wavy,
+}
+
+/// A line along a run of text, placed by the font's underline and strikeout metrics.
+/// Underlines and overlines are drawn behind the glyphs and skip their ink, like CSS's
+/// `text-decoration-skip-ink: auto`, strikethroughs are drawn in front.
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub struct Decoration {
+    pub line:  DecorationLine,
+    pub style: DecorationStyle,
+    /// the style's color if `None`
+    pub color: Option<LinearRgba>,
+}
+impl Decoration {
+    pub const fn new(line: DecorationLine) -> Self { Self{ line, style: DecorationStyle::Single, color: None } }
+    pub const fn with_style(mut self, style: DecorationStyle) -> Self { self.style = style; self }
+    pub const fn with_color(mut self, color: LinearRgba) -> Self { self.color = Some(color); self }
+}
+
+/// Adjusts glyph coverage before it is blended, like DirectWrite and Skia do.
+/// Blending coverage in linear light makes light text on dark backgrounds look thin
+/// and dark text on light backgrounds heavy, these curves counter that.
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub struct TextGamma{
+    /// > 1 thickens light text and thins dark text, 1 leaves coverage alone.
+    /// Coverage is blended as if light was encoded with this exponent,
+    /// assuming the background is the opposite of the text's luminance.
+    pub gamma:    f32,
+    /// DirectWrite's enhanced contrast, 0 leaves coverage alone, larger values thicken all text
+    pub contrast: f32,
+}
+impl Default for TextGamma {
+    fn default() -> Self { Self::LINEAR }
+}
+impl TextGamma {
+    pub const LINEAR : Self = Self{ gamma: 1.0, contrast: 0.0 };
+    /// similar to the DirectWrite defaults
+    pub const DIRECTWRITE : Self = Self{ gamma: 1.8, contrast: 0.5 };
+
+    /// text luminance is quantized, so every color does not need its own glyphs
+    const LUMINANCE_STEPS : u8 = 7;
+
+    fn luminance_step(color: LinearRgba) -> u8 {
+        let luminance = 0.2126*color.r + 0.7152*color.g + 0.0722*color.b;
+        (luminance.clamp(0.0, 1.0)*Self::LUMINANCE_STEPS as f32).round() as u8
+    }
+
+    /// `luminance` of the text, in linear light
+    pub fn adjust(self, coverage: f32, luminance: f32) -> f32 {
+        let k = self.contrast;
+        let coverage = coverage*(k+1.0)/(coverage*k+1.0);
+        let fg = luminance;
+        let bg = if fg < 0.5 { 1.0 } else { 0.0 };
+        let encode = |x:f32| x.powf(self.gamma);
+        let decode = |x:f32| x.powf(1.0/self.gamma);
+        let blended = decode(encode(bg) + coverage*(encode(fg)-encode(bg)));
+        ((blended-bg)/(fg-bg)).clamp(0.0, 1.0)
+    }
+
+    fn lut(self, luminance_step: u8) -> [u8;256] {
+        let luminance = luminance_step as f32/Self::LUMINANCE_STEPS as f32;
+        core::array::from_fn(|c| (self.adjust(c as f32/255.0, luminance)*255.0).round() as u8)
+    }
+
+    fn key(self) -> (u32,u32) { (self.gamma.to_bits(), self.contrast.to_bits()) }
+}
 
 #[derive(Copy,Clone)]
 struct GlyphCacheEntry{
@@ -72,34 +156,63 @@ struct GlyphCacheKey{
     font_weight: u32,
     subpixel  : u32,
     autohint  : bool,
+    gamma     : (u32,u32),
+    luminance : u8,
 }
 
+// raw coverage above which a glyph's ink interrupts decorations
+const INK_THRESHOLD : u8 = 0x40;
+
 // TODO: make multi-thread friendly
 struct GlyphCache{
     map: HashMap<GlyphCacheKey,GlyphCacheEntry>,
+    // horizontal extent of the ink in each row of a glyph's bitmap, for skipping decorations
+    ink: HashMap<GlyphCacheKey,Vec<Option<(u16,u16)>>>,
     tex_size:  u16,
     current_x: u16,
     current_y: u16,
     max_y:     u16,
+    // a glyph did not fit, the texture is cleared by begin_frame
+    full:      bool,
 }
 impl GlyphCache {
-    fn new(tex_size:u16) -> Self { Self { map: HashMap::new(), current_x:0, current_y:0, tex_size, max_y:0 } }
+    fn new(tex_size:u16) -> Self { Self { map: HashMap::new(), ink: HashMap::new(), current_x:0, current_y:0, tex_size, max_y:0, full: false } }
     fn get(&self, key: &GlyphCacheKey) -> Option<GlyphCacheEntry> {
         self.map.get(key).copied()
     }
-    fn insert(&mut self, key:GlyphCacheKey, width: u16, height:u16, left: i16, top: i16) -> (u16,u16) {
-        if self.current_x+width >= self.tex_size {
-            self.current_x = 0;
-            self.current_y = self.max_y;
-        }
-        let ret = (self.current_x, self.current_y);
+    fn insert(&mut self, key:GlyphCacheKey, width: u16, height:u16, left: i16, top: i16) -> Option<(u16,u16)> {
+        let ret = self.allocate(width, height)?;
         self.map.insert(key, GlyphCacheEntry{
             u: ret.0, v: ret.1,
             width, height, left, top
         });
+        Some(ret)
+    }
+    // shelf packing, None once the texture is full
+    fn allocate(&mut self, width: u16, height:u16) -> Option<(u16,u16)> {
+        let size = self.tex_size as u32;
+        if self.current_x as u32 + width as u32 > size {
+            self.current_x = 0;
+            self.current_y = self.max_y;
+        }
+        if self.current_x as u32 + width as u32 > size || self.current_y as u32 + height as u32 > size {
+            self.full = true;
+            return None;
+        }
+        let ret = (self.current_x, self.current_y);
         self.current_x += width;
         self.max_y = self.max_y.max(self.current_y+height);
-        ret
+        Some(ret)
+    }
+    // evicts every glyph if one did not fit, the texture fills up from the top left again.
+    // Not within a frame, its earlier quads still sample the evicted glyphs
+    fn begin_frame(&mut self) -> bool {
+        if !self.full { return false }
+        self.map.clear();
+        self.ink.clear();
+        (self.current_x, self.current_y, self.max_y) = (0, 0, 0);
+        self.full = false;
+        true
     }
 }
 
@@ -108,10 +221,19 @@ pub struct Text{
     pub quads          : Vec<[Vertex;4]>,
     pub buffer_updates : Vec<BufferImageCopy>,
     pub pixels         : Vec<u8>,
+    /// edges of the paths among the quads, for the fragment shader's storage buffer, see `path::Outline::quad`
+    pub edges          : Vec<[f32;4]>,
 }
 impl Text{
     pub fn append(&mut self, rhs:Text){
-        self.quads.extend(&rhs.quads);
+        self.insert(self.quads.len(), rhs);
+    }
+    // quads of `rhs` are drawn before the quads from `quad_index` on
+    fn insert(&mut self, quad_index: usize, rhs:Text){
+        let edge_offset = self.edges.len() as u32;
+        let quads = rhs.quads.into_iter().map(|mut quad| { shapes::offset_path_edges(&mut quad, edge_offset); quad });
+        self.quads.splice(quad_index..quad_index, quads);
+        self.edges.extend(rhs.edges);
         let px_offset = self.pixels.len();
         self.pixels.extend(&rhs.pixels);
         for mut bu in rhs.buffer_updates {
@@ -119,22 +241,29 @@ impl Text{
             self.buffer_updates.push(bu);
         }
     }
-    pub fn draw_hook_top_left(&mut self, origin:Vec2<f32>, color: Color){
+    /// Filled or stroked, with coverage computed in the fragment shader.
+    pub fn draw_path(&mut self, path: &path::Path, paint: &path::Paint, fill: impl Into<shapes::Fill>){
+        if let Some(quad) = path.outline(paint).quad(fill, &mut self.edges) {
+            self.quads.push(quad);
+        }
+    }
+
+    pub fn draw_hook_top_left(&mut self, origin:Vec2<f32>, color: LinearRgba){
         self.quads.push(gen_rect(origin-vec2(10, 5), vec2(10, 5), color) );
         self.quads.push(gen_rect(origin-vec2( 5,10), vec2( 5,10), color) );
     }
 
-    pub fn draw_hook_top_right(&mut self, origin:Vec2<f32>, color: Color){
+    pub fn draw_hook_top_right(&mut self, origin:Vec2<f32>, color: LinearRgba){
         self.quads.push(gen_rect(origin-vec2( 0, 5), vec2(10, 5), color));
         self.quads.push(gen_rect(origin-vec2( 0,10), vec2( 5,10), color));
     }
 
-    pub fn draw_hook_bottom_left(&mut self, origin:Vec2<f32>, color: Color){
+    pub fn draw_hook_bottom_left(&mut self, origin:Vec2<f32>, color: LinearRgba){
         self.quads.push(gen_rect(origin-vec2(10, 0), vec2(10, 5), color) );
         self.quads.push(gen_rect(origin-vec2( 5, 0), vec2( 5,10), color) );
     }
 
-    pub fn draw_hook_bottom_right(&mut self, origin:Vec2<f32>, color: Color){
+    pub fn draw_hook_bottom_right(&mut self, origin:Vec2<f32>, color: LinearRgba){
         self.quads.push(gen_rect(origin, vec2(10, 5), color) );
         self.quads.push(gen_rect(origin, vec2( 5,10), color) );
     }
@@ -192,15 +321,104 @@ fn is_mandatory_linebreak(c: char) -> bool {
 }
 
 
+/// in pixels, up from the baseline
+#[derive(Copy,Clone)]
+struct LineMetrics{
+    center:    f32,
+    thickness: f32,
+}
+
+#[derive(Copy,Clone)]
+struct DecorationMetrics{
+    underline:     LineMetrics,
+    strikethrough: LineMetrics,
+    overline:      LineMetrics,
+}
+impl DecorationMetrics{
+    fn line(&self, line: DecorationLine) -> LineMetrics {
+        match line {
+            DecorationLine::Underline     => self.underline,
+            DecorationLine::Strikethrough => self.strikethrough,
+            DecorationLine::Overline      => self.overline,
+        }
+    }
+}
+
+// the decorated part of a style run on one line, in 26.6 like the cursor
+struct DecorationSpan<'a>{
+    decorations: &'a[Decoration],
+    color:       LinearRgba,
+    metrics:     DecorationMetrics,
+    baseline:    i32,
+    start:       i32,
+    end:         i32,
+    first_quad:  usize,
+    glyphs:      Vec<(Vec2<i32>,GlyphCacheKey)>,
+}
+impl<'a> DecorationSpan<'a>{
+    fn new(style: &Style<'a>, metrics: DecorationMetrics, cursor: Vec2<i32>, first_quad: usize) -> Self {
+        Self{ decorations: style.decorations, color: style.color, metrics, baseline: cursor.y, start: cursor.x, end: cursor.x, first_quad, glyphs: Vec::new() }
+    }
+}
+
+// amplitude and wavelength of wavy decorations, in multiples of their thickness
+const WAVE_AMPLITUDE : f32 = 1.0;
+const WAVE_LENGTH    : f32 = 6.0;
+
+/// `start`..`end` without the `gaps`, dropping pieces shorter than `min_length`.
+fn skip_ink(start: f32, end: f32, mut gaps: Vec<(f32,f32)>, min_length: f32) -> Vec<(f32,f32)> {
+    gaps.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut pieces = Vec::new();
+    let mut from = start;
+    for (gap_start, gap_end) in gaps {
+        if gap_start > from {
+            pieces.push((from, gap_start.min(end)));
+        }
+        from = from.max(gap_end);
+        if from >= end { break }
+    }
+    if from < end {
+        pieces.push((from, end));
+    }
+    pieces.retain(|&(from, to)| to - from >= min_length);
+    pieces
+}
+
 struct Font{
     ft_face: ft::Face,
     hb_font: *mut hb::hb_font_t, // hb_font_t is an opaque type
+    // yStrikeoutPosition (top of the stroke) and yStrikeoutSize from the OS/2 table, in font units
+    strikeout: Option<(i16,i16)>,
 }
 impl Font{
     fn from_path(lib: &ft::Library, path: &str) -> Self {
         let mut ft_face = lib.new_face(path, 0).expect("could not find font");
         let hb_font = unsafe{hb::freetype::hb_ft_font_create_referenced(ft_face.raw_mut())};
-        Self{ ft_face, hb_font }
+        let strikeout = ft::tt_os2::TrueTypeOS2Table::from_face(&mut ft_face)
+            .map(|os2| (os2.y_strikeout_position(), os2.y_strikeout_size()))
+            .filter(|&(_, size)| size > 0);
+        Self{ ft_face, hb_font, strikeout }
+    }
+    /// For the size set by `apply_style`.
+    /// FreeType reads the underline from the `post` table, already moved to the center of the stroke.
+    fn decoration_metrics(&self) -> DecorationMetrics {
+        let face = &self.ft_face;
+        let em = face.em_size() as f32;
+        // font units to pixels
+        let scale = face.size_metrics().map_or(0.0, |metrics| metrics.y_scale as f32/65536.0/64.0);
+        let thickness = if face.underline_thickness() > 0 { face.underline_thickness() as f32 } else { em/14.0 };
+        let underline = if face.underline_position() != 0 { face.underline_position() as f32 } else { -em/10.0 };
+        let (strikeout, strikeout_thickness) = match self.strikeout {
+            Some((position, size)) => (position as f32 - size as f32/2.0, size as f32),
+            None => (em/4.0, thickness),
+        };
+        let overline = face.ascender() as f32 - thickness/2.0;
+        let line = |center: f32, thickness: f32| LineMetrics{ center: center*scale, thickness: (thickness*scale).max(1.0) };
+        DecorationMetrics{
+            underline:     line(underline, thickness),
+            strikethrough: line(strikeout, strikeout_thickness),
+            overline:      line(overline,  thickness),
+        }
     }
     fn apply_style(&mut self, style: &Style){
         use hb::*;
@@ -350,6 +568,8 @@ pub struct TextEngine{
     glyph_cache: GlyphCache, 
     buffer:      *mut hb::hb_buffer_t,
     fonts:       Vec<Font>,
+    gamma:       TextGamma,
+    gamma_luts:  HashMap<((u32,u32),u8),[u8;256]>,
 }
 impl TextEngine {
     pub fn new(glyph_texture_size:u16, font_file_paths: &[&str]) -> Self {
@@ -363,9 +583,24 @@ impl TextEngine {
             buffer: unsafe{hb::hb_buffer_create()},
             fonts,
             glyph_cache: GlyphCache::new(glyph_texture_size),
+            gamma: TextGamma::default(),
+            gamma_luts: HashMap::new(),
         }
     }
 
+    /// Call before rendering a frame's text. Glyphs that did not fit into the texture
+    /// during the last frame were left out, this evicts all glyphs so they fit again.
+    pub fn begin_frame(&mut self){
+        if self.glyph_cache.begin_frame() {
+            println!("glyph texture full, evicting all glyphs");
+        }
+    }
+
+    /// Applies to glyphs rasterized from now on, for styles without their own `gamma`.
+    pub fn set_gamma(&mut self, gamma: TextGamma){
+        self.gamma = gamma;
+    }
+
     pub fn render_paragraph( &mut self,
             cursor_f:        &mut Vec2<f32>,
             max_line_width:  f32,
@@ -411,21 +646,30 @@ impl TextEngine {
 
             let font = &mut self.fonts[style.font_idx as usize];
             font.apply_style(style);
+            let metrics = font.decoration_metrics();
+            let mut span = DecorationSpan::new(style, metrics, cursor, ret.quads.len());
 
             for ((l,r),&(info,pos)) in &mut shaped_glyph_iter {
-                self.rasterize_glyph(&mut ret, style, cursor, info, pos);
+                if let Some(glyph) = self.rasterize_glyph(&mut ret, style, cursor, info, pos) {
+                    span.glyphs.push(glyph);
+                }
 
                 if r==next_break_point {
                     println!("newline: {l}~{r}");
+                    // the glyph at the break, usually a space, stays undecorated
+                    self.decorate(&mut ret, span);
                     cursor.x = left_margin;
                     cursor.y += max_lineskip;
                     next_break_point = *break_points_iter.next().unwrap_or(&0);
+                    span = DecorationSpan::new(style, metrics, cursor, ret.quads.len());
                 } else {
                     cursor.x += pos.x_advance;
+                    span.end = cursor.x;
                 }
 
                 if style_r==r { break }
             }
+            self.decorate(&mut ret, span);
         }
         println!("{cursor} -> {}", cursor.map(|o|o as f32/64.0));
 
@@ -510,7 +754,64 @@ impl TextEngine {
         std::iter::zip(glyph_infos, glyph_positons).map(|(i,p)|(*i,*p)).collect()
     }
 
-    fn rasterize_glyph(&mut self, ret: &mut Text, style: &Style, cursor: Vec2<i32>, info: hb::hb_glyph_info_t, pos: hb::hb_glyph_position_t){
+    fn decorate(&mut self, ret: &mut Text, span: DecorationSpan){
+        if span.decorations.is_empty() || span.end <= span.start {
+            return;
+        }
+        let (start, end) = (span.start as f32/64.0, span.end as f32/64.0);
+        let baseline = span.baseline as f32/64.0;
+        let mut behind = Text::default();
+        let mut front  = Text::default();
+        for decoration in span.decorations {
+            let color = decoration.color.unwrap_or(span.color);
+            let LineMetrics{ center, thickness } = span.metrics.line(decoration.line);
+            let center = baseline - center;
+            // centers of the strokes in thicknesses from `center`, double lines grow away from the text
+            let offsets : &[f32] = match (decoration.style, decoration.line) {
+                (DecorationStyle::Double, DecorationLine::Underline)     => &[0.0,  2.0],
+                (DecorationStyle::Double, DecorationLine::Overline)      => &[0.0, -2.0],
+                (DecorationStyle::Double, DecorationLine::Strikethrough) => &[-1.0, 1.0],
+                (DecorationStyle::Wavy, _) => &[-WAVE_AMPLITUDE, WAVE_AMPLITUDE],
+                (DecorationStyle::Single, _) => &[0.0],
+            };
+            let top    = center + offsets.iter().copied().fold(0.0, f32::min)*thickness - thickness/2.0;
+            let bottom = center + offsets.iter().copied().fold(0.0, f32::max)*thickness + thickness/2.0;
+            let (pieces, target) = if decoration.line == DecorationLine::Strikethrough {
+                (vec![(start, end)], &mut front)
+            } else {
+                let gaps = self.ink_extents(&span.glyphs, top, bottom, thickness);
+                (skip_ink(start, end, gaps, thickness), &mut behind)
+            };
+            for (from, to) in pieces {
+                if decoration.style == DecorationStyle::Wavy {
+                    // in phase with x = 0, so the pieces between skipped ink line up
+                    target.quads.push(shapes::wave(from, to, center, WAVE_AMPLITUDE*thickness, WAVE_LENGTH*thickness, thickness, color));
+                    continue;
+                }
+                for offset in offsets {
+                    let position = vec2(from, center + offset*thickness - thickness/2.0);
+                    target.quads.push(shapes::rounded_rect(position, vec2(to-from, thickness), 0.0, color));
+                }
+            }
+        }
+        ret.insert(span.first_quad, behind);
+        ret.append(front);
+    }
+
+    // horizontal extents of the glyphs' ink between `top` and `bottom`, widened by `gap`
+    fn ink_extents(&self, glyphs: &[(Vec2<i32>,GlyphCacheKey)], top: f32, bottom: f32, gap: f32) -> Vec<(f32,f32)> {
+        glyphs.iter().filter_map(|(position, key)| {
+            let rows  = self.glyph_cache.ink.get(key)?;
+            let first = (top - position.y as f32).floor().max(0.0) as usize;
+            let last  = ((bottom - position.y as f32).ceil().max(0.0) as usize).min(rows.len());
+            let (left, right) = rows.get(first..last)?.iter().flatten()
+                .fold(None, |extent: Option<(u16,u16)>, &(l, r)| Some(extent.map_or((l, r), |(el, er)| (el.min(l), er.max(r)))))?;
+            Some((position.x as f32 + left as f32 - gap, position.x as f32 + right as f32 + gap))
+        }).collect()
+    }
+
+    // returns the top left pixel of visible glyphs, with the key of their ink
+    fn rasterize_glyph(&mut self, ret: &mut Text, style: &Style, cursor: Vec2<i32>, info: hb::hb_glyph_info_t, pos: hb::hb_glyph_position_t) -> Option<(Vec2<i32>,GlyphCacheKey)> {
         let font = &self.fonts[style.font_idx as usize];
 
         let id = info.codepoint; // actually glyph index, not codepoint
@@ -522,7 +823,10 @@ impl TextEngine {
         let frac64 = (x_frac*64/style.subpixel) as u32;
         //println!("{x:4}+{x_frac:2}/{:2} = {frac64:2}/64", style.subpixel);
 
-        if let Some(entry) = self.glyph_cache.get(&GlyphCacheKey{font_idx:style.font_idx, glyph_idx:id, font_size:style.size, font_weight:style.weight, autohint:style.autohint, subpixel:frac64}) {
+        let gamma = style.gamma.unwrap_or(self.gamma);
+        let luminance = if gamma == TextGamma::LINEAR { 0 } else { TextGamma::luminance_step(style.color) };
+        let key = GlyphCacheKey{font_idx:style.font_idx, glyph_idx:id, font_size:style.size, font_weight:style.weight, autohint:style.autohint, subpixel:frac64, gamma:gamma.key(), luminance};
+        if let Some(entry) = self.glyph_cache.get(&key) {
             if !(entry.width<=0 || entry.height<=0) { // invisible character, ignore for rendering
                 ret.quads.push(
                     gen_quad(x as i16 + entry.left,
@@ -531,6 +835,7 @@ impl TextEngine {
                              entry.height as i16,
                              entry.u, entry.v,
                              style.color));
+                return Some((Vec2{ x: x + entry.left as i32, y: y - entry.top as i32 }, key));
             }
         }else{
             font.ft_face.load_glyph(id, style.load_flags()).unwrap();
@@ -546,21 +851,29 @@ impl TextEngine {
             if !(width<=0 || height<=0) { 
                 assert_eq!(ret.pixels.len()%4, 0);
                 let buffer_offset = ret.pixels.len() as u64;
-                let uv = self.glyph_cache.insert( GlyphCacheKey{ font_idx:style.font_idx, glyph_idx:id, font_size:style.size, font_weight:style.weight, autohint:style.autohint, subpixel:frac64}, 
-                                                      width as u16, height as u16, left as i16, top as i16);
-                // convert to tightly-packed rgba
+                // not drawn if the texture is full, until begin_frame made room
+                let uv = self.glyph_cache.insert(key, width as u16, height as u16, left as i16, top as i16)?;
+                let lut = self.gamma_luts.entry((key.gamma, luminance)).or_insert_with(|| gamma.lut(luminance));
+                // convert to tightly-packed rgba, adjusting coverage
                 let bitmap_buffer = bitmap.buffer();
                 let mut pixel_counter = 0;
                 for h in 0..height {
                     for w in 0..width_sub {
-                        ret.pixels.push(bitmap_buffer[(h*pitch + w) as usize]);
+                        ret.pixels.push(lut[bitmap_buffer[(h*pitch + w) as usize] as usize]);
                         if pixel_counter%3==2 { 
                             ret.pixels.push(0xFF);
                         }
                         pixel_counter += 1;
                     }
                 }
-                
+                let ink = (0..height).map(|h| {
+                    let row = &bitmap_buffer[(h*pitch) as usize..(h*pitch + width_sub) as usize];
+                    let first = row.iter().position(|&c| c >= INK_THRESHOLD)?;
+                    let last  = row.iter().rposition(|&c| c >= INK_THRESHOLD)?;
+                    Some(((first/3) as u16, (last/3 + 1) as u16))
+                }).collect();
+                self.glyph_cache.ink.insert(key, ink);
+
                 ret.quads.push(
                     gen_quad((x+left) as i16,
                              (y-top)  as i16,
@@ -574,8 +887,10 @@ impl TextEngine {
                         height: height as u32,
                         u: uv.0 as i32,
                         v: uv.1 as i32 });
+                return Some((Vec2{ x: x + left, y: y - top }, key));
             }
         }
+        None
     }
 }
 
@@ -761,3 +1076,107 @@ impl Script{
     pub const KAWI                   :Self = Self::new(b"Kawi");
     pub const NAG_MUNDARI            :Self = Self::new(b"Nagm");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LUMINANCES : [f32;5] = [0.0, 0.2, 0.5, 0.8, 1.0];
+    fn coverages() -> impl Iterator<Item=f32> { (0..=255).map(|c| c as f32/255.0) }
+
+    #[test]
+    fn linear_gamma_is_the_identity() {
+        for luminance in LUMINANCES {
+            for coverage in coverages() {
+                assert!((TextGamma::LINEAR.adjust(coverage, luminance) - coverage).abs() < 1e-5, "{coverage} at luminance {luminance}");
+            }
+        }
+    }
+
+    #[test]
+    fn gamma_keeps_the_ends_and_order() {
+        for gamma in [TextGamma::DIRECTWRITE, TextGamma{ gamma: 2.2, contrast: 0.0 }, TextGamma{ gamma: 1.0, contrast: 1.0 }] {
+            for luminance in LUMINANCES {
+                assert!(gamma.adjust(0.0, luminance).abs() < 1e-5);
+                assert!((gamma.adjust(1.0, luminance) - 1.0).abs() < 1e-5);
+                let adjusted : Vec<f32> = coverages().map(|coverage| gamma.adjust(coverage, luminance)).collect();
+                assert!(adjusted.windows(2).all(|pair| pair[0] <= pair[1]), "{gamma:?} is not monotonic at luminance {luminance}");
+            }
+        }
+    }
+
+    #[test]
+    fn glyph_cache_stays_in_the_texture() {
+        let mut cache = GlyphCache::new(16);
+        assert_eq!(cache.allocate(10, 4), Some((0, 0)));
+        // fills the row exactly
+        assert_eq!(cache.allocate(6, 6), Some((10, 0)));
+        assert_eq!(cache.allocate(8, 8), Some((0, 6)));
+        assert_eq!(cache.allocate(8, 2), Some((8, 6)));
+        // the next row would end below the texture
+        assert_eq!(cache.allocate(4, 4), None);
+        let mut cache = GlyphCache::new(16);
+        assert_eq!(cache.allocate(17, 1), None);
+        assert_eq!(cache.allocate(16, 16), Some((0, 0)));
+    }
+
+    #[test]
+    fn full_glyph_texture_recovers_on_the_next_frame() {
+        let key = |glyph_idx| GlyphCacheKey{ font_idx: 0, glyph_idx, font_size: 16, font_weight: 400, subpixel: 0, autohint: false, gamma: (0, 0), luminance: 0 };
+        let mut cache = GlyphCache::new(16);
+        assert_eq!(cache.insert(key(0), 16, 8, 0, 0), Some((0, 0)));
+        // glyphs stay while they fit
+        assert!(!cache.begin_frame());
+        assert!(cache.get(&key(0)).is_some());
+        assert_eq!(cache.insert(key(1), 16, 8, 0, 0), Some((0, 8)));
+        // left out for the rest of the frame
+        assert_eq!(cache.insert(key(2), 16, 8, 0, 0), None);
+        assert_eq!(cache.insert(key(3), 1, 1, 0, 0), None);
+        assert!(cache.begin_frame());
+        assert!(cache.get(&key(0)).is_none());
+        assert_eq!(cache.insert(key(2), 16, 8, 0, 0), Some((0, 0)));
+    }
+
+    #[test]
+    fn appended_paths_keep_their_edges() {
+        let square = |x: f32| path::Path::new().move_to(vec2(x, 0)).line_to(vec2(x+4.0, 0)).line_to(vec2(x+4.0, 4)).line_to(vec2(x, 4)).close();
+        let fill = path::Paint::Fill(path::FillRule::NonZero);
+        let (mut text, mut appended) = (Text::default(), Text::default());
+        text.draw_path(&square(0.0), &fill, LinearRgba::default());
+        appended.draw_path(&square(10.0), &fill, LinearRgba::default());
+        let edges = appended.edges.clone();
+        text.append(appended);
+        // horizontal edges are left out
+        assert_eq!(text.edges.len(), 4);
+        let [first, _, count, _] = text.quads[1][0].shape;
+        assert_eq!(text.edges[first as usize..(first+count) as usize], edges);
+    }
+
+    #[test]
+    fn skip_ink_merges_unsorted_and_overlapping_gaps() {
+        assert_eq!(skip_ink(0.0, 20.0, vec![(12.0, 14.0), (3.0, 6.0), (5.0, 8.0)], 1.0), [(0.0, 3.0), (8.0, 12.0), (14.0, 20.0)]);
+        // a gap inside another
+        assert_eq!(skip_ink(0.0, 20.0, vec![(2.0, 10.0), (4.0, 6.0)], 1.0), [(0.0, 2.0), (10.0, 20.0)]);
+    }
+
+    #[test]
+    fn skip_ink_clips_gaps_at_the_ends() {
+        assert_eq!(skip_ink(0.0, 20.0, vec![(-3.0, 2.0), (18.0, 25.0)], 1.0), [(2.0, 18.0)]);
+        assert_eq!(skip_ink(5.0, 10.0, vec![(0.0, 2.0), (12.0, 14.0)], 1.0), [(5.0, 10.0)]);
+        assert!(skip_ink(0.0, 20.0, vec![(-1.0, 21.0)], 1.0).is_empty());
+        // every piece is shorter than the minimum
+        assert!(skip_ink(0.0, 20.0, vec![(0.5, 10.0), (10.5, 19.5)], 1.0).is_empty());
+    }
+
+    #[test]
+    fn gamma_thickens_light_text_and_thins_dark_text() {
+        let gamma = TextGamma{ gamma: 1.8, contrast: 0.0 };
+        for coverage in coverages().filter(|&c| c > 0.0 && c < 1.0) {
+            assert!(gamma.adjust(coverage, 1.0) > coverage);
+            assert!(gamma.adjust(coverage, 0.0) < coverage);
+        }
+        // contrast thickens both
+        let contrast = TextGamma{ gamma: 1.0, contrast: 0.5 };
+        assert!(contrast.adjust(0.5, 1.0) > 0.5 && contrast.adjust(0.5, 0.0) > 0.5);
+    }
+}